@@ -14,7 +14,10 @@ void* InitializeEventTap(EventTapCallback callback, void* userData) {
     CGEventMask eventMask = (1 << kCGEventKeyDown) | (1 << kCGEventKeyUp) |
                            (1 << kCGEventLeftMouseDown) | (1 << kCGEventLeftMouseUp) |
                            (1 << kCGEventRightMouseDown) | (1 << kCGEventRightMouseUp) |
-                           (1 << kCGEventMouseMoved) | (1 << kCGEventScrollWheel);
+                           (1 << kCGEventOtherMouseDown) | (1 << kCGEventOtherMouseUp) |
+                           (1 << kCGEventMouseMoved) | (1 << kCGEventLeftMouseDragged) |
+                           (1 << kCGEventRightMouseDragged) | (1 << kCGEventOtherMouseDragged) |
+                           (1 << kCGEventScrollWheel);
     
     // Create the event tap
     CFMachPortRef eventTap = CGEventTapCreate(
@@ -82,6 +85,24 @@ uint16_t GetKeyCodeFromEvent(CGEventRef event) {
     return 0;
 }
 
+// Get the button number of a mouse event (0 left, 1 right, 2 middle)
+int64_t GetMouseButtonFromEvent(CGEventRef event) {
+    if (event) {
+        return CGEventGetIntegerValueField(event, kCGMouseEventButtonNumber);
+    }
+    return 0;
+}
+
+// Get the lines scrolled by a scroll wheel event on each axis
+void GetScrollDeltaFromEvent(CGEventRef event, int32_t* vertical, int32_t* horizontal) {
+    *vertical = 0;
+    *horizontal = 0;
+    if (event) {
+        *vertical = (int32_t)CGEventGetIntegerValueField(event, kCGScrollWheelEventDeltaAxis1);
+        *horizontal = (int32_t)CGEventGetIntegerValueField(event, kCGScrollWheelEventDeltaAxis2);
+    }
+}
+
 // Get current mouse position
 int GetCurrentMousePos(CGPoint* point) {
     if (!point) return -1;
@@ -95,6 +116,16 @@ int GetCurrentMousePos(CGPoint* point) {
     return -1;
 }
 
+// Pixels per inch of the main display, in the same units as mouse
+// positions; 0 when the display does not report its physical size
+double GetMainDisplayDpi(void) {
+    CGDirectDisplayID display = CGMainDisplayID();
+    CGSize size = CGDisplayScreenSize(display);
+    if (size.width <= 0) return 0;
+
+    return (double)CGDisplayPixelsWide(display) * 25.4 / size.width;
+}
+
 // Print information about a specific event (for debugging)
 void PrintEventInfo(CGEventType type, CGEventRef event) {
     printf("Event type: %d\n", (int)type);
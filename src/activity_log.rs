@@ -0,0 +1,497 @@
+// activity_monitor/src/activity_log.rs
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::monitor::{ActivitySnapshot, MouseCounts};
+use crate::pointer::PointerTravel;
+use crate::rotation::{self, LogRotation};
+
+// Bumped whenever a JSON Lines field is removed or changes meaning
+pub const LOG_SCHEMA_VERSION: u32 = 1;
+
+// Values accepted by save_activity_log_as()
+pub const LOG_FORMAT_CSV: u32 = 0;
+pub const LOG_FORMAT_JSON_LINES: u32 = 1;
+
+// Values accepted by set_log_sync()
+pub const LOG_SYNC_NONE: u32 = 0;
+pub const LOG_SYNC_DATA: u32 = 1;
+pub const LOG_SYNC_ALL: u32 = 2;
+
+pub(crate) const CSV_HEADER: &[u8] = b"timestamp,keyboard_count,mouse_count,idle_time_seconds\n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    // The original timestamp,keyboard_count,mouse_count,idle_time_seconds
+    // rows, with a header on new files. The layout is fixed, so the mouse
+    // breakdown is left out.
+    Csv,
+    // One LogRecord object per line
+    JsonLines,
+}
+
+impl LogFormat {
+    pub fn from_ffi(format: u32) -> Option<LogFormat> {
+        match format {
+            LOG_FORMAT_CSV => Some(LogFormat::Csv),
+            LOG_FORMAT_JSON_LINES => Some(LogFormat::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+// How far each log write is flushed before the save reports success
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogSync {
+    // Leave it to the OS; a crash may lose recent rows
+    None,
+    // Flush the written rows to disk (fdatasync)
+    #[default]
+    Data,
+    // Also flush file metadata, and the directory entry of new files
+    All,
+}
+
+impl LogSync {
+    pub fn from_ffi(sync: u32) -> Option<LogSync> {
+        match sync {
+            LOG_SYNC_NONE => Some(LogSync::None),
+            LOG_SYNC_DATA => Some(LogSync::Data),
+            LOG_SYNC_ALL => Some(LogSync::All),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counts {
+    pub keyboard: u64,
+    pub mouse: u64,
+    // All zero in CSV logs and records written before it was logged
+    #[serde(default)]
+    pub mouse_detail: MouseCounts,
+}
+
+// One logged interval. Times are in seconds since the epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub schema_version: u32,
+    pub interval_start: u64,
+    pub interval_end: u64,
+    pub host: String,
+    pub session_id: String,
+    // Null when the running sources cannot count input (Wayland)
+    pub counts: Option<Counts>,
+    pub idle_time_seconds: u64,
+    // "active" or "idle"
+    pub idle_state: String,
+    // "active", "locked" or "sleeping"
+    pub session_state: String,
+}
+
+lazy_static::lazy_static! {
+    static ref HOST: String = gethostname::gethostname().to_string_lossy().into_owned();
+}
+
+// This machine's name as logged in records
+pub(crate) fn host() -> &'static str {
+    &HOST
+}
+
+impl LogRecord {
+    pub fn new(snapshot: &ActivitySnapshot, session_id: &str) -> LogRecord {
+        let counts = if snapshot.counters_available {
+            Some(Counts {
+                keyboard: snapshot.keyboard_count,
+                mouse: snapshot.mouse_count,
+                mouse_detail: snapshot.mouse,
+            })
+        } else {
+            None
+        };
+
+        let idle_state = match snapshot.activity_state {
+            crate::ACTIVITY_STATE_IDLE => "idle",
+            _ => "active",
+        };
+
+        let session_state = match snapshot.session_state {
+            crate::SESSION_STATE_LOCKED => "locked",
+            crate::SESSION_STATE_SLEEPING => "sleeping",
+            _ => "active",
+        };
+
+        LogRecord {
+            schema_version: LOG_SCHEMA_VERSION,
+            interval_start: snapshot.interval_start,
+            interval_end: snapshot.timestamp,
+            host: host().to_string(),
+            session_id: session_id.to_string(),
+            counts,
+            idle_time_seconds: snapshot.idle_time,
+            idle_state: idle_state.to_string(),
+            session_state: session_state.to_string(),
+        }
+    }
+
+    // The record in the layout get_activity_snapshot() fills in. States
+    // missing from CSV rows come back as active.
+    pub fn to_snapshot(&self) -> ActivitySnapshot {
+        let activity_state = match self.idle_state.as_str() {
+            "idle" => crate::ACTIVITY_STATE_IDLE,
+            _ => crate::ACTIVITY_STATE_ACTIVE,
+        };
+
+        let session_state = match self.session_state.as_str() {
+            "locked" => crate::SESSION_STATE_LOCKED,
+            "sleeping" => crate::SESSION_STATE_SLEEPING,
+            _ => crate::SESSION_STATE_ACTIVE,
+        };
+
+        let counts = self.counts.unwrap_or_default();
+
+        ActivitySnapshot {
+            timestamp: self.interval_end,
+            keyboard_count: counts.keyboard,
+            mouse_count: counts.mouse,
+            idle_time: self.idle_time_seconds,
+            activity_state,
+            session_state,
+            counters_available: self.counts.is_some(),
+            interval_start: self.interval_start,
+            mouse: counts.mouse_detail,
+            // Not logged
+            pointer_travel: PointerTravel::default(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        // Leave the counts empty rather than logging a misleading zero
+        match self.counts {
+            Some(counts) => format!(
+                "{},{},{},{}\n",
+                self.interval_end, counts.keyboard, counts.mouse, self.idle_time_seconds
+            ),
+            None => format!("{},,,{}\n", self.interval_end, self.idle_time_seconds),
+        }
+    }
+
+    fn to_json_line(&self) -> Option<String> {
+        let mut line = serde_json::to_string(self).ok()?;
+        line.push('\n');
+        Some(line)
+    }
+}
+
+// Append `record` to the log at `path`, creating it if needed. Writers in
+// any process take an exclusive lock on `<path>.lock` first, so rows never
+// interleave and rotation and header checks see a settled file. A row torn
+// by an earlier crash is cut off before the new one is written. A log
+// already holding the other format is left alone with InvalidData, as
+// readers would skip every row after the switch.
+pub(crate) fn append(
+    path: &Path,
+    format: LogFormat,
+    record: &LogRecord,
+    rotation: Option<&LogRotation>,
+    sync: LogSync,
+) -> io::Result<()> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(path))?;
+    lock.lock()?;
+
+    if let Some(rotation) = rotation {
+        rotation::rotate_if_due(path, rotation)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let created = repair_torn_row(&mut file)? == 0;
+    if !created && existing_format(&mut file)? != Some(format) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} holds another log format", path.display()),
+        ));
+    }
+
+    let log_entry = match format {
+        LogFormat::Csv => record.to_csv(),
+        LogFormat::JsonLines => record.to_json_line().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "record could not be serialized")
+        })?,
+    };
+
+    // Header and row go out in one write
+    let mut bytes = Vec::with_capacity(CSV_HEADER.len() + log_entry.len());
+    if created && format == LogFormat::Csv {
+        bytes.extend_from_slice(CSV_HEADER);
+    }
+    bytes.extend_from_slice(log_entry.as_bytes());
+
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(&bytes)?;
+
+    match sync {
+        LogSync::None => Ok(()),
+        LogSync::Data => file.sync_data(),
+        LogSync::All => {
+            file.sync_all()?;
+            if created {
+                sync_directory(path)?;
+            }
+            Ok(())
+        },
+    }
+}
+
+// The file every writer of `path` locks, stable across rotations
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(rotation::LOCK_EXTENSION);
+    PathBuf::from(name)
+}
+
+// The format of a non-empty log, going by its first line as LogReader
+// does; None if that is neither
+fn existing_format(file: &mut File) -> io::Result<Option<LogFormat>> {
+    let mut start = vec![0; CSV_HEADER.len()];
+    file.seek(SeekFrom::Start(0))?;
+    let length = file.read(&mut start)?;
+    let start = &start[..length];
+
+    Ok(if start == CSV_HEADER {
+        Some(LogFormat::Csv)
+    } else if start.starts_with(b"{") {
+        Some(LogFormat::JsonLines)
+    } else {
+        None
+    })
+}
+
+// Truncate the file after its last newline and return its new length.
+// Every complete row and header ends with one, so anything after it is
+// left over from an interrupted write.
+fn repair_torn_row(file: &mut File) -> io::Result<u64> {
+    const CHUNK: u64 = 4096;
+
+    let length = file.metadata()?.len();
+    let mut end = length;
+    let mut buffer = vec![0; CHUNK as usize];
+
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+
+        if let Some(newline) = chunk.iter().rposition(|&byte| byte == b'\n') {
+            end = start + newline as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+
+    if end != length {
+        file.set_len(end)?;
+    }
+    Ok(end)
+}
+
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+// Directory handles cannot be flushed here
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::testing::TempDir;
+
+    fn record(interval_end: u64) -> LogRecord {
+        LogRecord {
+            schema_version: LOG_SCHEMA_VERSION,
+            interval_start: interval_end - 60,
+            interval_end,
+            host: "host".to_string(),
+            session_id: "session".to_string(),
+            counts: Some(Counts {
+                keyboard: 3,
+                mouse: 4,
+                mouse_detail: MouseCounts {
+                    left_clicks: 2,
+                    wheel_vertical: 6,
+                    moves: 1,
+                    ..MouseCounts::default()
+                },
+            }),
+            idle_time_seconds: 5,
+            idle_state: "active".to_string(),
+            session_state: "active".to_string(),
+        }
+    }
+
+    fn append_csv(path: &Path, interval_end: u64) {
+        append(path, LogFormat::Csv, &record(interval_end), None, LogSync::Data).unwrap();
+    }
+
+    #[test]
+    fn new_csv_file_gets_header() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        append_csv(&path, 100);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n");
+    }
+
+    #[test]
+    fn mouse_breakdown_goes_to_json_lines_only() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.jsonl");
+        append(&path, LogFormat::JsonLines, &record(100), None, LogSync::None).unwrap();
+
+        let line = fs::read_to_string(&path).unwrap();
+        let logged: LogRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(logged, record(100));
+
+        // Lines from before the breakdown still read
+        let line = line.replace(r#","mouse_detail":{"left_clicks":2,"right_clicks":0,"middle_clicks":0,"extra_clicks":0,"wheel_vertical":6,"wheel_horizontal":0,"moves":1,"drags":0}"#, "");
+        let logged: LogRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(logged.counts, Some(Counts { keyboard: 3, mouse: 4, ..Counts::default() }));
+    }
+
+    #[test]
+    fn formats_are_never_mixed() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.log");
+        append_csv(&path, 100);
+
+        let error = append(&path, LogFormat::JsonLines, &record(200), None, LogSync::None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        append_csv(&path, 300);
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n300,3,4,5\n");
+
+        let path = path.with_extension("jsonl");
+        append(&path, LogFormat::JsonLines, &record(100), None, LogSync::None).unwrap();
+        assert_eq!(
+            append(&path, LogFormat::Csv, &record(200), None, LogSync::None).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn torn_row_is_cut_off() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        append_csv(&path, 100);
+
+        // A crash in the middle of the second row
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"200,7,").unwrap();
+        drop(file);
+
+        append_csv(&path, 300);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n300,3,4,5\n");
+    }
+
+    #[test]
+    fn torn_header_is_rewritten() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        fs::write(&path, "timestamp,keyboard_co").unwrap();
+
+        append_csv(&path, 100);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n");
+    }
+
+    #[test]
+    fn torn_json_line_is_cut_off() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.jsonl");
+        append(&path, LogFormat::JsonLines, &record(100), None, LogSync::None).unwrap();
+
+        let whole = fs::read(&path).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&whole[..whole.len() / 2]).unwrap();
+        drop(file);
+
+        append(&path, LogFormat::JsonLines, &record(200), None, LogSync::None).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert!(serde_json::from_str::<serde_json::Value>(line).is_ok());
+        }
+    }
+
+    #[test]
+    fn repair_scans_past_one_chunk() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        append_csv(&path, 100);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[b'9'; 10_000]).unwrap();
+        drop(file);
+
+        append_csv(&path, 200);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, "timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n200,3,4,5\n");
+    }
+
+    #[test]
+    fn concurrent_writers_do_not_interleave() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for row in 0..50 {
+                        append_csv(&path, 1000 + writer * 100 + row);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(lines.next(), Some("timestamp,keyboard_count,mouse_count,idle_time_seconds"));
+
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows.len(), 200);
+        assert!(rows.iter().all(|row| row.ends_with(",3,4,5") && row.len() == 10));
+    }
+}
@@ -0,0 +1,187 @@
+// activity_monitor/src/autosave.rs
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use crate::activity_log::LogFormat;
+use crate::monitor::ActivityMonitor;
+
+// Where and how often a monitor instance saves its activity log by itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutosaveConfig {
+    pub path: PathBuf,
+    pub interval_secs: u64,
+    pub format: LogFormat,
+}
+
+// A failed autosave write. `timestamp` is in seconds since the epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutosaveError {
+    pub timestamp: u64,
+    pub message: String,
+}
+
+// Autosave state of one monitor instance. The writer runs while the
+// instance is monitoring and autosave is configured.
+#[derive(Default)]
+pub(crate) struct Autosave {
+    pub(crate) config: Option<AutosaveConfig>,
+    pub(crate) writer: Option<Writer>,
+}
+
+pub(crate) struct Writer {
+    // Dropped to wake the thread and make it exit
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+// The instance a background thread works for. Instances stop their
+// threads before they are freed, so the pointer stays valid for the life
+// of the thread.
+pub(crate) struct MonitorRef(pub(crate) *const ActivityMonitor);
+
+unsafe impl Send for MonitorRef {}
+
+impl MonitorRef {
+    pub(crate) fn get(&self) -> &ActivityMonitor {
+        unsafe { &*self.0 }
+    }
+}
+
+impl Writer {
+    pub(crate) fn spawn(monitor: &ActivityMonitor, config: AutosaveConfig) -> Writer {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let monitor = MonitorRef(monitor);
+        let interval = Duration::from_secs(config.interval_secs);
+
+        let thread = thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                monitor.get().autosave_now(&config);
+            }
+        });
+
+        Writer { stop, thread }
+    }
+
+    // Returns once the thread has exited; a save in progress completes first
+    pub(crate) fn stop(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::activity_log::{LogFormat, LogRecord};
+    use crate::log_reader::read_log;
+    use crate::source::{InputEvent, MockSource};
+    use crate::testing::{self, simulated, TempDir, START};
+
+    fn press(source: &MockSource, code: u32) {
+        source.emit(InputEvent::Key { code, pressed: true });
+        source.emit(InputEvent::Key { code, pressed: false });
+    }
+
+    fn records(path: &Path) -> Vec<LogRecord> {
+        read_log(path, 0, u64::MAX).unwrap().0
+    }
+
+    // The writer waits in real time; the clock only stamps the rows
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn writes_every_interval_and_once_more_on_stop() {
+        let _isolated = testing::isolate();
+        let (clock, source, monitor) = simulated();
+        let dir = TempDir::new();
+        let path = dir.join("activity.jsonl");
+
+        for code in 1..=3 {
+            press(&source, code);
+        }
+        clock.advance(Duration::from_secs(120));
+
+        monitor.start_autosave(&path, 1, LogFormat::JsonLines);
+        assert!(!path.exists());
+        wait_until(|| path.exists() && !records(&path).is_empty());
+
+        let first = &records(&path)[0];
+        assert_eq!((first.interval_start, first.interval_end), (START, START + 120));
+        assert_eq!(first.counts.as_ref().unwrap().keyboard, 3);
+        assert_eq!(first.idle_time_seconds, 120);
+        assert_eq!(first.idle_state, "active");
+
+        // Saving does not move the idle baseline
+        clock.advance(Duration::from_secs(200));
+        wait_until(|| records(&path).iter().any(|record| record.interval_end == START + 320));
+        assert_eq!(monitor.idle_time(), 320);
+        let idle = records(&path).into_iter().find(|record| record.interval_end == START + 320).unwrap();
+        assert_eq!(idle.idle_time_seconds, 320);
+        assert_eq!(idle.idle_state, "idle");
+
+        press(&source, 4);
+        assert!(monitor.stop_autosave());
+        assert!(!monitor.stop_autosave());
+
+        let written = records(&path);
+        let keyboard: u64 = written.iter().filter_map(|record| record.counts.as_ref()).map(|counts| counts.keyboard).sum();
+        assert_eq!(keyboard, 4);
+        assert_eq!(monitor.snapshot().keyboard_count, 0);
+        assert_eq!(monitor.autosave_error(), None);
+
+        // Nothing more once stopped
+        let rows = written.len();
+        thread::sleep(Duration::from_millis(1500));
+        assert_eq!(records(&path).len(), rows);
+    }
+
+    #[test]
+    fn failed_writes_are_reported_and_keep_the_counts() {
+        let _isolated = testing::isolate();
+        let (clock, source, monitor) = simulated();
+        // A directory cannot be appended to
+        let dir = TempDir::new();
+        let path = dir.join("log");
+        fs::create_dir(&path).unwrap();
+
+        press(&source, 1);
+        press(&source, 2);
+        clock.advance(Duration::from_secs(30));
+
+        monitor.start_autosave(&path, 1, LogFormat::Csv);
+        assert_eq!(monitor.autosave_error(), None);
+        wait_until(|| monitor.autosave_error().is_some());
+
+        // The final write fails too
+        assert!(monitor.stop_autosave());
+        let error = monitor.autosave_error().unwrap();
+        assert_eq!(error.timestamp, START + 30);
+        assert!(!error.message.is_empty());
+        let snapshot = monitor.snapshot();
+        assert_eq!(snapshot.keyboard_count, 2);
+        assert_eq!(snapshot.interval_start, START);
+
+        // A new start clears the error, and the kept counts go out with
+        // the next write that works
+        let path = path.join("activity.csv");
+        monitor.start_autosave(&path, 60, LogFormat::Csv);
+        assert_eq!(monitor.autosave_error(), None);
+        assert!(monitor.stop_autosave());
+        assert_eq!(monitor.autosave_error(), None);
+
+        let written = records(&path);
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].interval_end, START + 30);
+        assert_eq!(written[0].counts.as_ref().unwrap().keyboard, 2);
+    }
+}
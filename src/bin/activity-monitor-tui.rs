@@ -0,0 +1,222 @@
+// activity_monitor/src/bin/activity-monitor-tui.rs
+// Live terminal dashboard. Everything shown comes from the library's
+// snapshots and getters; the dashboard installs no hooks of its own.
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use activity_monitor::{ActivityMonitor, ActivitySnapshot, ActivityState, Capabilities};
+
+const REFRESH: Duration = Duration::from_secs(1);
+// Per-refresh samples behind the rates, and minutes in the sparklines
+const RATE_WINDOW: usize = 60;
+const HISTORY_MINUTES: usize = 60;
+
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    keyboard: u64,
+    mouse: u64,
+}
+
+struct Dashboard {
+    monitor: std::sync::Arc<ActivityMonitor>,
+    latest: ActivitySnapshot,
+    // Events per refresh over the last minute, newest last
+    recent: VecDeque<Counts>,
+    // Events per minute over the last hour, newest (current) minute last
+    minutes: VecDeque<Counts>,
+    minute_started: Instant,
+}
+
+impl Dashboard {
+    fn new() -> io::Result<Dashboard> {
+        let monitor = ActivityMonitor::new();
+        if let Some(path) = activity_monitor::config::env_path() {
+            monitor
+                .load_config(&path)
+                .map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?;
+        }
+        monitor.start();
+
+        let mut minutes = VecDeque::with_capacity(HISTORY_MINUTES);
+        minutes.push_back(Counts::default());
+
+        Ok(Dashboard {
+            latest: monitor.snapshot(),
+            monitor,
+            recent: VecDeque::with_capacity(RATE_WINDOW),
+            minutes,
+            minute_started: Instant::now(),
+        })
+    }
+
+    // Take this refresh's counts; each snapshot resets them for the next
+    fn refresh(&mut self) {
+        let snapshot = self.monitor.take_snapshot_and_reset();
+        let counts = Counts {
+            keyboard: snapshot.keyboard_count,
+            mouse: snapshot.mouse_count,
+        };
+        self.latest = snapshot;
+
+        if self.recent.len() == RATE_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(counts);
+
+        if self.minute_started.elapsed() >= Duration::from_secs(60) {
+            self.minute_started = Instant::now();
+            if self.minutes.len() == HISTORY_MINUTES {
+                self.minutes.pop_front();
+            }
+            self.minutes.push_back(Counts::default());
+        }
+        if let Some(minute) = self.minutes.back_mut() {
+            minute.keyboard += counts.keyboard;
+            minute.mouse += counts.mouse;
+        }
+    }
+
+    // Events per minute over the samples of the last minute
+    fn rates(&self) -> (f64, f64) {
+        let seconds = (self.recent.len() as f64 * REFRESH.as_secs_f64()).max(1.0);
+        let total = self.recent.iter().fold(Counts::default(), |total, counts| Counts {
+            keyboard: total.keyboard + counts.keyboard,
+            mouse: total.mouse + counts.mouse,
+        });
+
+        (total.keyboard as f64 * 60.0 / seconds, total.mouse as f64 * 60.0 / seconds)
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, rates, keyboard, mouse, sources, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Min(5),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(self.status_line(), status);
+        frame.render_widget(self.rates_line(), rates);
+
+        let keyboard_history: Vec<u64> = self.minutes.iter().map(|minute| minute.keyboard).collect();
+        let mouse_history: Vec<u64> = self.minutes.iter().map(|minute| minute.mouse).collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" Keyboard events per minute, last hour "))
+                .data(&keyboard_history)
+                .style(Style::default().fg(Color::Cyan)),
+            keyboard,
+        );
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" Mouse events per minute, last hour "))
+                .data(&mouse_history)
+                .style(Style::default().fg(Color::Magenta)),
+            mouse,
+        );
+
+        frame.render_widget(self.sources_line(), sources);
+        let mut footer = vec![Span::from(" q: quit").dim()];
+        if let Some(message) = self.monitor.config_error() {
+            footer.push(Span::from(format!("   config not reloaded: {message}")).red());
+        }
+        frame.render_widget(Line::from(footer), help);
+    }
+
+    fn status_line(&self) -> Paragraph<'static> {
+        let state = match self.monitor.activity_state() {
+            ActivityState::Active => Span::from("ACTIVE").green().bold(),
+            ActivityState::Idle { .. } => Span::from("IDLE").yellow().bold(),
+        };
+        let session = match self.latest.session_state {
+            activity_monitor::SESSION_STATE_LOCKED => "locked",
+            activity_monitor::SESSION_STATE_SLEEPING => "sleeping",
+            _ => "active",
+        };
+
+        Paragraph::new(Line::from(vec![
+            state,
+            Span::from(format!(
+                "   idle {}s (threshold {}s)   session {}",
+                self.latest.idle_time,
+                self.monitor.idle_threshold(),
+                session
+            )),
+        ]))
+        .block(Block::bordered().title(" State "))
+    }
+
+    fn rates_line(&self) -> Paragraph<'static> {
+        let text = if self.latest.counters_available {
+            let (keyboard, mouse) = self.rates();
+            format!(
+                "keyboard {keyboard:>7.1}/min   mouse {mouse:>7.1}/min   keys held {}",
+                activity_monitor::get_pressed_key_count()
+            )
+        } else {
+            "input events cannot be counted here; only idle time is tracked".to_string()
+        };
+
+        Paragraph::new(text).block(Block::bordered().title(" Rates, last minute "))
+    }
+
+    fn sources_line(&self) -> Paragraph<'static> {
+        let mut spans = Vec::new();
+        for source in activity_monitor::try_source_status().unwrap_or_default() {
+            let health = if source.running {
+                Span::from("up").green()
+            } else {
+                Span::from("down").red()
+            };
+            spans.extend([Span::from(format!("{} ", source.name)), health, Span::from("   ")]);
+        }
+
+        let capabilities = Capabilities(activity_monitor::get_capabilities());
+        if spans.is_empty() || capabilities == Capabilities::NONE {
+            spans.push(Span::from("no source is observing input").red());
+        }
+
+        Paragraph::new(Line::from(spans)).block(Block::bordered().title(" Sources "))
+    }
+}
+
+fn run(terminal: &mut DefaultTerminal, mut dashboard: Dashboard) -> io::Result<()> {
+    let mut next_refresh = Instant::now() + REFRESH;
+
+    loop {
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        let timeout = next_refresh.saturating_duration_since(Instant::now());
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c) {
+                    return Ok(());
+                }
+            }
+        }
+
+        if Instant::now() >= next_refresh {
+            dashboard.refresh();
+            next_refresh += REFRESH;
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    // Before taking over the terminal, so a bad config is readable
+    let dashboard = Dashboard::new()?;
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, dashboard);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,362 @@
+// activity_monitor/src/bin/activity-monitor.rs
+// Command-line front end for the library, for use without a host app.
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use activity_monitor::{
+    summarize_log, ActivityMonitor, ActivityState, Capabilities, LogFormat, LogSummary,
+};
+
+const USAGE: &str = "\
+Usage: activity-monitor <command> [options]
+
+Commands:
+  watch [--interval SECS] [--config FILE]     Print live counters and idle time
+  log PATH [--interval SECS] [--format csv|jsonl] [--config FILE]
+                                              Append an interval to PATH every SECS
+  report PATH [--from TIME] [--to TIME]       Summarize a log and its rotated files
+  status                                      Show what monitoring can observe here
+
+TIME is seconds since the epoch, YYYY-MM-DD or \"YYYY-MM-DD HH:MM\" in local time.
+FILE is a TOML config, reloaded when it changes; it defaults to
+$ACTIVITY_MONITOR_CONFIG when that is set.";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().unwrap_or_default();
+    let args: Vec<String> = args.collect();
+
+    let result = match command.as_str() {
+        "watch" => watch(&args),
+        "log" => log(&args),
+        "report" => report(&args),
+        "status" => status(&args),
+        "-h" | "--help" | "help" => {
+            println!("{USAGE}");
+            Ok(())
+        },
+        "" => Err("missing command".to_string()),
+        other => Err(format!("unknown command {other}")),
+    };
+
+    if let Err(message) = result {
+        eprintln!("activity-monitor: {message}\n\n{USAGE}");
+        std::process::exit(2);
+    }
+}
+
+// Options after the command: positional arguments and `--name value` pairs
+struct Options {
+    positional: Vec<String>,
+    named: Vec<(String, String)>,
+}
+
+impl Options {
+    fn parse(args: &[String], allowed: &[&str]) -> Result<Options, String> {
+        let mut options = Options {
+            positional: Vec::new(),
+            named: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if allowed.contains(&name) => match args.next() {
+                    Some(value) => options.named.push((name.to_string(), value.clone())),
+                    None => return Err(format!("--{name} needs a value")),
+                },
+                Some(name) => return Err(format!("unknown option --{name}")),
+                None => options.positional.push(arg.clone()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.named.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn seconds(&self, name: &str, default: u64) -> Result<u64, String> {
+        match self.get(name) {
+            Some(value) => value.parse().map_err(|_| format!("--{name} must be a number of seconds")),
+            None => Ok(default),
+        }
+    }
+
+    // A monitor set up from --config or $ACTIVITY_MONITOR_CONFIG, if given
+    fn monitor(&self) -> Result<Arc<ActivityMonitor>, String> {
+        let monitor = ActivityMonitor::new();
+        if let Some(path) = self.get("config").map(PathBuf::from).or_else(activity_monitor::config::env_path) {
+            monitor.load_config(&path).map_err(|error| format!("{}: {error}", path.display()))?;
+        }
+        Ok(monitor)
+    }
+
+    fn path(&self) -> Result<PathBuf, String> {
+        match self.positional.as_slice() {
+            [path] => Ok(PathBuf::from(path)),
+            [] => Err("missing PATH".to_string()),
+            _ => Err("too many arguments".to_string()),
+        }
+    }
+}
+
+fn watch(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args, &["interval", "config"])?;
+    let interval = options.seconds("interval", 1)?.max(1);
+
+    let monitor = options.monitor()?;
+    monitor.start();
+    if !activity_monitor::counters_available() {
+        println!("Input events cannot be counted here; only idle time is tracked.");
+    }
+
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+
+        let snapshot = monitor.snapshot();
+        let state = match monitor.activity_state() {
+            ActivityState::Active => "active",
+            ActivityState::Idle { .. } => "idle",
+        };
+        println!(
+            "{}  keyboard {:>6}  mouse {:>6}  travel {:>8.0}px  idle {:>5}s  {}",
+            Local::now().format("%H:%M:%S"),
+            snapshot.keyboard_count,
+            snapshot.mouse_count,
+            snapshot.pointer_travel.pixels,
+            snapshot.idle_time,
+            state
+        );
+    }
+}
+
+fn log(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args, &["interval", "format", "config"])?;
+    let path = options.path()?;
+    let interval = options.seconds("interval", 60)?;
+    let monitor = options.monitor()?;
+
+    // The config's log format unless given here. Either way it stays put
+    // for the run, so the log never mixes formats.
+    let format = match options.get("format") {
+        Some("csv") => LogFormat::Csv,
+        Some("jsonl" | "json_lines") => LogFormat::JsonLines,
+        Some(other) => return Err(format!("unknown format {other}")),
+        None => monitor.log_format(),
+    };
+
+    handle_stop_signals();
+    monitor.start_autosave(&path, interval, format);
+    monitor.start();
+    println!("Logging to {} every {}s", path.display(), interval.max(1));
+
+    // The writer runs on its own; report failures as they show up
+    let mut reported = None;
+    let mut reported_config = None;
+    while !STOP_REQUESTED.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(200));
+
+        let error = monitor.autosave_error();
+        if error.is_some() && error != reported {
+            if let Some(error) = &error {
+                eprintln!("activity-monitor: writing {} failed: {}", path.display(), error.message);
+            }
+            reported = error;
+        }
+
+        let config_error = monitor.config_error();
+        if config_error.is_some() && config_error != reported_config {
+            if let Some(message) = &config_error {
+                eprintln!("activity-monitor: config not reloaded: {message}");
+            }
+        }
+        reported_config = config_error;
+    }
+
+    // Write the interval in progress before going
+    monitor.stop_autosave();
+    monitor.stop();
+    if let Some(error) = monitor.autosave_error().filter(|error| Some(error) != reported.as_ref()) {
+        eprintln!("activity-monitor: writing {} failed: {}", path.display(), error.message);
+    }
+    Ok(())
+}
+
+// Set by SIGINT and SIGTERM, so `log` can finish its file and return
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn handle_stop_signals() {
+    extern "C" fn request_stop(_: libc::c_int) {
+        STOP_REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    let handler: extern "C" fn(libc::c_int) = request_stop;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_stop_signals() {}
+
+fn report(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args, &["from", "to"])?;
+    let path = options.path()?;
+    let from = options.get("from").map(parse_time).transpose()?.unwrap_or(0);
+    let to = options.get("to").map(parse_time).transpose()?.unwrap_or(u64::MAX);
+
+    let summary = summarize_log(&path, from, to).map_err(|error| format!("{}: {error}", path.display()))?;
+    print_summary(&summary);
+    Ok(())
+}
+
+fn print_summary(summary: &LogSummary) {
+    if summary.records == 0 {
+        println!("No records in range.");
+    } else {
+        println!("From:          {}", format_time(summary.first_timestamp));
+        println!("To:            {}", format_time(summary.last_timestamp));
+        println!("Records:       {} ({} while idle)", summary.records, summary.idle_records);
+        println!("Keyboard:      {}", summary.keyboard_count);
+        println!("Mouse:         {}", summary.mouse_count);
+        println!(
+            "  clicks:      {} left, {} right, {} middle, {} extra",
+            summary.mouse.left_clicks, summary.mouse.right_clicks, summary.mouse.middle_clicks, summary.mouse.extra_clicks
+        );
+        println!(
+            "  wheel:       {} vertical, {} horizontal",
+            summary.mouse.wheel_vertical, summary.mouse.wheel_horizontal
+        );
+        println!("  moves:       {} ({} drags)", summary.mouse.moves, summary.mouse.drags);
+        println!("Longest idle:  {}s", summary.longest_idle_seconds);
+    }
+
+    if summary.skipped > 0 {
+        println!("Skipped {} unreadable rows.", summary.skipped);
+    }
+}
+
+fn status(args: &[String]) -> Result<(), String> {
+    Options::parse(args, &[])?;
+
+    #[cfg(unix)]
+    {
+        let socket = activity_monitor::ipc::default_socket_path();
+        if let Ok(snapshot) = activity_monitor::ipc::Client::connect(&socket).and_then(|mut client| client.snapshot()) {
+            println!("Daemon:        running on {}", socket.display());
+            println!("Keyboard:      {}", snapshot.keyboard_count);
+            println!("Mouse:         {}", snapshot.mouse_count);
+            println!("Idle:          {}s", snapshot.idle_time);
+        } else {
+            println!("Daemon:        not running");
+        }
+    }
+
+    // Bring the sources up briefly to see which of them work here
+    let monitor = ActivityMonitor::new();
+    monitor.start();
+    let capabilities = Capabilities(activity_monitor::get_capabilities());
+    let session_state = activity_monitor::get_session_state();
+    monitor.stop();
+
+    let names = [
+        (Capabilities::KEYBOARD, "keyboard"),
+        (Capabilities::MOUSE, "mouse"),
+        (Capabilities::IDLE, "idle"),
+        (Capabilities::SESSION, "session"),
+    ];
+    let observed: Vec<&str> = names
+        .iter()
+        .filter(|(capability, _)| capabilities.contains(*capability))
+        .map(|(_, name)| *name)
+        .collect();
+
+    if observed.is_empty() {
+        println!("Sources:       none could start");
+    } else {
+        println!("Sources:       {}", observed.join(", "));
+    }
+    println!(
+        "Session:       {}",
+        match session_state {
+            activity_monitor::SESSION_STATE_LOCKED => "locked",
+            activity_monitor::SESSION_STATE_SLEEPING => "sleeping",
+            _ => "active",
+        }
+    );
+
+    Ok(())
+}
+
+// Seconds since the epoch, YYYY-MM-DD or "YYYY-MM-DD HH:MM" in local time
+fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
+    }
+
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| format!("cannot read time {value:?}"))?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.timestamp().max(0) as u64)
+        .ok_or_else(|| format!("{value:?} does not exist in local time"))
+}
+
+fn format_time(seconds: u64) -> String {
+    match Local.timestamp_opt(seconds as i64, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => seconds.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options_split_positional_and_named() {
+        let options = Options::parse(&args(&["log.csv", "--interval", "30", "--interval", "5"]), &["interval"]).unwrap();
+        assert_eq!(options.path().unwrap(), PathBuf::from("log.csv"));
+        // The last one given wins
+        assert_eq!(options.seconds("interval", 60).unwrap(), 5);
+        assert_eq!(options.seconds("other", 60).unwrap(), 60);
+
+        let options = Options::parse(&args(&["--interval", "soon"]), &["interval"]).unwrap();
+        assert_eq!(options.seconds("interval", 60).unwrap_err(), "--interval must be a number of seconds");
+        assert_eq!(options.path().unwrap_err(), "missing PATH");
+    }
+
+    #[test]
+    fn options_reject_bad_arguments() {
+        assert_eq!(Options::parse(&args(&["--format"]), &["format"]).err().unwrap(), "--format needs a value");
+        assert_eq!(Options::parse(&args(&["--from", "0"]), &["to"]).err().unwrap(), "unknown option --from");
+        let options = Options::parse(&args(&["a.csv", "b.csv"]), &[]).unwrap();
+        assert_eq!(options.path().unwrap_err(), "too many arguments");
+    }
+
+    #[test]
+    fn times_in_local_time_or_seconds() {
+        assert_eq!(parse_time("1700000000").unwrap(), 1_700_000_000);
+        let midnight = Local.with_ymd_and_hms(2024, 6, 15, 0, 0, 0).unwrap().timestamp() as u64;
+        assert_eq!(parse_time("2024-06-15").unwrap(), midnight);
+        assert_eq!(parse_time("2024-06-15 13:45").unwrap(), midnight + 13 * 3600 + 45 * 60);
+
+        assert_eq!(parse_time("yesterday").unwrap_err(), "cannot read time \"yesterday\"");
+        assert!(parse_time("2024-02-30").is_err());
+        assert!(parse_time("-5").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+// activity_monitor/src/bin/activity-monitord.rs
+// Owns one monitor and shares it with local clients over a Unix socket, so
+// several applications can use a single set of input hooks.
+//
+// Usage: activity-monitord [--socket PATH] [--config FILE] [--no-start]
+
+#[cfg(unix)]
+fn main() {
+    use std::path::PathBuf;
+    use activity_monitor::{ipc, ActivityMonitor};
+
+    let mut socket = ipc::default_socket_path();
+    let mut config = activity_monitor::config::env_path();
+    let mut start = true;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => match args.next() {
+                Some(path) => socket = PathBuf::from(path),
+                None => exit_with_usage("--socket needs a path"),
+            },
+            "--config" => match args.next() {
+                Some(path) => config = Some(PathBuf::from(path)),
+                None => exit_with_usage("--config needs a path"),
+            },
+            "--no-start" => start = false,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            },
+            other => exit_with_usage(&format!("unknown argument {other}")),
+        }
+    }
+
+    let monitor = ActivityMonitor::new();
+    // Reloaded whenever the file changes; a broken edit keeps the old settings
+    if let Some(path) = config {
+        if let Err(error) = monitor.load_config(&path) {
+            eprintln!("activity-monitord: {}: {error}", path.display());
+            std::process::exit(1);
+        }
+    }
+
+    // Bound first, so a second daemon gives up before touching any input
+    let listener = match ipc::bind(&socket) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("activity-monitord: {}: {error}", socket.display());
+            std::process::exit(1);
+        },
+    };
+    if start {
+        monitor.start();
+    }
+
+    eprintln!("activity-monitord: serving on {}", socket.display());
+    ipc::serve_listener(listener, monitor)
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("activity-monitord: Unix domain sockets are not supported on this platform");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+const USAGE: &str = "Usage: activity-monitord [--socket PATH] [--config FILE] [--no-start]";
+
+#[cfg(unix)]
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("activity-monitord: {message}\n{USAGE}");
+    std::process::exit(2);
+}
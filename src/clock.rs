@@ -0,0 +1,74 @@
+// activity_monitor/src/clock.rs
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Source of wall-clock time for every timestamp the core takes: idle time,
+// key timeouts and log rows
+pub trait Clock: Send + Sync {
+    // Time since the Unix epoch
+    fn now(&self) -> Duration;
+}
+
+// The real time of day
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+    }
+}
+
+// A clock that only moves when told to. Clones share the same time.
+#[derive(Clone)]
+pub struct SimulatedClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl SimulatedClock {
+    // Start at `start` since the Unix epoch
+    pub fn new(start: Duration) -> SimulatedClock {
+        SimulatedClock {
+            nanos: Arc::new(AtomicU64::new(start.as_nanos() as u64)),
+        }
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(now.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CLOCK: RwLock<Arc<dyn Clock>> = RwLock::new(Arc::new(SystemClock));
+}
+
+// Replace the clock used by the core, e.g. with a SimulatedClock in tests
+pub fn set_clock(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+// Go back to the real time of day
+pub fn use_system_clock() {
+    set_clock(Arc::new(SystemClock));
+}
+
+pub(crate) fn now() -> Duration {
+    CLOCK.read().unwrap().now()
+}
+
+// Whole seconds since the Unix epoch
+pub(crate) fn now_secs() -> u64 {
+    now().as_secs()
+}
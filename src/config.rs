@@ -0,0 +1,478 @@
+// activity_monitor/src/config.rs
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use serde::Deserialize;
+use crate::activity_log::{LogFormat, LogSync};
+use crate::autosave::MonitorRef;
+use crate::debounce::{self, KeyRepeatConfig};
+use crate::monitor::ActivityMonitor;
+use crate::{pointer, source};
+
+// Names a config file for the default monitor and the binaries
+pub const CONFIG_ENV: &str = "ACTIVITY_MONITOR_CONFIG";
+
+// Settings read from a TOML file:
+//
+//   [keyboard]
+//   repeat_timeout_ms = 2000
+//   cleanup_interval_ms = 10000
+//   count_repeats = true
+//
+//   [sources]
+//   poll_interval_ms = 50
+//
+//   [idle]
+//   threshold_secs = 300
+//
+//   [log]
+//   format = "csv"    # or "json_lines"
+//   sync = "data"     # "none", "data" or "all"
+//
+//   [pointer]
+//   travel = true
+//   dpi = 0                  # 0 detects it where the display tells
+//   heatmap_cell_size = 0    # pixels; 0 keeps no heatmap
+//
+// Every key is optional. Missing keys leave the setting as it is, so
+// values set through the API survive a reload; deleting a line from a
+// watched file keeps the value it last set. Unknown keys are errors rather
+// than silently ignored typos.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keyboard: KeyboardConfig,
+    pub sources: SourcesConfig,
+    pub idle: IdleConfig,
+    pub log: LogConfig,
+    pub pointer: PointerConfig,
+}
+
+// See KeyRepeatConfig. Process-wide.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    pub repeat_timeout_ms: Option<u64>,
+    pub cleanup_interval_ms: Option<u64>,
+    pub count_repeats: Option<bool>,
+}
+
+// See source::set_poll_interval(). Process-wide.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    pub poll_interval_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    pub threshold_secs: Option<u64>,
+}
+
+// What save_activity_log() writes and how it is flushed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: Option<LogFormat>,
+    pub sync: Option<LogSync>,
+}
+
+// See pointer::set_travel_enabled() and set_screen_dpi() (process-wide)
+// and enable_heatmap()
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PointerConfig {
+    pub travel: Option<bool>,
+    pub dpi: Option<u32>,
+    pub heatmap_cell_size: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    // Not TOML, or a key or value of the wrong kind. Lines and columns
+    // count from 1; both are 0 when the position is unknown.
+    Parse { line: usize, column: usize, message: String },
+    // A value outside its allowed range
+    Invalid { key: &'static str, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "{error}"),
+            ConfigError::Parse { line: 0, message, .. } => write!(f, "{message}"),
+            ConfigError::Parse { line, column, message } => write!(f, "line {line}, column {column}: {message}"),
+            ConfigError::Invalid { key, message } => write!(f, "{key}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> ConfigError {
+        ConfigError::Io(error)
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        Config::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+        let config: Config = toml::from_str(text).map_err(|error| parse_error(text, &error))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, message: &str| {
+            Err(ConfigError::Invalid {
+                key,
+                message: message.to_string(),
+            })
+        };
+
+        if self.keyboard.repeat_timeout_ms == Some(0) {
+            return invalid("keyboard.repeat_timeout_ms", "must be at least 1");
+        }
+        if self.keyboard.cleanup_interval_ms == Some(0) {
+            return invalid("keyboard.cleanup_interval_ms", "must be at least 1");
+        }
+        if self.sources.poll_interval_ms.is_some_and(|milliseconds| !(1..=1000).contains(&milliseconds)) {
+            return invalid("sources.poll_interval_ms", "must be between 1 and 1000");
+        }
+        if self.idle.threshold_secs == Some(0) {
+            return invalid("idle.threshold_secs", "must be at least 1");
+        }
+
+        Ok(())
+    }
+
+    // `current` with the keyboard settings this config has
+    pub fn key_repeat(&self, current: KeyRepeatConfig) -> KeyRepeatConfig {
+        KeyRepeatConfig {
+            repeat_timeout: self.keyboard.repeat_timeout_ms.map_or(current.repeat_timeout, Duration::from_millis),
+            cleanup_interval: self.keyboard.cleanup_interval_ms.map_or(current.cleanup_interval, Duration::from_millis),
+            count_repeats: self.keyboard.count_repeats.unwrap_or(current.count_repeats),
+        }
+    }
+
+    // Apply the settings this config has. The keyboard, sources, pointer
+    // travel and DPI settings apply to every instance, the rest to
+    // `monitor` only.
+    // Running sources pick the changes up as they go.
+    pub fn apply(&self, monitor: &ActivityMonitor) {
+        debounce::set_key_repeat_config(self.key_repeat(debounce::key_repeat_config()));
+        if let Some(milliseconds) = self.sources.poll_interval_ms {
+            source::set_poll_interval(Duration::from_millis(milliseconds));
+        }
+        if let Some(seconds) = self.idle.threshold_secs {
+            monitor.set_idle_threshold(seconds);
+        }
+        if let Some(format) = self.log.format {
+            monitor.set_log_format(format);
+        }
+        if let Some(sync) = self.log.sync {
+            monitor.set_log_sync(sync);
+        }
+
+        if let Some(enabled) = self.pointer.travel {
+            pointer::set_travel_enabled(enabled);
+        }
+        if let Some(dpi) = self.pointer.dpi {
+            pointer::set_screen_dpi((dpi > 0).then_some(dpi as f64));
+        }
+        // Only a changed size starts the heatmap over
+        match self.pointer.heatmap_cell_size {
+            Some(0) => {
+                monitor.disable_heatmap();
+            },
+            Some(size) if monitor.heatmap_cell_size() != Some(size) => monitor.enable_heatmap(size),
+            _ => {}
+        }
+    }
+}
+
+fn parse_error(text: &str, error: &toml::de::Error) -> ConfigError {
+    let (line, column) = match error.span() {
+        Some(span) => {
+            let before = &text[..span.start.min(text.len())];
+            let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+            (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+        },
+        None => (0, 0),
+    };
+
+    ConfigError::Parse {
+        line,
+        column,
+        message: error.message().to_string(),
+    }
+}
+
+// The file named by ACTIVITY_MONITOR_CONFIG, if set
+pub fn env_path() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+// How often a watcher checks whether it should stop
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+// Reloads a config file into one monitor instance whenever it changes
+pub(crate) struct Watcher {
+    // Dropped to make the thread exit
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Watcher {
+    // Changes from the moment this returns are seen, so a load right after
+    // cannot miss an edit
+    pub(crate) fn spawn(monitor: &ActivityMonitor, path: PathBuf) -> Watcher {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let monitor = MonitorRef(monitor);
+        let mut changes = Changes::new(&path);
+
+        let thread = thread::spawn(move || {
+            while let Err(mpsc::TryRecvError::Empty) = stopped.try_recv() {
+                if changes.wait(&path, STOP_CHECK) {
+                    // Failures are kept in the monitor's config_error()
+                    let _ = monitor.get().reload_config(&path);
+                }
+            }
+        });
+
+        Watcher { stop, thread }
+    }
+
+    // Returns once the thread has exited; a reload in progress completes first
+    pub(crate) fn stop(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
+
+// How a watcher notices that the file changed
+enum Changes {
+    #[cfg(target_os = "linux")]
+    Inotify(Inotify),
+    // Where inotify is not available, the modification time last seen. A
+    // file that goes missing keeps the settings it last had.
+    Modified(Option<SystemTime>),
+}
+
+impl Changes {
+    fn new(path: &Path) -> Changes {
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = Inotify::new(path) {
+            return Changes::Inotify(inotify);
+        }
+
+        Changes::Modified(modified(path))
+    }
+
+    // Wait up to `timeout`; true if the file changed meanwhile
+    fn wait(&mut self, path: &Path, timeout: Duration) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            Changes::Inotify(inotify) => inotify.wait(timeout),
+            Changes::Modified(last) => {
+                thread::sleep(timeout);
+                let current = modified(path);
+                let changed = current.is_some() && current != *last;
+                *last = current;
+                changed
+            },
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// An inotify watch on the directory holding the config file, so a file
+// replaced by a rename (as most editors save) is still seen
+#[cfg(target_os = "linux")]
+struct Inotify {
+    fd: std::os::fd::OwnedFd,
+    name: std::ffi::OsString,
+}
+
+#[cfg(target_os = "linux")]
+impl Inotify {
+    fn new(path: &Path) -> Option<Inotify> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = path.file_name()?.to_os_string();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let directory = std::ffi::CString::new(directory.as_os_str().as_bytes()).ok()?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), directory.as_ptr(), mask) } < 0 {
+            return None;
+        }
+
+        Some(Inotify { fd, name })
+    }
+
+    // Wait up to `timeout` and drain the events; true if any was about the
+    // config file rather than a neighbour in its directory
+    fn wait(&self, timeout: Duration) -> bool {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+        let mut fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+            return false;
+        }
+
+        let mut changed = false;
+        let mut buffer = [0u8; 4096];
+        loop {
+            let length = unsafe { libc::read(fd.fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+            if length <= 0 {
+                break;
+            }
+
+            // Each event is a header followed by a NUL-padded name
+            let events = &buffer[..length as usize];
+            let mut offset = 0;
+            while offset + HEADER <= events.len() {
+                let name_length = u32::from_ne_bytes(events[offset + 12..offset + 16].try_into().unwrap()) as usize;
+                let name = &events[offset + HEADER..(offset + HEADER + name_length).min(events.len())];
+                let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+
+                changed |= name == self.name.as_bytes();
+                offset += HEADER + name_length;
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn missing_keys_leave_settings_alone() {
+        let _isolated = testing::isolate();
+        let config = Config::from_toml("[idle]\nthreshold_secs = 60\n[log]\nformat = \"json_lines\"\n").unwrap();
+        assert_eq!(config.idle.threshold_secs, Some(60));
+        assert_eq!(config.log, LogConfig { format: Some(LogFormat::JsonLines), sync: None });
+        assert_eq!(config.keyboard, KeyboardConfig::default());
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+
+        // Set through the API before the (re)load
+        let monitor = ActivityMonitor::new();
+        let key_repeat = KeyRepeatConfig::default().with_repeat_timeout(Duration::from_millis(500));
+        debounce::set_key_repeat_config(key_repeat);
+        monitor.set_log_sync(LogSync::All);
+
+        config.apply(&monitor);
+        assert_eq!(monitor.idle_threshold(), 60);
+        assert_eq!(monitor.log_format(), LogFormat::JsonLines);
+        assert_eq!(monitor.log_sync(), LogSync::All);
+        assert_eq!(debounce::key_repeat_config(), key_repeat);
+
+        let config = Config::from_toml("[keyboard]\ncount_repeats = false\n").unwrap();
+        config.apply(&monitor);
+        assert_eq!(debounce::key_repeat_config(), key_repeat.with_count_repeats(false));
+        assert_eq!(monitor.idle_threshold(), 60);
+    }
+
+    #[test]
+    fn pointer_settings_apply_only_when_present() {
+        let _isolated = testing::isolate();
+        let monitor = ActivityMonitor::new();
+        monitor.enable_heatmap(32);
+        pointer::set_screen_dpi(Some(120.0));
+
+        Config::from_toml("[idle]\nthreshold_secs = 60\n").unwrap().apply(&monitor);
+        assert_eq!(monitor.heatmap_cell_size(), Some(32));
+        assert_eq!(pointer::screen_dpi(), Some(120.0));
+        assert!(pointer::travel_enabled());
+
+        Config::from_toml("[pointer]\ndpi = 96\nheatmap_cell_size = 32\n").unwrap().apply(&monitor);
+        assert_eq!(pointer::screen_dpi(), Some(96.0));
+        assert_eq!(monitor.heatmap_cell_size(), Some(32));
+
+        Config::from_toml("[pointer]\ntravel = false\ndpi = 0\nheatmap_cell_size = 0\n").unwrap().apply(&monitor);
+        assert!(!pointer::travel_enabled());
+        assert_eq!(pointer::screen_dpi(), None);
+        assert_eq!(monitor.heatmap_cell_size(), None);
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_values() {
+        let error = Config::from_toml("[keyboard]\nrepeat_timeout = 2000\n").unwrap_err();
+        assert!(matches!(error, ConfigError::Parse { line: 2, column: 1, .. }), "{error:?}");
+        assert!(error.to_string().contains("unknown field `repeat_timeout`"), "{error}");
+
+        let error = Config::from_toml("[log]\nformat = \"xml\"\n").unwrap_err();
+        assert!(matches!(error, ConfigError::Parse { line: 2, column: 10, .. }), "{error:?}");
+
+        let error = Config::from_toml("[sources]\npoll_interval_ms = 0\n").unwrap_err();
+        assert_eq!(error.to_string(), "sources.poll_interval_ms: must be between 1 and 1000");
+    }
+
+    #[test]
+    fn watched_file_is_reloaded() {
+        let directory = TempDir::new();
+        let path = directory.join("activity-monitor.toml");
+        fs::write(&path, "[idle]\nthreshold_secs = 60\n").unwrap();
+
+        let monitor = ActivityMonitor::new();
+        monitor.load_config(&path).unwrap();
+        assert_eq!(monitor.idle_threshold(), 60);
+
+        // A broken edit keeps the previous settings
+        fs::write(&path, "[idle]\nthreshold_secs = \"soon\"\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while monitor.config_error().is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(monitor.config_error().is_some());
+        assert_eq!(monitor.idle_threshold(), 60);
+
+        // Replaced by a rename, as editors save
+        let replacement = directory.join("activity-monitor.toml.new");
+        fs::write(&replacement, "[idle]\nthreshold_secs = 90\n").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+        while monitor.idle_threshold() != 90 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(monitor.idle_threshold(), 90);
+        assert!(monitor.config_error().is_none());
+
+        assert!(monitor.stop_watching_config());
+    }
+}
@@ -0,0 +1,218 @@
+// activity_monitor/src/debounce.rs
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::clock;
+use crate::monitor::MouseCounts;
+use crate::source::{MouseButtons, PointerEvent};
+
+// Stateful tracking of keys and mouse buttons shared by every source, so a
+// held key is counted once no matter which backend reports it. Times come from the
+// core clock so the filter can be driven by a simulated clock.
+lazy_static::lazy_static! {
+    // When each held key was counted, or last seen if repeats do not count
+    static ref PRESSED_KEYS: Mutex<HashMap<u32, Duration>> = Mutex::new(HashMap::new());
+    static ref CONFIG: Mutex<KeyRepeatConfig> = Mutex::new(KeyRepeatConfig::default());
+    static ref LAST_CLEANUP: Mutex<Duration> = Mutex::new(Duration::from_secs(0));
+}
+
+// Mouse buttons held down, and whether the pointer has moved since the
+// first of them went down
+static HELD_BUTTONS: Mutex<(MouseButtons, bool)> = Mutex::new((MouseButtons::NONE, false));
+
+// How held and stuck keys are counted. Applies to every source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyRepeatConfig {
+    // With count_repeats, a key held this long counts again on its next
+    // auto-repeat. Either way, a key not seen for this long is forgotten.
+    pub repeat_timeout: Duration,
+    // How often forgotten (stuck) keys are dropped
+    pub cleanup_interval: Duration,
+    // Whether auto-repeat of a held key ever counts as another press
+    pub count_repeats: bool,
+}
+
+impl Default for KeyRepeatConfig {
+    fn default() -> KeyRepeatConfig {
+        KeyRepeatConfig {
+            repeat_timeout: Duration::from_secs(2),
+            cleanup_interval: Duration::from_secs(10),
+            count_repeats: true,
+        }
+    }
+}
+
+impl KeyRepeatConfig {
+    pub fn with_repeat_timeout(mut self, repeat_timeout: Duration) -> KeyRepeatConfig {
+        self.repeat_timeout = repeat_timeout;
+        self
+    }
+
+    pub fn with_cleanup_interval(mut self, cleanup_interval: Duration) -> KeyRepeatConfig {
+        self.cleanup_interval = cleanup_interval;
+        self
+    }
+
+    pub fn with_count_repeats(mut self, count_repeats: bool) -> KeyRepeatConfig {
+        self.count_repeats = count_repeats;
+        self
+    }
+}
+
+// Takes effect from the next key event; keys already held keep their times
+pub fn set_key_repeat_config(config: KeyRepeatConfig) {
+    *CONFIG.lock().unwrap() = config;
+}
+
+pub fn key_repeat_config() -> KeyRepeatConfig {
+    *CONFIG.lock().unwrap()
+}
+
+// Smart activity detection for keyboard. Returns (increment_counter, is_genuine).
+pub(crate) fn process_keyboard_event(key_code: u32, is_down: bool) -> (bool, bool) {
+    cleanup_stale_keys();
+
+    let mut keys = PRESSED_KEYS.lock().unwrap();
+    let config = key_repeat_config();
+
+    // Get current time
+    let now = clock::now();
+
+    if is_down {
+        // If this key is not already pressed (or has timed out), count it as new activity
+        let is_new = match keys.get(&key_code) {
+            Some(time) => now.saturating_sub(*time) > config.repeat_timeout,
+            None => true,
+        };
+
+        if is_new {
+            keys.insert(key_code, now);
+
+            // Signal genuine activity
+            return (true, true);
+        }
+
+        // Still held: keep it from being forgotten as stuck
+        if !config.count_repeats {
+            keys.insert(key_code, now);
+        }
+    } else {
+        // For key up, we don't count it as genuine activity
+        keys.remove(&key_code);
+    }
+
+    // No new activity detected
+    (false, false)
+}
+
+// What a pointer event adds to the mouse counts. Motion while a button is
+// held starts a drag, counted once until every button is up again.
+pub(crate) fn process_pointer_event(event: &PointerEvent) -> MouseCounts {
+    let mut held = HELD_BUTTONS.lock().unwrap();
+    let (buttons, dragging) = &mut *held;
+    let click = |button| event.pressed.contains(button) as u64;
+
+    let mut counts = MouseCounts {
+        left_clicks: click(MouseButtons::LEFT),
+        right_clicks: click(MouseButtons::RIGHT),
+        middle_clicks: click(MouseButtons::MIDDLE),
+        extra_clicks: click(MouseButtons::EXTRA),
+        wheel_vertical: event.wheel_vertical as u64,
+        wheel_horizontal: event.wheel_horizontal as u64,
+        moves: event.moved as u64,
+        drags: 0,
+    };
+
+    *buttons = *buttons | event.pressed;
+    if event.moved && !buttons.is_empty() && !*dragging {
+        *dragging = true;
+        counts.drags = 1;
+    }
+
+    *buttons = buttons.without(event.released);
+    if buttons.is_empty() {
+        *dragging = false;
+    }
+
+    counts
+}
+
+// Remove keys that have been pressed too long (stuck keys), at most once
+// per cleanup interval
+fn cleanup_stale_keys() {
+    let now = clock::now();
+    let config = key_repeat_config();
+    {
+        let mut last_cleanup = LAST_CLEANUP.lock().unwrap();
+        if now.saturating_sub(*last_cleanup) < config.cleanup_interval {
+            return;
+        }
+        *last_cleanup = now;
+    }
+
+    let mut keys = PRESSED_KEYS.lock().unwrap();
+    keys.retain(|_, time| now.saturating_sub(*time) < config.repeat_timeout);
+}
+
+// Keys currently held down, as far as the filter knows
+pub(crate) fn pressed_key_count() -> usize {
+    PRESSED_KEYS.lock().unwrap().len()
+}
+
+pub(crate) fn reset() {
+    let mut keys = PRESSED_KEYS.lock().unwrap();
+    keys.clear();
+    *LAST_CLEANUP.lock().unwrap() = clock::now();
+    *HELD_BUTTONS.lock().unwrap() = (MouseButtons::NONE, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn held_key_counts_once_without_repeats() {
+        // The filter settings and held keys are shared with other tests
+        let _isolated = testing::isolate();
+        set_key_repeat_config(KeyRepeatConfig::default().with_count_repeats(false));
+
+        let key = 0xfff0_0001;
+        assert_eq!(process_keyboard_event(key, true), (true, true));
+        assert_eq!(process_keyboard_event(key, true), (false, false));
+        assert_eq!(process_keyboard_event(key, true), (false, false));
+        assert_eq!(process_keyboard_event(key, false), (false, false));
+        assert_eq!(process_keyboard_event(key, true), (true, true));
+    }
+
+    #[test]
+    fn drag_counts_once_per_press() {
+        let _isolated = testing::isolate();
+        let press = PointerEvent { pressed: MouseButtons::LEFT, ..PointerEvent::default() };
+        let motion = PointerEvent { moved: true, ..PointerEvent::default() };
+        let release = PointerEvent { released: MouseButtons::LEFT, ..PointerEvent::default() };
+
+        assert_eq!(process_pointer_event(&motion).drags, 0);
+        assert_eq!(process_pointer_event(&press).left_clicks, 1);
+        assert_eq!(process_pointer_event(&motion).drags, 1);
+
+        let second = process_pointer_event(&motion);
+        assert_eq!((second.moves, second.drags), (1, 0));
+
+        assert_eq!(process_pointer_event(&release), MouseCounts::default());
+        assert_eq!(process_pointer_event(&motion).drags, 0);
+    }
+
+    #[test]
+    fn ffi_rejects_zero_intervals() {
+        let _isolated = testing::isolate();
+        assert!(!crate::set_key_repeat_timeout_ms(0));
+        assert!(!crate::set_stale_key_cleanup_interval_ms(0));
+        assert_eq!(key_repeat_config(), KeyRepeatConfig::default());
+
+        assert!(crate::set_key_repeat_timeout_ms(500));
+        assert!(crate::set_stale_key_cleanup_interval_ms(1));
+        assert_eq!(crate::get_key_repeat_timeout_ms(), 500);
+        assert_eq!(crate::get_stale_key_cleanup_interval_ms(), 1);
+    }
+}
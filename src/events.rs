@@ -0,0 +1,298 @@
+// activity_monitor/src/events.rs
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+// Values of ActivityEvent::kind
+pub const ACTIVITY_EVENT_KEYBOARD: u32 = 0;
+pub const ACTIVITY_EVENT_MOUSE: u32 = 1;
+pub const ACTIVITY_EVENT_IDLE_START: u32 = 2;
+pub const ACTIVITY_EVENT_IDLE_END: u32 = 3;
+
+// Passed to activity callbacks. Times are in seconds since the epoch.
+// For IDLE_START and IDLE_END, `idle_since` is the last activity before
+// the idle period and `idle_seconds` is how long it has lasted so far
+// (IDLE_START) or in total (IDLE_END); both are zero for other kinds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ActivityEvent {
+    pub kind: u32,
+    pub timestamp: u64,
+    pub idle_seconds: u64,
+    pub idle_since: u64,
+}
+
+// An idle state change carried by an IDLE_START or IDLE_END event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    // Idle time reached the threshold; the user was last active at `idle_since`
+    BecameIdle { idle_since: u64 },
+    // Activity at `active_at` ended the idle period that began at `idle_since`
+    BecameActive { idle_since: u64, active_at: u64 },
+}
+
+impl ActivityEvent {
+    pub fn transition(&self) -> Option<Transition> {
+        match self.kind {
+            ACTIVITY_EVENT_IDLE_START => Some(Transition::BecameIdle {
+                idle_since: self.idle_since,
+            }),
+            ACTIVITY_EVENT_IDLE_END => Some(Transition::BecameActive {
+                idle_since: self.idle_since,
+                active_at: self.timestamp,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// C callback type for register_activity_callback()
+pub type ActivityCallback = extern "C" fn(event: *const ActivityEvent, user_data: *mut c_void);
+
+type Callback = Arc<dyn Fn(&ActivityEvent) + Send + Sync>;
+
+// Callbacks registered on one monitor instance. Shared with the dispatcher
+// so queued events can still be delivered after the instance is freed.
+pub(crate) type CallbackList = Arc<Mutex<Vec<(u64, Callback)>>>;
+
+// Ids are unique across instances, so passing an id to the wrong instance
+// never removes someone else's callback
+static NEXT_CALLBACK_ID: AtomicU64 = AtomicU64::new(1);
+
+// How often the dispatcher checks the monitors for idle transitions
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Dispatcher {
+    sender: Mutex<mpsc::Sender<(CallbackList, ActivityEvent)>>,
+    thread_id: ThreadId,
+}
+
+lazy_static::lazy_static! {
+    // Started by the first registration and kept for the life of the process
+    static ref DISPATCHER: Dispatcher = Dispatcher::spawn();
+    // Held while a callback runs, so unregistering can wait for it to return
+    static ref DELIVERY: Mutex<()> = Mutex::new(());
+}
+
+impl Dispatcher {
+    fn spawn() -> Dispatcher {
+        let (sender, receiver) = mpsc::channel::<(CallbackList, ActivityEvent)>();
+
+        let handle = thread::spawn(move || {
+            let mut last_idle_check = Instant::now();
+
+            loop {
+                match receiver.recv_timeout(IDLE_CHECK_INTERVAL) {
+                    Ok((callbacks, event)) => deliver(&callbacks, &event),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+
+                if last_idle_check.elapsed() >= IDLE_CHECK_INTERVAL {
+                    last_idle_check = Instant::now();
+                    crate::monitor::check_idle();
+                }
+            }
+        });
+
+        Dispatcher {
+            sender: Mutex::new(sender),
+            thread_id: handle.thread().id(),
+        }
+    }
+}
+
+// Call each callback still registered, one at a time. The list lock is not
+// held during the call so a callback may register or unregister callbacks.
+fn deliver(callbacks: &CallbackList, event: &ActivityEvent) {
+    let ids: Vec<u64> = callbacks.lock().unwrap().iter().map(|(id, _)| *id).collect();
+
+    for id in ids {
+        let _delivery = DELIVERY.lock().unwrap();
+        let callback = callbacks
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(registered, _)| *registered == id)
+            .map(|(_, callback)| callback.clone());
+
+        if let Some(callback) = callback {
+            callback(event);
+        }
+    }
+}
+
+// Queue `event` for every callback in `callbacks`
+pub(crate) fn post(callbacks: &CallbackList, event: ActivityEvent) {
+    if callbacks.lock().unwrap().is_empty() {
+        return;
+    }
+
+    let _ = DISPATCHER.sender.lock().unwrap().send((callbacks.clone(), event));
+}
+
+// Start the dispatcher, if it is not running yet, so idle transitions
+// happen on time even when nobody polls a getter
+pub(crate) fn start_idle_checks() {
+    lazy_static::initialize(&DISPATCHER);
+}
+
+pub(crate) fn add(callbacks: &CallbackList, callback: Callback) -> u64 {
+    start_idle_checks();
+
+    let id = NEXT_CALLBACK_ID.fetch_add(1, Ordering::SeqCst);
+    callbacks.lock().unwrap().push((id, callback));
+    id
+}
+
+// Once this returns the callback is not running and will not be called
+// again. A callback may remove itself; it then returns normally.
+pub(crate) fn remove(callbacks: &CallbackList, id: u64) -> bool {
+    let removed = {
+        let mut callbacks = callbacks.lock().unwrap();
+        let before = callbacks.len();
+        callbacks.retain(|(registered, _)| *registered != id);
+        callbacks.len() != before
+    };
+
+    if removed && thread::current().id() != DISPATCHER.thread_id {
+        // Wait out a delivery that picked up the callback before removal
+        drop(DELIVERY.lock().unwrap());
+    }
+
+    removed
+}
+
+// Wraps a C callback and its user data for the dispatcher thread
+pub(crate) fn from_ffi(callback: ActivityCallback, user_data: *mut c_void) -> Callback {
+    struct UserData(*mut c_void);
+    unsafe impl Send for UserData {}
+    unsafe impl Sync for UserData {}
+
+    impl UserData {
+        fn get(&self) -> *mut c_void {
+            self.0
+        }
+    }
+
+    let user_data = UserData(user_data);
+    Arc::new(move |event: &ActivityEvent| {
+        callback(event as *const ActivityEvent, user_data.get());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    fn event(timestamp: u64) -> ActivityEvent {
+        ActivityEvent {
+            kind: ACTIVITY_EVENT_KEYBOARD,
+            timestamp,
+            idle_seconds: 0,
+            idle_since: 0,
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn callbacks_run_in_order_on_the_dispatcher() {
+        let callbacks = CallbackList::default();
+        let (sender, delivered) = mpsc::channel();
+        for callback in 0..2 {
+            let sender = Mutex::new(sender.clone());
+            add(
+                &callbacks,
+                Arc::new(move |event: &ActivityEvent| {
+                    let _ = sender.lock().unwrap().send((callback, event.timestamp, thread::current().id()));
+                }),
+            );
+        }
+
+        for timestamp in 0..20 {
+            post(&callbacks, event(timestamp));
+        }
+
+        for timestamp in 0..20 {
+            for callback in 0..2 {
+                let (received, at, thread) = delivered.recv_timeout(TIMEOUT).unwrap();
+                assert_eq!((received, at), (callback, timestamp));
+                assert_eq!(thread, DISPATCHER.thread_id);
+            }
+        }
+        assert_ne!(thread::current().id(), DISPATCHER.thread_id);
+    }
+
+    #[test]
+    fn remove_waits_for_a_running_callback() {
+        let callbacks = CallbackList::default();
+        let (entered, running) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let (entered, released) = (Mutex::new(entered), Mutex::new(released));
+        let returned = Arc::new(AtomicBool::new(false));
+        let finished = returned.clone();
+        let id = add(
+            &callbacks,
+            Arc::new(move |_: &ActivityEvent| {
+                let _ = entered.lock().unwrap().send(());
+                let _ = released.lock().unwrap().recv_timeout(TIMEOUT);
+                finished.store(true, Ordering::SeqCst);
+            }),
+        );
+
+        post(&callbacks, event(1));
+        running.recv_timeout(TIMEOUT).unwrap();
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let _ = release.send(());
+        });
+        assert!(remove(&callbacks, id));
+        assert!(returned.load(Ordering::SeqCst));
+        assert!(!remove(&callbacks, id));
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn removed_callbacks_are_not_called() {
+        let callbacks = CallbackList::default();
+        let calls = Arc::new(AtomicU64::new(0));
+        let counted = calls.clone();
+        let id = add(
+            &callbacks,
+            Arc::new(move |_: &ActivityEvent| {
+                counted.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+        // Registered second, so it sees each event after the first one
+        let (sender, delivered) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        add(
+            &callbacks,
+            Arc::new(move |event: &ActivityEvent| {
+                let _ = sender.lock().unwrap().send(event.timestamp);
+            }),
+        );
+
+        post(&callbacks, event(1));
+        assert_eq!(delivered.recv_timeout(TIMEOUT).unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Some of these are likely still queued when it goes
+        for timestamp in 2..100 {
+            post(&callbacks, event(timestamp));
+        }
+        assert!(remove(&callbacks, id));
+        let at_removal = calls.load(Ordering::SeqCst);
+
+        for timestamp in 2..100 {
+            assert_eq!(delivered.recv_timeout(TIMEOUT).unwrap(), timestamp);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), at_removal);
+    }
+}
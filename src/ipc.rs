@@ -0,0 +1,401 @@
+// activity_monitor/src/ipc.rs
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::activity_log::LogFormat;
+use crate::events::ActivityEvent;
+use crate::monitor::{ActivityMonitor, ActivitySnapshot};
+
+// One JSON object per line from client to daemon, e.g.
+// {"command":"save","path":"/var/log/activity.csv","format":"csv"}
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Snapshot,
+    // Snapshot and reset in one step
+    TakeSnapshot,
+    Start,
+    Stop,
+    Reset,
+    Save {
+        path: PathBuf,
+        #[serde(default = "default_format")]
+        format: LogFormat,
+    },
+    // Stream every event of the daemon's monitor on this connection
+    Subscribe,
+}
+
+fn default_format() -> LogFormat {
+    LogFormat::Csv
+}
+
+// One JSON object per line from daemon to client. Each request gets
+// exactly one reply; after Subscribe, Event lines follow as they happen.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    // What the matching ActivityMonitor method returned
+    Ack { value: bool },
+    Snapshot { snapshot: ActivitySnapshot },
+    Event { event: ActivityEvent },
+    Error { message: String },
+}
+
+// $XDG_RUNTIME_DIR/activity-monitor.sock, or the same name in a directory
+// of this user's own under the temp directory
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(fallback_dir)
+        .join("activity-monitor.sock")
+}
+
+// The shared temp directory lets anyone create names in it, so the socket
+// goes one level down, in a directory only this user can enter
+fn fallback_dir() -> PathBuf {
+    // SAFETY: getuid cannot fail
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("activity-monitor-{uid}"))
+}
+
+// Serve `monitor` on a Unix socket at `path`, one thread per client. Only
+// returns if the socket cannot be bound; see bind() and serve_listener().
+pub fn serve(path: &Path, monitor: Arc<ActivityMonitor>) -> io::Result<()> {
+    serve_listener(bind(path)?, monitor)
+}
+
+// Serve `monitor` on a socket from bind(), one thread per client, for good.
+// A failed accept, such as when out of file descriptors, only costs that
+// one connection.
+pub fn serve_listener(listener: UnixListener, monitor: Arc<ActivityMonitor>) -> ! {
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(_) => {
+                // Give a lasting failure time to clear rather than spin
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            },
+        };
+
+        let monitor = monitor.clone();
+        thread::spawn(move || {
+            let _ = serve_client(stream, &monitor);
+        });
+    }
+}
+
+// Listen on a Unix socket at `path`. A stale socket left by a daemon that
+// died is replaced; a live one is an error. The socket is only accessible
+// to this user.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if !dir.exists() {
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+        if dir == fallback_dir() {
+            check_private(dir)?;
+        }
+    }
+
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "a daemon is already serving this socket"));
+        }
+        fs::remove_file(path)?;
+    }
+
+    // Created without group or other access, rather than narrowed after
+    // bind, so nobody can connect in between
+    // SAFETY: umask only swaps the process file mode mask
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Someone else may have made the directory first, to listen in our place
+fn check_private(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: getuid cannot fail
+    let uid = unsafe { libc::getuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory of this user", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn serve_client(stream: UnixStream, monitor: &ActivityMonitor) -> io::Result<()> {
+    // Replies and events go through one writer thread, so a slow client
+    // never holds up the event dispatcher
+    let (sender, receiver) = mpsc::channel::<Response>();
+    let mut writer = stream.try_clone()?;
+    let writer_thread = thread::spawn(move || {
+        for response in receiver {
+            if write_line(&mut writer, &response).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subscription = None;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe) if subscription.is_none() => {
+                let events = sender.clone();
+                subscription = Some(monitor.register_callback(move |event| {
+                    let _ = events.send(Response::Event { event: *event });
+                }));
+                Response::Ack { value: true }
+            },
+            Ok(Request::Subscribe) => Response::Ack { value: false },
+            Ok(request) => handle(monitor, request),
+            Err(error) => Response::Error { message: error.to_string() },
+        };
+
+        if sender.send(response).is_err() {
+            break;
+        }
+    }
+
+    if let Some(id) = subscription {
+        monitor.unregister_callback(id);
+    }
+    drop(sender);
+    let _ = writer_thread.join();
+    Ok(())
+}
+
+fn handle(monitor: &ActivityMonitor, request: Request) -> Response {
+    match request {
+        Request::Snapshot => Response::Snapshot { snapshot: monitor.snapshot() },
+        Request::TakeSnapshot => Response::Snapshot { snapshot: monitor.take_snapshot_and_reset() },
+        Request::Start => Response::Ack { value: monitor.start() },
+        Request::Stop => Response::Ack { value: monitor.stop() },
+        Request::Reset => {
+            monitor.reset_counters();
+            Response::Ack { value: true }
+        },
+        Request::Save { path, format } => Response::Ack { value: monitor.save_activity_log_as(&path, format) },
+        Request::Subscribe => Response::Error { message: "subscribe is handled per connection".to_string() },
+    }
+}
+
+fn write_line<T: Serialize>(stream: &mut UnixStream, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value).map_err(io::Error::other)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+// A connection to a daemon started with serve()
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    pub fn connect(path: &Path) -> io::Result<Client> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client { reader, writer })
+    }
+
+    pub fn snapshot(&mut self) -> io::Result<ActivitySnapshot> {
+        self.request_snapshot(&Request::Snapshot)
+    }
+
+    pub fn take_snapshot_and_reset(&mut self) -> io::Result<ActivitySnapshot> {
+        self.request_snapshot(&Request::TakeSnapshot)
+    }
+
+    pub fn start(&mut self) -> io::Result<bool> {
+        self.request_ack(&Request::Start)
+    }
+
+    pub fn stop(&mut self) -> io::Result<bool> {
+        self.request_ack(&Request::Stop)
+    }
+
+    pub fn reset(&mut self) -> io::Result<bool> {
+        self.request_ack(&Request::Reset)
+    }
+
+    // The daemon writes the log, so `path` is resolved on its side
+    pub fn save(&mut self, path: &Path, format: LogFormat) -> io::Result<bool> {
+        self.request_ack(&Request::Save {
+            path: path.to_path_buf(),
+            format,
+        })
+    }
+
+    // Turn the connection into a stream of the daemon's events
+    pub fn subscribe(mut self) -> io::Result<Subscription> {
+        self.request_ack(&Request::Subscribe)?;
+        Ok(Subscription { reader: self.reader })
+    }
+
+    fn request_snapshot(&mut self, request: &Request) -> io::Result<ActivitySnapshot> {
+        match self.request(request)? {
+            Response::Snapshot { snapshot } => Ok(snapshot),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn request_ack(&mut self, request: &Request) -> io::Result<bool> {
+        match self.request(request)? {
+            Response::Ack { value } => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn request(&mut self, request: &Request) -> io::Result<Response> {
+        write_line(&mut self.writer, request)?;
+        read_response(&mut self.reader)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "daemon closed the connection"))
+    }
+}
+
+// Events pushed by the daemon after Client::subscribe(). Ends when the
+// connection closes.
+pub struct Subscription {
+    reader: BufReader<UnixStream>,
+}
+
+impl Iterator for Subscription {
+    type Item = ActivityEvent;
+
+    fn next(&mut self) -> Option<ActivityEvent> {
+        loop {
+            match read_response(&mut self.reader).ok()?? {
+                Response::Event { event } => return Some(event),
+                _ => continue,
+            }
+        }
+    }
+}
+
+fn read_response(reader: &mut BufReader<UnixStream>) -> io::Result<Option<Response>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn unexpected(response: Response) -> io::Error {
+    match response {
+        Response::Error { message } => io::Error::other(message),
+        other => io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply: {other:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{register_source, Capabilities, InputEvent, MockSource};
+    use crate::testing::TempDir;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("activity-monitor-test-{}-{name}.sock", std::process::id()))
+    }
+
+    fn spawn_daemon(path: &Path) -> Arc<ActivityMonitor> {
+        let monitor = ActivityMonitor::new();
+        let listener = bind(path).unwrap();
+        let served = monitor.clone();
+        thread::spawn(move || serve_listener(listener, served));
+        monitor
+    }
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(serde_json::from_str::<Request>(r#"{"command":"reset"}"#).unwrap(), Request::Reset);
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command":"save","path":"/tmp/a.jsonl","format":"json_lines"}"#).unwrap(),
+            Request::Save { path: PathBuf::from("/tmp/a.jsonl"), format: LogFormat::JsonLines }
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"command":"explode"}"#).is_err());
+    }
+
+    #[test]
+    fn clients_share_one_monitor() {
+        let _isolated = crate::testing::isolate();
+        let path = socket_path("share");
+        let source = MockSource::new(Capabilities::KEYBOARD | Capabilities::MOUSE);
+        register_source(Box::new(source.clone()));
+        let _monitor = spawn_daemon(&path);
+
+        let mut control = Client::connect(&path).unwrap();
+        assert!(control.start().unwrap());
+        assert!(!control.start().unwrap());
+
+        let mut events = Client::connect(&path).unwrap().subscribe().unwrap();
+        source.emit(InputEvent::Mouse);
+
+        let event = events.next().unwrap();
+        assert_eq!(event.kind, crate::events::ACTIVITY_EVENT_MOUSE);
+        assert_eq!(control.snapshot().unwrap().mouse_count, 1);
+        assert_eq!(control.take_snapshot_and_reset().unwrap().mouse_count, 1);
+        assert_eq!(control.snapshot().unwrap().mouse_count, 0);
+
+        assert!(control.stop().unwrap());
+        drop(events);
+        thread::sleep(Duration::from_millis(50));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn live_socket_is_not_replaced() {
+        let path = socket_path("live");
+        let _monitor = spawn_daemon(&path);
+
+        assert_eq!(bind(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn socket_is_private_from_the_start() {
+        let dir = TempDir::new();
+        let path = dir.join("nested").join("daemon.sock");
+        let _monitor = spawn_daemon(&path);
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+    }
+
+    #[test]
+    fn fallback_directory_must_be_private() {
+        let dir = fallback_dir();
+        if dir.exists() {
+            // In use by a daemon of this user
+            return;
+        }
+        fs::DirBuilder::new().mode(0o777).create(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+
+        let result = bind(&dir.join("activity-monitor.sock"));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+}
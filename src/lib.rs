@@ -1,177 +1,1169 @@
 // activity_monitor/src/lib.rs
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::fs::OpenOptions;
-use std::io::Write;
+// FFI entry points validate their raw pointers before dereferencing them
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::os::raw::c_void;
 use std::path::Path;
+use std::time::Duration;
+
+pub mod activity_log;
+pub mod autosave;
+pub mod clock;
+pub mod config;
+mod debounce;
+pub mod events;
+#[cfg(unix)]
+pub mod ipc;
+pub mod log_reader;
+pub mod monitor;
+pub mod pointer;
+pub mod rotation;
+pub mod simulated;
+pub mod source;
+#[cfg(test)]
+mod testing;
 
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "macos")]
 mod macos;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+mod x11;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(all(target_os = "linux", feature = "logind"))]
+mod logind;
+#[cfg(feature = "sqlite")]
+pub mod store;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+#[cfg(feature = "statsd")]
+pub mod statsd;
 
-// Global state
-static MONITORING: AtomicBool = AtomicBool::new(false);
-static KEYBOARD_COUNT: AtomicU64 = AtomicU64::new(0);
-static MOUSE_COUNT: AtomicU64 = AtomicU64::new(0);
-static LAST_GENUINE_ACTIVITY: AtomicU64 = AtomicU64::new(0);
+pub use activity_log::{
+    Counts, LogFormat, LogRecord, LogSync, LOG_FORMAT_CSV, LOG_FORMAT_JSON_LINES,
+    LOG_SCHEMA_VERSION, LOG_SYNC_ALL, LOG_SYNC_DATA, LOG_SYNC_NONE,
+};
+pub use autosave::{AutosaveConfig, AutosaveError};
+pub use clock::{set_clock, Clock, SimulatedClock, SystemClock};
+pub use config::{Config, ConfigError, CONFIG_ENV};
+pub use debounce::{key_repeat_config, set_key_repeat_config, KeyRepeatConfig};
+pub use events::{
+    ActivityCallback, ActivityEvent, Transition, ACTIVITY_EVENT_IDLE_END,
+    ACTIVITY_EVENT_IDLE_START, ACTIVITY_EVENT_KEYBOARD, ACTIVITY_EVENT_MOUSE,
+};
+pub use log_reader::{read_log, summarize_log, LogReader, LogSummary, ReadError};
+pub use monitor::{
+    default_monitor, ActivityMonitor, ActivitySnapshot, ActivityState, MouseCounts,
+    DEFAULT_IDLE_THRESHOLD_SECS,
+};
+pub use pointer::{
+    Heatmap, HeatmapFormat, HeatmapLayer, HeatmapMatrix, PointerTravel, DEFAULT_HEATMAP_CELL_SIZE, HEATMAP_FORMAT_MATRIX, HEATMAP_FORMAT_PGM, HEATMAP_LAYER_CLICKS,
+    HEATMAP_LAYER_POSITIONS, MAX_HEATMAP_MATRIX_CELLS,
+};
+pub use rotation::{LogRotation, RotateOn, LOG_ROTATE_DAILY, LOG_ROTATE_NONE, LOG_ROTATE_SIZE};
+pub use simulated::SimulatedSource;
+pub use source::{
+    clear_sources, dispatch, poll_interval, register_source, set_poll_interval, try_source_status,
+    ActivitySource, Capabilities, FirstAvailable, InputEvent, MockSource, MouseButtons,
+    PointerEvent, SourceStatus,
+};
+#[cfg(target_os = "windows")]
+pub use windows::WindowsHookSource;
+#[cfg(target_os = "macos")]
+pub use macos::EventTapSource;
+#[cfg(target_os = "linux")]
+pub use linux::EvdevSource;
+#[cfg(target_os = "linux")]
+pub use x11::X11Source;
+#[cfg(target_os = "linux")]
+pub use wayland::WaylandSource;
+#[cfg(all(target_os = "linux", feature = "logind"))]
+pub use logind::LogindSource;
+#[cfg(feature = "statsd")]
+pub use statsd::StatsdConfig;
+#[cfg(feature = "sqlite")]
+pub use store::{ActivityStore, ActivityTotals, DailyTotals, IdlePeriod};
 
-// FFI exports
-#[no_mangle]
-pub extern "C" fn start_monitoring() -> bool {
-    if MONITORING.load(Ordering::SeqCst) {
-        return false; // Already monitoring
-    }
-    
-    MONITORING.store(true, Ordering::SeqCst);
-    
-    // Reset counters
-    reset_counters();
+// Values returned by get_session_state()
+pub const SESSION_STATE_ACTIVE: u32 = 0;
+pub const SESSION_STATE_LOCKED: u32 = 1;
+pub const SESSION_STATE_SLEEPING: u32 = 2;
+
+// Values returned by get_activity_state()
+pub const ACTIVITY_STATE_ACTIVE: u32 = 0;
+pub const ACTIVITY_STATE_IDLE: u32 = 1;
+
+// Buttons accepted by get_click_count()
+pub const MOUSE_BUTTON_LEFT: u32 = 0;
+pub const MOUSE_BUTTON_RIGHT: u32 = 1;
+pub const MOUSE_BUTTON_MIDDLE: u32 = 2;
+pub const MOUSE_BUTTON_EXTRA: u32 = 3;
+
+// Axes accepted by get_wheel_ticks()
+pub const WHEEL_AXIS_VERTICAL: u32 = 0;
+pub const WHEEL_AXIS_HORIZONTAL: u32 = 1;
+
+// Global state shared by all monitor instances
+static SESSION_LOCKED: AtomicBool = AtomicBool::new(false);
+static SYSTEM_SLEEPING: AtomicBool = AtomicBool::new(false);
+static SLEEP_STARTED: AtomicU64 = AtomicU64::new(0);
+// False when the running sources can only observe idleness (Wayland)
+static COUNTERS_AVAILABLE: AtomicBool = AtomicBool::new(true);
+static ACTIVE_CAPABILITIES: AtomicU32 = AtomicU32::new(0);
+static SOURCES_RUNNING: AtomicBool = AtomicBool::new(false);
+// Events counted since the process started. Never reset, unlike the
+// per-instance counters.
+static TOTAL_KEYBOARD_EVENTS: AtomicU64 = AtomicU64::new(0);
+static TOTAL_MOUSE_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+// Sources used when the host registered none
+pub(crate) fn default_sources() -> Vec<Box<dyn ActivitySource>> {
+    #[allow(unused_mut)]
+    let mut sources: Vec<Box<dyn ActivitySource>> = Vec::new();
     
-    // Start platform-specific monitoring
     #[cfg(target_os = "windows")]
-    windows::start_monitoring();
+    sources.push(Box::new(WindowsHookSource));
     
     #[cfg(target_os = "macos")]
-    macos::start_monitoring();
+    sources.push(Box::new(EventTapSource));
     
-    true
+    // Prefer evdev; most desktop users cannot read /dev/input, so fall
+    // back to the display server. Under Wayland the X server only sees
+    // XWayland clients, so use the compositor's idle notifications instead.
+    #[cfg(target_os = "linux")]
+    {
+        let mut candidates: Vec<Box<dyn ActivitySource>> = vec![Box::new(EvdevSource::new())];
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            candidates.push(Box::new(WaylandSource::new()));
+        }
+        candidates.push(Box::new(X11Source::new()));
+        sources.push(Box::new(FirstAvailable::new("linux-input", candidates)));
+    }
+    
+    // Lock and sleep state from systemd-logind, in addition to input
+    #[cfg(all(target_os = "linux", feature = "logind"))]
+    sources.push(Box::new(LogindSource::new()));
+    
+    sources
+}
+
+// Called by the first monitor instance to start monitoring
+pub(crate) fn sources_started(capabilities: Capabilities) {
+    SOURCES_RUNNING.store(true, Ordering::SeqCst);
+    ACTIVE_CAPABILITIES.store(capabilities.0, Ordering::SeqCst);
+    COUNTERS_AVAILABLE.store(
+        capabilities.intersects(Capabilities::KEYBOARD | Capabilities::MOUSE),
+        Ordering::SeqCst,
+    );
+}
+
+// Called by the last monitor instance to stop monitoring
+pub(crate) fn sources_stopped() {
+    SOURCES_RUNNING.store(false, Ordering::SeqCst);
+    SESSION_LOCKED.store(false, Ordering::SeqCst);
+    SYSTEM_SLEEPING.store(false, Ordering::SeqCst);
+}
+
+// Whether any instance is monitoring, so the shared sources are up
+#[cfg(any(feature = "prometheus", feature = "statsd"))]
+pub(crate) fn sources_running() -> bool {
+    SOURCES_RUNNING.load(Ordering::SeqCst)
+}
+
+// Keyboard and mouse events counted since the process started
+#[cfg(any(feature = "prometheus", feature = "statsd"))]
+pub(crate) fn total_events() -> (u64, u64) {
+    (
+        TOTAL_KEYBOARD_EVENTS.load(Ordering::Relaxed),
+        TOTAL_MOUSE_EVENTS.load(Ordering::Relaxed),
+    )
+}
+
+// The current time for idle calculations. Time spent suspended is not idle
+// time, so while asleep the clock is held at the moment sleep began.
+pub(crate) fn awake_now_secs() -> u64 {
+    if SYSTEM_SLEEPING.load(Ordering::SeqCst) {
+        SLEEP_STARTED.load(Ordering::SeqCst)
+    } else {
+        clock::now_secs()
+    }
+}
+
+// FFI exports. These operate on the default monitor instance.
+#[no_mangle]
+pub extern "C" fn start_monitoring() -> bool {
+    default_monitor().start()
 }
 
 #[no_mangle]
 pub extern "C" fn stop_monitoring() -> bool {
-    if !MONITORING.load(Ordering::SeqCst) {
-        return false; // Not monitoring
-    }
-    
-    MONITORING.store(false, Ordering::SeqCst);
-    
-    // Stop platform-specific monitoring
-    #[cfg(target_os = "windows")]
-    windows::stop_monitoring();
-    
-    #[cfg(target_os = "macos")]
-    macos::stop_monitoring();
-    
-    true
+    default_monitor().stop()
 }
 
 #[no_mangle]
 pub extern "C" fn get_keyboard_count() -> u64 {
-    KEYBOARD_COUNT.load(Ordering::SeqCst)
+    default_monitor().keyboard_count()
 }
 
 #[no_mangle]
 pub extern "C" fn get_mouse_count() -> u64 {
-    MOUSE_COUNT.load(Ordering::SeqCst)
+    default_monitor().mouse_count()
+}
+
+// The mouse count broken down into clicks, wheel notches, moves and drags.
+// Events a source cannot break down count only towards get_mouse_count().
+#[no_mangle]
+pub extern "C" fn get_mouse_counts(counts: *mut MouseCounts) -> bool {
+    write_mouse_counts(counts, default_monitor().mouse_counts())
+}
+
+// Presses of one of the MOUSE_BUTTON_* buttons; 0 for an unknown button
+#[no_mangle]
+pub extern "C" fn get_click_count(button: u32) -> u64 {
+    click_count(&default_monitor().mouse_counts(), button)
+}
+
+// Wheel notches turned on one of the WHEEL_AXIS_* axes, in either direction
+#[no_mangle]
+pub extern "C" fn get_wheel_ticks(axis: u32) -> u64 {
+    wheel_ticks(&default_monitor().mouse_counts(), axis)
+}
+
+#[no_mangle]
+pub extern "C" fn get_mouse_move_count() -> u64 {
+    default_monitor().mouse_counts().moves
+}
+
+// Drags started, one per button press that the pointer moved during
+#[no_mangle]
+pub extern "C" fn get_mouse_drag_count() -> u64 {
+    default_monitor().mouse_counts().drags
+}
+
+// Distance the pointer travelled. Millimetres stay 0 while the screen DPI
+// is unknown, see get_screen_dpi().
+#[no_mangle]
+pub extern "C" fn get_pointer_travel(travel: *mut PointerTravel) -> bool {
+    write_pointer_travel(travel, default_monitor().pointer_travel())
+}
+
+// Whether pointer travel is measured, for all monitors (default true)
+#[no_mangle]
+pub extern "C" fn set_pointer_travel_enabled(enabled: bool) {
+    pointer::set_travel_enabled(enabled);
+}
+
+#[no_mangle]
+pub extern "C" fn get_pointer_travel_enabled() -> bool {
+    pointer::travel_enabled()
+}
+
+// Pixels per inch used for travel in millimetres, for all monitors. 0 or
+// less goes back to what the display reports, where it does.
+#[no_mangle]
+pub extern "C" fn set_screen_dpi(dpi: f64) {
+    pointer::set_screen_dpi(Some(dpi));
+}
+
+// 0 when unknown
+#[no_mangle]
+pub extern "C" fn get_screen_dpi() -> f64 {
+    pointer::screen_dpi().unwrap_or(0.0)
+}
+
+// Start counting pointer positions and clicks on a grid of `cell_size`
+// pixel cells, replacing any heatmap kept before. Never reset by the
+// counters.
+#[no_mangle]
+pub extern "C" fn enable_heatmap(cell_size: u32) {
+    default_monitor().enable_heatmap(cell_size);
+}
+
+// Returns false if no heatmap was kept
+#[no_mangle]
+pub extern "C" fn disable_heatmap() -> bool {
+    default_monitor().disable_heatmap()
+}
+
+// Export one of the HEATMAP_LAYER_* counts of the heatmap to `path` in one
+// of the HEATMAP_FORMAT_* formats, replacing the file
+#[no_mangle]
+pub extern "C" fn save_heatmap(path_ptr: *const u8, path_len: usize, layer: u32, format: u32) -> bool {
+    save_heatmap_on(default_monitor(), path_ptr, path_len, layer, format)
+}
+
+fn save_heatmap_on(monitor: &ActivityMonitor, path_ptr: *const u8, path_len: usize, layer: u32, format: u32) -> bool {
+    match (path_from_ffi(path_ptr, path_len), HeatmapLayer::from_ffi(layer), HeatmapFormat::from_ffi(format)) {
+        (Some(path), Some(layer), Some(format)) => monitor.save_heatmap(path, layer, format),
+        _ => false,
+    }
+}
+
+fn write_pointer_travel(out: *mut PointerTravel, travel: PointerTravel) -> bool {
+    if out.is_null() {
+        return false;
+    }
+
+    unsafe { *out = travel };
+    true
+}
+
+fn write_mouse_counts(out: *mut MouseCounts, counts: MouseCounts) -> bool {
+    if out.is_null() {
+        return false;
+    }
+
+    unsafe { *out = counts };
+    true
+}
+
+fn click_count(counts: &MouseCounts, button: u32) -> u64 {
+    match button {
+        MOUSE_BUTTON_LEFT => counts.left_clicks,
+        MOUSE_BUTTON_RIGHT => counts.right_clicks,
+        MOUSE_BUTTON_MIDDLE => counts.middle_clicks,
+        MOUSE_BUTTON_EXTRA => counts.extra_clicks,
+        _ => 0,
+    }
+}
+
+fn wheel_ticks(counts: &MouseCounts, axis: u32) -> u64 {
+    match axis {
+        WHEEL_AXIS_VERTICAL => counts.wheel_vertical,
+        WHEEL_AXIS_HORIZONTAL => counts.wheel_horizontal,
+        _ => 0,
+    }
+}
+
+// Whether get_keyboard_count/get_mouse_count reflect real input. When this
+// is false only get_idle_time is meaningful and the counts stay at zero.
+#[no_mangle]
+pub extern "C" fn counters_available() -> bool {
+    COUNTERS_AVAILABLE.load(Ordering::SeqCst)
+}
+
+// Capabilities::* bits of the sources that started with the last
+// start_monitoring()
+#[no_mangle]
+pub extern "C" fn get_capabilities() -> u32 {
+    ACTIVE_CAPABILITIES.load(Ordering::SeqCst)
+}
+
+// A key held this long counts again on its next auto-repeat, if repeats
+// count; either way a key not seen for this long is treated as released.
+// Applies to all sources. Default 2000 ms. Returns false and changes
+// nothing for 0.
+#[no_mangle]
+pub extern "C" fn set_key_repeat_timeout_ms(milliseconds: u64) -> bool {
+    if milliseconds == 0 {
+        return false;
+    }
+    set_key_repeat_config(key_repeat_config().with_repeat_timeout(Duration::from_millis(milliseconds)));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn get_key_repeat_timeout_ms() -> u64 {
+    key_repeat_config().repeat_timeout.as_millis() as u64
+}
+
+// How often keys stuck down (no key-up seen) are dropped. Default 10000 ms.
+// Returns false and changes nothing for 0.
+#[no_mangle]
+pub extern "C" fn set_stale_key_cleanup_interval_ms(milliseconds: u64) -> bool {
+    if milliseconds == 0 {
+        return false;
+    }
+    set_key_repeat_config(key_repeat_config().with_cleanup_interval(Duration::from_millis(milliseconds)));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn get_stale_key_cleanup_interval_ms() -> u64 {
+    key_repeat_config().cleanup_interval.as_millis() as u64
+}
+
+// Whether a held key's auto-repeat ever counts as another press (default
+// true). When false a key counts once per press however long it is held.
+#[no_mangle]
+pub extern "C" fn set_count_key_repeats(count_repeats: bool) {
+    set_key_repeat_config(key_repeat_config().with_count_repeats(count_repeats));
+}
+
+#[no_mangle]
+pub extern "C" fn get_count_key_repeats() -> bool {
+    key_repeat_config().count_repeats
+}
+
+// How long the built-in sources wait for input at a time before checking
+// for a stop request, 1..1000 ms. Applies to all sources. Default 50 ms.
+#[no_mangle]
+pub extern "C" fn set_poll_interval_ms(milliseconds: u64) {
+    set_poll_interval(Duration::from_millis(milliseconds));
+}
+
+#[no_mangle]
+pub extern "C" fn get_poll_interval_ms() -> u64 {
+    poll_interval().as_millis() as u64
+}
+
+// Keys the repeat filter currently considers held down, across all sources
+#[no_mangle]
+pub extern "C" fn get_pressed_key_count() -> u32 {
+    debounce::pressed_key_count() as u32
 }
 
 #[no_mangle]
 pub extern "C" fn get_idle_time() -> u64 {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs();
-    
-    let last = LAST_GENUINE_ACTIVITY.load(Ordering::SeqCst);
-    if last == 0 || now < last {
-        return 0;
+    default_monitor().idle_time()
+}
+
+// Idle time in seconds after which the user counts as idle (default 300)
+#[no_mangle]
+pub extern "C" fn set_idle_threshold(seconds: u64) {
+    default_monitor().set_idle_threshold(seconds);
+}
+
+#[no_mangle]
+pub extern "C" fn get_idle_threshold() -> u64 {
+    default_monitor().idle_threshold()
+}
+
+// One of the ACTIVITY_STATE_* values
+#[no_mangle]
+pub extern "C" fn get_activity_state() -> u32 {
+    activity_state_to_ffi(default_monitor().activity_state())
+}
+
+// Time of the last activity before the current idle period (seconds since
+// the epoch), or 0 while active
+#[no_mangle]
+pub extern "C" fn get_idle_since() -> u64 {
+    idle_since_to_ffi(default_monitor().activity_state())
+}
+
+fn activity_state_to_ffi(state: ActivityState) -> u32 {
+    match state {
+        ActivityState::Active => ACTIVITY_STATE_ACTIVE,
+        ActivityState::Idle { .. } => ACTIVITY_STATE_IDLE,
+    }
+}
+
+fn idle_since_to_ffi(state: ActivityState) -> u64 {
+    match state {
+        ActivityState::Active => 0,
+        ActivityState::Idle { since } => since,
+    }
+}
+
+// One of the SESSION_STATE_* values. Lock and sleep are only detected
+// when built with the `logind` feature.
+#[no_mangle]
+pub extern "C" fn get_session_state() -> u32 {
+    if SYSTEM_SLEEPING.load(Ordering::SeqCst) {
+        SESSION_STATE_SLEEPING
+    } else if SESSION_LOCKED.load(Ordering::SeqCst) {
+        SESSION_STATE_LOCKED
+    } else {
+        SESSION_STATE_ACTIVE
     }
-    
-    now - last
 }
 
 #[no_mangle]
 pub extern "C" fn reset_counters() {
-    KEYBOARD_COUNT.store(0, Ordering::SeqCst);
-    MOUSE_COUNT.store(0, Ordering::SeqCst);
+    default_monitor().reset_counters();
+}
+
+#[no_mangle]
+pub extern "C" fn save_activity_log(path_ptr: *const u8, path_len: usize) -> bool {
+    match path_from_ffi(path_ptr, path_len) {
+        Some(path) => default_monitor().save_activity_log(path),
+        None => false,
+    }
+}
+
+// Like save_activity_log() in one of the LOG_FORMAT_* formats. Fails if
+// the file already holds the other format.
+#[no_mangle]
+pub extern "C" fn save_activity_log_as(path_ptr: *const u8, path_len: usize, format: u32) -> bool {
+    match (path_from_ffi(path_ptr, path_len), LogFormat::from_ffi(format)) {
+        (Some(path), Some(format)) => default_monitor().save_activity_log_as(path, format),
+        _ => false,
+    }
+}
+
+// Rotate logs written by save_activity_log() and autosave once they reach
+// `max_bytes` (LOG_ROTATE_SIZE) or on a new local day (LOG_ROTATE_DAILY).
+// Keeps at most `keep_files` rotated files, none older than `max_age_secs`;
+// zero means no limit. LOG_ROTATE_NONE turns rotation off.
+#[no_mangle]
+pub extern "C" fn set_log_rotation(mode: u32, max_bytes: u64, keep_files: u32, max_age_secs: u64, compress: bool) -> bool {
+    set_log_rotation_on(default_monitor(), mode, max_bytes, keep_files, max_age_secs, compress)
+}
+
+fn set_log_rotation_on(monitor: &ActivityMonitor, mode: u32, max_bytes: u64, keep_files: u32, max_age_secs: u64, compress: bool) -> bool {
+    if mode == LOG_ROTATE_NONE {
+        monitor.set_log_rotation(None);
+        return true;
+    }
     
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs();
-    LAST_GENUINE_ACTIVITY.store(now, Ordering::SeqCst);
+    match LogRotation::from_ffi(mode, max_bytes, keep_files, max_age_secs, compress) {
+        Some(log_rotation) => {
+            monitor.set_log_rotation(Some(log_rotation));
+            true
+        },
+        None => false,
+    }
+}
+
+// Read back the log at the path and its rotated segments. Writes up to
+// `capacity` records whose interval ended in [from, to), oldest first, and
+// returns how many records matched, or 0 on failure. Fields missing from
+// CSV logs are zero.
+#[no_mangle]
+pub extern "C" fn read_activity_log(path_ptr: *const u8, path_len: usize, from: u64, to: u64, records: *mut ActivitySnapshot, capacity: usize) -> usize {
+    let path = match path_from_ffi(path_ptr, path_len) {
+        Some(path) => path,
+        None => return 0,
+    };
     
-    // Also reset platform-specific state if needed
-    #[cfg(target_os = "windows")]
-    windows::reset_monitoring_state();
+    match read_log(path, from, to) {
+        Ok((matched, _)) => {
+            let snapshots: Vec<ActivitySnapshot> = matched.iter().take(capacity).map(LogRecord::to_snapshot).collect();
+            write_slice(records, capacity, &snapshots);
+            matched.len()
+        },
+        Err(_) => 0,
+    }
+}
+
+// Like read_activity_log(), totalled into `summary`
+#[no_mangle]
+pub extern "C" fn summarize_activity_log(path_ptr: *const u8, path_len: usize, from: u64, to: u64, summary: *mut LogSummary) -> bool {
+    if summary.is_null() {
+        return false;
+    }
     
-    #[cfg(target_os = "macos")]
-    macos::reset_monitoring_state();
+    match path_from_ffi(path_ptr, path_len).map(|path| summarize_log(path, from, to)) {
+        Some(Ok(totals)) => {
+            unsafe { summary.write(totals) };
+            true
+        },
+        _ => false,
+    }
 }
 
+// One of the LOG_SYNC_* values: how far each log write is flushed to disk
+// before the save returns (default LOG_SYNC_DATA)
 #[no_mangle]
-pub extern "C" fn save_activity_log(path_ptr: *const u8, path_len: usize) -> bool {
-    if path_ptr.is_null() {
+pub extern "C" fn set_log_sync(sync: u32) -> bool {
+    match LogSync::from_ffi(sync) {
+        Some(sync) => {
+            default_monitor().set_log_sync(sync);
+            true
+        },
+        None => false,
+    }
+}
+
+// Fill `snapshot` with all counters read at one instant
+#[no_mangle]
+pub extern "C" fn get_activity_snapshot(snapshot: *mut ActivitySnapshot) -> bool {
+    write_snapshot(snapshot, default_monitor().snapshot())
+}
+
+// Like get_activity_snapshot() followed by reset_counters(), except that
+// no event can fall between the two
+#[no_mangle]
+pub extern "C" fn take_snapshot_and_reset(snapshot: *mut ActivitySnapshot) -> bool {
+    if snapshot.is_null() {
         return false;
     }
     
-    let path_slice = unsafe { std::slice::from_raw_parts(path_ptr, path_len) };
-    let path_str = match std::str::from_utf8(path_slice) {
-        Ok(s) => s,
-        Err(_) => return false,
-    };
+    write_snapshot(snapshot, default_monitor().take_snapshot_and_reset())
+}
+
+fn write_snapshot(out: *mut ActivitySnapshot, snapshot: ActivitySnapshot) -> bool {
+    if out.is_null() {
+        return false;
+    }
     
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or(Duration::from_secs(0))
-        .as_secs();
-        
-    let keyboard_count = KEYBOARD_COUNT.load(Ordering::SeqCst);
-    let mouse_count = MOUSE_COUNT.load(Ordering::SeqCst);
-    let idle_time = get_idle_time();
-    
-    let log_entry = format!(
-        "{},{},{},{}\n",
-        now, keyboard_count, mouse_count, idle_time
-    );
+    unsafe { out.write(snapshot) };
+    true
+}
+
+// Deliver keyboard, mouse, idle-start and idle-end events to `callback` on
+// the library's dispatcher thread, one at a time and in order; it is never
+// called from an OS hook thread. `event` is only valid during the call.
+// Returns an id for unregister_activity_callback(), or 0 on failure.
+#[no_mangle]
+pub extern "C" fn register_activity_callback(callback: Option<ActivityCallback>, user_data: *mut c_void) -> u64 {
+    match callback {
+        Some(callback) => default_monitor().register_ffi_callback(callback, user_data),
+        None => 0,
+    }
+}
+
+// Once this returns the callback is not running and will not be called
+// again, so `user_data` may be freed. Safe to call from inside the callback.
+#[no_mangle]
+pub extern "C" fn unregister_activity_callback(id: u64) -> bool {
+    default_monitor().unregister_callback(id)
+}
+
+// Save the activity log to the path every `interval_secs` on a background
+// thread while monitoring, and once more on stop_monitoring(). `format` is
+// one of the LOG_FORMAT_* values. Replaces any autosave started before.
+#[no_mangle]
+pub extern "C" fn start_autosave(path_ptr: *const u8, path_len: usize, interval_secs: u64, format: u32) -> bool {
+    start_autosave_on(default_monitor(), path_ptr, path_len, interval_secs, format)
+}
+
+// Saves the pending interval first while monitoring. Returns false if
+// autosave was not started.
+#[no_mangle]
+pub extern "C" fn stop_autosave() -> bool {
+    default_monitor().stop_autosave()
+}
+
+// Copies the message of the most recent failed autosave write into `buffer`
+// (UTF-8, not NUL-terminated, truncated to `capacity`) and returns its full
+// length, or 0 if no write has failed since start_autosave()
+#[no_mangle]
+pub extern "C" fn get_autosave_error(buffer: *mut u8, capacity: usize) -> usize {
+    autosave_error_to_ffi(default_monitor(), buffer, capacity)
+}
+
+fn start_autosave_on(monitor: &ActivityMonitor, path_ptr: *const u8, path_len: usize, interval_secs: u64, format: u32) -> bool {
+    match (path_from_ffi(path_ptr, path_len), LogFormat::from_ffi(format)) {
+        (Some(path), Some(format)) => {
+            monitor.start_autosave(path, interval_secs, format);
+            true
+        },
+        _ => false,
+    }
+}
+
+fn autosave_error_to_ffi(monitor: &ActivityMonitor, buffer: *mut u8, capacity: usize) -> usize {
+    message_to_ffi(monitor.autosave_error().map(|error| error.message), buffer, capacity)
+}
+
+// Apply the TOML config file at `path` and reapply it whenever it changes,
+// replacing any file loaded before. Settings under [keyboard] and [sources],
+// and the pointer DPI, apply to all monitors. Returns false, with the reason in
+// get_config_error(), if the file cannot be loaded now. A file named by the
+// ACTIVITY_MONITOR_CONFIG environment variable is loaded this way when the
+// handle-less functions are first used.
+#[no_mangle]
+pub extern "C" fn load_config(path_ptr: *const u8, path_len: usize) -> bool {
+    load_config_on(default_monitor(), path_ptr, path_len)
+}
+
+// Keeps the settings loaded so far. Returns false if no file was watched.
+#[no_mangle]
+pub extern "C" fn stop_watching_config() -> bool {
+    default_monitor().stop_watching_config()
+}
+
+// Copies why the config file last failed to load into `buffer` (UTF-8, not
+// NUL-terminated, truncated to `capacity`) and returns its full length, or
+// 0 if it has loaded since. A failed reload keeps the previous settings.
+#[no_mangle]
+pub extern "C" fn get_config_error(buffer: *mut u8, capacity: usize) -> usize {
+    message_to_ffi(default_monitor().config_error(), buffer, capacity)
+}
+
+fn load_config_on(monitor: &ActivityMonitor, path_ptr: *const u8, path_len: usize) -> bool {
+    path_from_ffi(path_ptr, path_len).is_some_and(|path| monitor.load_config(path).is_ok())
+}
+
+fn message_to_ffi(message: Option<String>, buffer: *mut u8, capacity: usize) -> usize {
+    match message {
+        Some(message) => {
+            write_slice(buffer, capacity, message.as_bytes());
+            message.len()
+        },
+        None => 0,
+    }
+}
+
+// Serve Prometheus metrics at http://127.0.0.1:<port>/metrics, replacing
+// any server started before. Port 0 picks a free port. Returns the port
+// served on, or 0 on failure.
+#[cfg(feature = "prometheus")]
+#[no_mangle]
+pub extern "C" fn start_metrics_server(port: u16) -> u16 {
+    metrics::serve(port).unwrap_or(0)
+}
+
+#[cfg(feature = "prometheus")]
+#[no_mangle]
+pub extern "C" fn stop_metrics_server() -> bool {
+    metrics::shutdown()
+}
+
+// Push StatsD metrics over UDP to `address` ("host:port") every
+// `interval_secs`, replacing any push started before. `tags` is an optional
+// DogStatsD tag list like "env:prod,team:ops"; pass an empty one for plain
+// StatsD.
+#[cfg(feature = "statsd")]
+#[no_mangle]
+pub extern "C" fn start_statsd(address_ptr: *const u8, address_len: usize, interval_secs: u64, tags_ptr: *const u8, tags_len: usize) -> bool {
+    let address = match str_from_ffi(address_ptr, address_len) {
+        Some(address) => address,
+        None => return false,
+    };
+    let tags = if tags_ptr.is_null() { Some("") } else { str_from_ffi(tags_ptr, tags_len) };
     
-    let path = Path::new(path_str);
-    let file_exists = path.exists();
+    match (statsd::StatsdConfig::new(address, interval_secs), tags) {
+        (Ok(config), Some(tags)) => statsd::start(config.with_tags(tags)).is_ok(),
+        _ => false,
+    }
+}
+
+// Sends the final deltas first
+#[cfg(feature = "statsd")]
+#[no_mangle]
+pub extern "C" fn stop_statsd() -> bool {
+    statsd::stop()
+}
+
+// Open (creating if needed) a SQLite activity store and attach it to the
+// default monitor, replacing any store attached before
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn open_activity_store(path_ptr: *const u8, path_len: usize) -> bool {
+    open_store(default_monitor(), path_ptr, path_len)
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn close_activity_store() {
+    default_monitor().detach_store();
+}
+
+// Like save_activity_log(), into the attached store
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn save_activity_store() -> bool {
+    default_monitor().save_to_store()
+}
+
+// Store queries take [from, to) in seconds since the epoch. An interval
+// partly inside the range adds the share of its counts that matches the
+// share of its time inside it.
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn query_activity_totals(from: u64, to: u64, totals: *mut ActivityTotals) -> bool {
+    query_totals(default_monitor(), from, to, totals)
+}
+
+// Writes up to `capacity` days, oldest first, and returns how many days
+// have data, or 0 on failure
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn query_daily_totals(from: u64, to: u64, days: *mut DailyTotals, capacity: usize) -> usize {
+    query_days(default_monitor(), from, to, days, capacity)
+}
+
+// Writes up to `capacity` idle periods, longest first, and returns how
+// many were written
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn query_longest_idle_gaps(from: u64, to: u64, gaps: *mut IdlePeriod, capacity: usize) -> usize {
+    query_gaps(default_monitor(), from, to, gaps, capacity)
+}
+
+#[cfg(feature = "sqlite")]
+fn open_store(monitor: &ActivityMonitor, path_ptr: *const u8, path_len: usize) -> bool {
+    let path = match path_from_ffi(path_ptr, path_len) {
+        Some(path) => path,
+        None => return false,
+    };
     
-    match OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path) 
-    {
-        Ok(mut file) => {
-            if !file_exists {
-                // Write header if creating new file
-                if let Err(_) = file.write_all(b"timestamp,keyboard_count,mouse_count,idle_time_seconds\n") {
-                    return false;
-                }
-            }
-            
-            match file.write_all(log_entry.as_bytes()) {
-                Ok(_) => {
-                    // Reset counters after logging
-                    reset_counters();
-                    true
-                },
-                Err(_) => false,
-            }
+    match ActivityStore::open(path) {
+        Ok(store) => {
+            monitor.attach_store(std::sync::Arc::new(store));
+            true
         },
         Err(_) => false,
     }
 }
 
-// Internal functions for the OS-specific modules to call
+#[cfg(feature = "sqlite")]
+fn query_totals(monitor: &ActivityMonitor, from: u64, to: u64, out: *mut ActivityTotals) -> bool {
+    if out.is_null() {
+        return false;
+    }
+    
+    match monitor.store().map(|store| store.totals(from, to)) {
+        Some(Ok(totals)) => {
+            unsafe { out.write(totals) };
+            true
+        },
+        _ => false,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn query_days(monitor: &ActivityMonitor, from: u64, to: u64, out: *mut DailyTotals, capacity: usize) -> usize {
+    match monitor.store().map(|store| store.daily_totals(from, to)) {
+        Some(Ok(days)) => {
+            write_slice(out, capacity, &days);
+            days.len()
+        },
+        _ => 0,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn query_gaps(monitor: &ActivityMonitor, from: u64, to: u64, out: *mut IdlePeriod, capacity: usize) -> usize {
+    if out.is_null() {
+        return 0;
+    }
+    
+    match monitor.store().map(|store| store.longest_idle_gaps(from, to, capacity)) {
+        Some(Ok(gaps)) => write_slice(out, capacity, &gaps),
+        _ => 0,
+    }
+}
+
+// Copy up to `capacity` items to `out` and return how many were copied
+fn write_slice<T: Copy>(out: *mut T, capacity: usize, items: &[T]) -> usize {
+    if out.is_null() {
+        return 0;
+    }
+    
+    let count = items.len().min(capacity);
+    unsafe { std::ptr::copy_nonoverlapping(items.as_ptr(), out, count) };
+    count
+}
+
+fn path_from_ffi<'a>(path_ptr: *const u8, path_len: usize) -> Option<&'a Path> {
+    str_from_ffi(path_ptr, path_len).map(Path::new)
+}
+
+fn str_from_ffi<'a>(ptr: *const u8, len: usize) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    
+    let slice = unsafe { std::slice::from_raw_parts(ptr, len) };
+    std::str::from_utf8(slice).ok()
+}
+
+fn monitor_from_ffi<'a>(monitor: *const ActivityMonitor) -> Option<&'a ActivityMonitor> {
+    unsafe { monitor.as_ref() }
+}
+
+// Handle-based FFI exports. Each handle has its own counters and idle
+// baseline; all handles share one set of OS hooks.
+#[no_mangle]
+pub extern "C" fn am_monitor_new() -> *mut ActivityMonitor {
+    std::sync::Arc::into_raw(ActivityMonitor::new()) as *mut ActivityMonitor
+}
+
+// Stops monitoring on the handle if needed. The handle must not be used
+// afterwards.
+#[no_mangle]
+pub extern "C" fn am_monitor_free(monitor: *mut ActivityMonitor) {
+    if !monitor.is_null() {
+        drop(unsafe { std::sync::Arc::from_raw(monitor as *const ActivityMonitor) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_start_monitoring(monitor: *mut ActivityMonitor) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.start())
+}
+
+#[no_mangle]
+pub extern "C" fn am_stop_monitoring(monitor: *mut ActivityMonitor) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.stop())
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_keyboard_count(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| monitor.keyboard_count())
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_mouse_count(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| monitor.mouse_count())
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_mouse_counts(monitor: *const ActivityMonitor, counts: *mut MouseCounts) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| write_mouse_counts(counts, monitor.mouse_counts()))
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_click_count(monitor: *const ActivityMonitor, button: u32) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| click_count(&monitor.mouse_counts(), button))
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_wheel_ticks(monitor: *const ActivityMonitor, axis: u32) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| wheel_ticks(&monitor.mouse_counts(), axis))
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_mouse_move_count(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| monitor.mouse_counts().moves)
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_mouse_drag_count(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| monitor.mouse_counts().drags)
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_pointer_travel(monitor: *const ActivityMonitor, travel: *mut PointerTravel) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| write_pointer_travel(travel, monitor.pointer_travel()))
+}
+
+#[no_mangle]
+pub extern "C" fn am_enable_heatmap(monitor: *mut ActivityMonitor, cell_size: u32) {
+    if let Some(monitor) = monitor_from_ffi(monitor) {
+        monitor.enable_heatmap(cell_size);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_disable_heatmap(monitor: *mut ActivityMonitor) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.disable_heatmap())
+}
+
+#[no_mangle]
+pub extern "C" fn am_save_heatmap(monitor: *const ActivityMonitor, path_ptr: *const u8, path_len: usize, layer: u32, format: u32) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| save_heatmap_on(monitor, path_ptr, path_len, layer, format))
+}
+
+// The OS hooks are shared, so these match their handle-less versions
+#[no_mangle]
+pub extern "C" fn am_counters_available(_monitor: *const ActivityMonitor) -> bool {
+    counters_available()
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_capabilities(_monitor: *const ActivityMonitor) -> u32 {
+    get_capabilities()
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_session_state(_monitor: *const ActivityMonitor) -> u32 {
+    get_session_state()
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_idle_time(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| monitor.idle_time())
+}
+
+#[no_mangle]
+pub extern "C" fn am_set_idle_threshold(monitor: *mut ActivityMonitor, seconds: u64) {
+    if let Some(monitor) = monitor_from_ffi(monitor) {
+        monitor.set_idle_threshold(seconds);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_idle_threshold(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| monitor.idle_threshold())
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_activity_state(monitor: *const ActivityMonitor) -> u32 {
+    monitor_from_ffi(monitor).map_or(ACTIVITY_STATE_ACTIVE, |monitor| {
+        activity_state_to_ffi(monitor.activity_state())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_idle_since(monitor: *const ActivityMonitor) -> u64 {
+    monitor_from_ffi(monitor).map_or(0, |monitor| idle_since_to_ffi(monitor.activity_state()))
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_activity_snapshot(monitor: *const ActivityMonitor, snapshot: *mut ActivitySnapshot) -> bool {
+    match monitor_from_ffi(monitor) {
+        Some(monitor) => write_snapshot(snapshot, monitor.snapshot()),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_take_snapshot_and_reset(monitor: *mut ActivityMonitor, snapshot: *mut ActivitySnapshot) -> bool {
+    match monitor_from_ffi(monitor) {
+        Some(monitor) if !snapshot.is_null() => write_snapshot(snapshot, monitor.take_snapshot_and_reset()),
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_reset_counters(monitor: *mut ActivityMonitor) {
+    if let Some(monitor) = monitor_from_ffi(monitor) {
+        monitor.reset_counters();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_save_activity_log(monitor: *mut ActivityMonitor, path_ptr: *const u8, path_len: usize) -> bool {
+    match (monitor_from_ffi(monitor), path_from_ffi(path_ptr, path_len)) {
+        (Some(monitor), Some(path)) => monitor.save_activity_log(path),
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_save_activity_log_as(monitor: *mut ActivityMonitor, path_ptr: *const u8, path_len: usize, format: u32) -> bool {
+    match (monitor_from_ffi(monitor), path_from_ffi(path_ptr, path_len), LogFormat::from_ffi(format)) {
+        (Some(monitor), Some(path), Some(format)) => monitor.save_activity_log_as(path, format),
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_set_log_rotation(monitor: *mut ActivityMonitor, mode: u32, max_bytes: u64, keep_files: u32, max_age_secs: u64, compress: bool) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| {
+        set_log_rotation_on(monitor, mode, max_bytes, keep_files, max_age_secs, compress)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn am_set_log_sync(monitor: *mut ActivityMonitor, sync: u32) -> bool {
+    match (monitor_from_ffi(monitor), LogSync::from_ffi(sync)) {
+        (Some(monitor), Some(sync)) => {
+            monitor.set_log_sync(sync);
+            true
+        },
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_register_activity_callback(monitor: *mut ActivityMonitor, callback: Option<ActivityCallback>, user_data: *mut c_void) -> u64 {
+    match (monitor_from_ffi(monitor), callback) {
+        (Some(monitor), Some(callback)) => monitor.register_ffi_callback(callback, user_data),
+        _ => 0,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn am_unregister_activity_callback(monitor: *mut ActivityMonitor, id: u64) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.unregister_callback(id))
+}
+
+#[no_mangle]
+pub extern "C" fn am_start_autosave(monitor: *mut ActivityMonitor, path_ptr: *const u8, path_len: usize, interval_secs: u64, format: u32) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| start_autosave_on(monitor, path_ptr, path_len, interval_secs, format))
+}
+
+#[no_mangle]
+pub extern "C" fn am_stop_autosave(monitor: *mut ActivityMonitor) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.stop_autosave())
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_autosave_error(monitor: *const ActivityMonitor, buffer: *mut u8, capacity: usize) -> usize {
+    monitor_from_ffi(monitor).map_or(0, |monitor| autosave_error_to_ffi(monitor, buffer, capacity))
+}
+
+#[no_mangle]
+pub extern "C" fn am_load_config(monitor: *mut ActivityMonitor, path_ptr: *const u8, path_len: usize) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| load_config_on(monitor, path_ptr, path_len))
+}
+
+#[no_mangle]
+pub extern "C" fn am_stop_watching_config(monitor: *mut ActivityMonitor) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.stop_watching_config())
+}
+
+#[no_mangle]
+pub extern "C" fn am_get_config_error(monitor: *const ActivityMonitor, buffer: *mut u8, capacity: usize) -> usize {
+    monitor_from_ffi(monitor).map_or(0, |monitor| message_to_ffi(monitor.config_error(), buffer, capacity))
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn am_open_activity_store(monitor: *mut ActivityMonitor, path_ptr: *const u8, path_len: usize) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| open_store(monitor, path_ptr, path_len))
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn am_close_activity_store(monitor: *mut ActivityMonitor) {
+    if let Some(monitor) = monitor_from_ffi(monitor) {
+        monitor.detach_store();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn am_save_activity_store(monitor: *mut ActivityMonitor) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| monitor.save_to_store())
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn am_query_activity_totals(monitor: *const ActivityMonitor, from: u64, to: u64, totals: *mut ActivityTotals) -> bool {
+    monitor_from_ffi(monitor).is_some_and(|monitor| query_totals(monitor, from, to, totals))
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn am_query_daily_totals(monitor: *const ActivityMonitor, from: u64, to: u64, days: *mut DailyTotals, capacity: usize) -> usize {
+    monitor_from_ffi(monitor).map_or(0, |monitor| query_days(monitor, from, to, days, capacity))
+}
+
+#[cfg(feature = "sqlite")]
+#[no_mangle]
+pub extern "C" fn am_query_longest_idle_gaps(monitor: *const ActivityMonitor, from: u64, to: u64, gaps: *mut IdlePeriod, capacity: usize) -> usize {
+    monitor_from_ffi(monitor).map_or(0, |monitor| query_gaps(monitor, from, to, gaps, capacity))
+}
+
+// Internal functions for the event dispatcher to call
 pub(crate) fn increment_keyboard() {
-    KEYBOARD_COUNT.fetch_add(1, Ordering::SeqCst);
+    TOTAL_KEYBOARD_EVENTS.fetch_add(1, Ordering::Relaxed);
+    monitor::increment_keyboard();
 }
 
-pub(crate) fn increment_mouse() {
-    MOUSE_COUNT.fetch_add(1, Ordering::SeqCst);
+pub(crate) fn increment_mouse(counts: MouseCounts, travel: PointerTravel, position: Option<(f64, f64)>) {
+    TOTAL_MOUSE_EVENTS.fetch_add(1, Ordering::Relaxed);
+    monitor::increment_mouse(counts, travel, position);
 }
 
 // Update the timestamp for genuine user activity
 pub(crate) fn update_genuine_activity_time(is_genuine: bool) {
     if is_genuine {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or(Duration::from_secs(0))
-            .as_secs();
-        
-        LAST_GENUINE_ACTIVITY.store(now, Ordering::SeqCst);
-    }
-}
\ No newline at end of file
+        monitor::record_genuine_activity_at(clock::now_secs());
+    }
+}
+
+// Record genuine activity that happened at an earlier time (seconds since
+// the epoch), without moving the timestamp backwards
+pub(crate) fn record_genuine_activity_at(timestamp: u64) {
+    monitor::record_genuine_activity_at(timestamp);
+}
+
+pub(crate) fn set_session_locked(locked: bool) {
+    SESSION_LOCKED.store(locked, Ordering::SeqCst);
+}
+
+// Shift the last activity forward by the time spent asleep on resume, so
+// idle time only counts time the machine was actually running
+pub(crate) fn set_system_sleeping(sleeping: bool) {
+    let now = clock::now_secs();
+    
+    if sleeping {
+        SLEEP_STARTED.store(now, Ordering::SeqCst);
+        SYSTEM_SLEEPING.store(true, Ordering::SeqCst);
+    } else if SYSTEM_SLEEPING.swap(false, Ordering::SeqCst) {
+        let slept = now.saturating_sub(SLEEP_STARTED.load(Ordering::SeqCst));
+        monitor::shift_genuine_activity(slept);
+    }
+}
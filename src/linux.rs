@@ -0,0 +1,576 @@
+// activity_monitor/src/linux.rs
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent, MouseButtons, PointerEvent};
+
+const INPUT_DIR: &str = "/dev/input";
+
+// Event types and codes from linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const EV_MAX: usize = 0x1f;
+const KEY_MAX: usize = 0x2ff;
+const REL_MAX: usize = 0x0f;
+
+const SYN_REPORT: u16 = 0x00;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_MT_POSITION_X: u16 = 0x35;
+const ABS_MT_POSITION_Y: u16 = 0x36;
+const KEY_ESC: usize = 1;
+const KEY_A: usize = 30;
+const KEY_SPACE: usize = 57;
+// Button codes; everything else below KEY_MAX is a key
+const BTN_MISC: u16 = 0x100;
+const BTN_LAST: u16 = 0x15f;
+const BTN_TRIGGER_HAPPY: u16 = 0x2c0;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+// Side, extra, forward, back and task
+const BTN_EXTRA_FIRST: u16 = 0x113;
+const BTN_EXTRA_LAST: u16 = 0x117;
+const BTN_TOUCH: usize = 0x14a;
+
+// Key event values
+const KEY_RELEASED: i32 = 0;
+const KEY_PRESSED: i32 = 1;
+
+// Mirrors struct input_event from linux/input.h
+#[repr(C)]
+struct KernelEvent {
+    time: libc::timeval,
+    type_: u16,
+    code: u16,
+    value: i32,
+}
+
+// What a device can report, derived from its capability bitmaps
+#[derive(Clone, Copy, PartialEq)]
+enum DeviceKind {
+    Keyboard,
+    Pointer,
+    Both,
+}
+
+struct Device {
+    path: PathBuf,
+    file: fs::File,
+    kind: DeviceKind,
+    // Set when pointer data arrived since the last SYN_REPORT
+    pointer_pending: bool,
+    // What that data amounts to so far
+    pointer: PointerEvent,
+}
+
+// EVIOCGBIT(ev, len) = _IOC(_IOC_READ, 'E', 0x20 + ev, len)
+fn eviocgbit(ev: usize, len: usize) -> libc::c_ulong {
+    ((2 << 30) | (len << 16) | ((b'E' as usize) << 8) | (0x20 + ev)) as libc::c_ulong
+}
+
+fn read_bits(file: &fs::File, ev: usize, max: usize) -> Option<Vec<u8>> {
+    let mut bits = vec![0u8; max / 8 + 1];
+    let ret = unsafe {
+        libc::ioctl(file.as_raw_fd(), eviocgbit(ev, bits.len()), bits.as_mut_ptr())
+    };
+    if ret < 0 {
+        return None;
+    }
+    Some(bits)
+}
+
+fn test_bit(bits: &[u8], bit: usize) -> bool {
+    bits.get(bit / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+}
+
+// Classify a device by what it reports; anything that is neither a
+// keyboard nor a pointer (power buttons, lid switches, ...) is ignored
+fn classify_device(file: &fs::File) -> Option<DeviceKind> {
+    let ev_bits = read_bits(file, 0, EV_MAX)?;
+
+    let mut is_keyboard = false;
+    let mut is_pointer = false;
+
+    if test_bit(&ev_bits, EV_KEY as usize) {
+        let key_bits = read_bits(file, EV_KEY as usize, KEY_MAX)?;
+        is_keyboard = test_bit(&key_bits, KEY_ESC) &&
+                      test_bit(&key_bits, KEY_A) &&
+                      test_bit(&key_bits, KEY_SPACE);
+        is_pointer = test_bit(&key_bits, BTN_LEFT as usize) || test_bit(&key_bits, BTN_TOUCH);
+    }
+
+    if test_bit(&ev_bits, EV_REL as usize) {
+        let rel_bits = read_bits(file, EV_REL as usize, REL_MAX)?;
+        is_pointer |= test_bit(&rel_bits, REL_X as usize) && test_bit(&rel_bits, REL_Y as usize);
+    }
+
+    match (is_keyboard, is_pointer) {
+        (true, true) => Some(DeviceKind::Both),
+        (true, false) => Some(DeviceKind::Keyboard),
+        (false, true) => Some(DeviceKind::Pointer),
+        (false, false) => None,
+    }
+}
+
+fn open_device(path: &Path) -> Option<Device> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+        .open(path)
+        .ok()?;
+
+    let kind = classify_device(&file)?;
+
+    Some(Device {
+        path: path.to_path_buf(),
+        file,
+        kind,
+        pointer_pending: false,
+        pointer: PointerEvent::default(),
+    })
+}
+
+// Open any event device we are not watching yet. Devices we cannot open
+// (usually because the user is not in the `input` group) are skipped.
+fn scan_devices(devices: &mut Vec<Device>) {
+    let entries = match fs::read_dir(INPUT_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_event_node = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("event"));
+
+        if !is_event_node || devices.iter().any(|device| device.path == path) {
+            continue;
+        }
+
+        if let Some(device) = open_device(&path) {
+            devices.push(device);
+        }
+    }
+}
+
+// Keys run past the buttons, from KEY_OK up to just below the extra
+// joystick triggers
+fn is_key(code: u16) -> bool {
+    !(BTN_MISC..=BTN_LAST).contains(&code) && code < BTN_TRIGGER_HAPPY
+}
+
+fn handle_event(device: &mut Device, event: &KernelEvent) {
+    match event.type_ {
+        EV_KEY if is_key(event.code) => {
+            if device.kind == DeviceKind::Pointer {
+                return;
+            }
+
+            // Auto-repeat (value 2) is treated like a fresh key down and
+            // filtered by the pressed-key timeout, as on the other platforms
+            dispatch(InputEvent::Key {
+                code: event.code as u32,
+                pressed: event.value != KEY_RELEASED,
+            });
+        }
+        EV_KEY | EV_REL | EV_ABS if device.kind != DeviceKind::Keyboard => {
+            device.pointer_pending = true;
+            add_pointer_data(&mut device.pointer, event);
+        }
+        // A pointer report is split into several axis/button events
+        // followed by SYN_REPORT; count the whole frame once
+        EV_SYN if event.code == SYN_REPORT && device.pointer_pending => {
+            device.pointer_pending = false;
+            dispatch(InputEvent::Pointer(std::mem::take(&mut device.pointer)));
+        }
+        _ => {}
+    }
+}
+
+// Fold one axis or button event into the frame being collected. Touch
+// contacts and tool changes count as pointer activity of no particular kind.
+fn add_pointer_data(pointer: &mut PointerEvent, event: &KernelEvent) {
+    match (event.type_, event.code) {
+        (EV_KEY, code) => {
+            let button = match code {
+                BTN_LEFT => MouseButtons::LEFT,
+                BTN_RIGHT => MouseButtons::RIGHT,
+                BTN_MIDDLE => MouseButtons::MIDDLE,
+                BTN_EXTRA_FIRST..=BTN_EXTRA_LAST => MouseButtons::EXTRA,
+                _ => return,
+            };
+
+            match event.value {
+                KEY_RELEASED => pointer.released = pointer.released | button,
+                KEY_PRESSED => pointer.pressed = pointer.pressed | button,
+                _ => {}
+            }
+        }
+        (EV_REL, REL_WHEEL) => pointer.wheel_vertical += event.value.unsigned_abs(),
+        (EV_REL, REL_HWHEEL) => pointer.wheel_horizontal += event.value.unsigned_abs(),
+        // Relative motion is in device counts, before the display server's
+        // acceleration; they stand in for pixels. Touchpad positions are in
+        // pad units and do not count towards travel.
+        (EV_REL, REL_X) => {
+            pointer.moved = true;
+            pointer.delta.0 += event.value as f64;
+        }
+        (EV_REL, REL_Y) => {
+            pointer.moved = true;
+            pointer.delta.1 += event.value as f64;
+        }
+        (EV_ABS, ABS_X | ABS_Y | ABS_MT_POSITION_X | ABS_MT_POSITION_Y) => pointer.moved = true,
+        _ => {}
+    }
+}
+
+// Drain all pending events from a device. Returns false once the device
+// is gone (unplugged) and should be dropped.
+fn read_device(device: &mut Device) -> bool {
+    let event_size = std::mem::size_of::<KernelEvent>();
+    let mut buffer: [KernelEvent; 64] = unsafe { std::mem::zeroed() };
+
+    loop {
+        let ret = unsafe {
+            libc::read(
+                device.file.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                event_size * buffer.len(),
+            )
+        };
+
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            return matches!(err.raw_os_error(), Some(libc::EAGAIN) | Some(libc::EINTR));
+        }
+
+        if ret == 0 {
+            return false;
+        }
+
+        let count = ret as usize / event_size;
+        for event in &buffer[..count] {
+            handle_event(device, event);
+        }
+
+        if count < buffer.len() {
+            return true;
+        }
+    }
+}
+
+fn run_event_loop(running: Arc<AtomicBool>, mut devices: Vec<Device>) {
+    let mut last_scan = Instant::now();
+    let scan_interval = Duration::from_secs(2);
+
+    while running.load(Ordering::SeqCst) {
+        let mut fds: Vec<libc::pollfd> = devices
+            .iter()
+            .map(|device| libc::pollfd {
+                fd: device.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+
+        if fds.is_empty() {
+            thread::sleep(crate::source::poll_interval());
+        } else {
+            // Wait up to one poll interval so we notice a stop request promptly
+            let timeout = crate::source::poll_interval().as_millis() as libc::c_int;
+            let ready = unsafe {
+                libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout)
+            };
+
+            if ready > 0 {
+                let mut index = 0;
+                devices.retain_mut(|device| {
+                    let revents = fds[index].revents;
+                    index += 1;
+
+                    if revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0 {
+                        return false;
+                    }
+
+                    revents & libc::POLLIN == 0 || read_device(device)
+                });
+            }
+        }
+
+        // Periodically pick up hotplugged devices
+        let now = Instant::now();
+        if now.duration_since(last_scan) > scan_interval {
+            scan_devices(&mut devices);
+            last_scan = now;
+        }
+    }
+}
+
+// Reads keyboards and pointers directly from /dev/input. Requires read
+// access to the event nodes, usually membership in the `input` group.
+pub struct EvdevSource {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl EvdevSource {
+    pub fn new() -> EvdevSource {
+        EvdevSource {
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl Default for EvdevSource {
+    fn default() -> EvdevSource {
+        EvdevSource::new()
+    }
+}
+
+impl ActivitySource for EvdevSource {
+    fn name(&self) -> &str {
+        "evdev"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::KEYBOARD | Capabilities::MOUSE
+    }
+
+    // Fails if no keyboard or pointer device could be opened
+    fn start(&mut self) -> bool {
+        if self.running.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        let mut devices = Vec::new();
+        scan_devices(&mut devices);
+        if devices.is_empty() {
+            return false;
+        }
+
+        self.running.store(true, Ordering::SeqCst);
+
+        let running = self.running.clone();
+        self.thread = Some(thread::spawn(move || run_event_loop(running, devices)));
+
+        true
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // Wait for the monitoring thread to close its devices
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::monitor::ActivityMonitor;
+    use crate::source::register_source;
+
+    // From linux/uinput.h
+    const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+    const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+    const KEY_BRIGHTNESSUP: u16 = 0x225;
+
+    // _IOW('U', nr, size)
+    fn uinput_write(nr: usize, size: usize) -> libc::c_ulong {
+        ((1 << 30) | (size << 16) | ((b'U' as usize) << 8) | nr) as libc::c_ulong
+    }
+
+    // UI_GET_SYSNAME(len) = _IOC(_IOC_READ, 'U', 44, len)
+    fn ui_get_sysname(len: usize) -> libc::c_ulong {
+        ((2 << 30) | (len << 16) | ((b'U' as usize) << 8) | 44) as libc::c_ulong
+    }
+
+    // Mirrors struct uinput_setup
+    #[repr(C)]
+    struct UinputSetup {
+        bustype: u16,
+        vendor: u16,
+        product: u16,
+        version: u16,
+        name: [u8; 80],
+        ff_effects_max: u32,
+    }
+
+    // A virtual keyboard with a wheel mouse, removed when dropped
+    struct VirtualDevice {
+        file: fs::File,
+        node: PathBuf,
+    }
+
+    impl VirtualDevice {
+        fn create() -> Option<VirtualDevice> {
+            let file = fs::OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open("/dev/uinput").ok()?;
+            let fd = file.as_raw_fd();
+            let set = |nr: usize, bit: u16| unsafe { libc::ioctl(fd, uinput_write(nr, 4), bit as libc::c_int) } >= 0;
+            let supported = [EV_KEY, EV_REL, EV_SYN].iter().all(|&ev| set(100, ev))
+                && [KEY_ESC as u16, KEY_A as u16, KEY_SPACE as u16, KEY_BRIGHTNESSUP, BTN_LEFT].iter().all(|&key| set(101, key))
+                && [REL_X, REL_Y, REL_WHEEL].iter().all(|&rel| set(102, rel));
+            if !supported {
+                return None;
+            }
+
+            let mut setup = UinputSetup {
+                bustype: 0x06, // BUS_VIRTUAL
+                vendor: 0x1234,
+                product: 0x5678,
+                version: 1,
+                name: [0; 80],
+                ff_effects_max: 0,
+            };
+            setup.name[..22].copy_from_slice(b"activity-monitor-test\0");
+            let size = std::mem::size_of::<UinputSetup>();
+            if unsafe { libc::ioctl(fd, uinput_write(3, size), &setup) } < 0
+                || unsafe { libc::ioctl(fd, UI_DEV_CREATE) } < 0
+            {
+                return None;
+            }
+            let mut device = VirtualDevice { file, node: PathBuf::new() };
+
+            let mut sysname = [0u8; 64];
+            if unsafe { libc::ioctl(fd, ui_get_sysname(sysname.len()), sysname.as_mut_ptr()) } < 0 {
+                return None;
+            }
+            let sysname = std::ffi::CStr::from_bytes_until_nul(&sysname).ok()?.to_str().ok()?.to_string();
+
+            // The event node shows up shortly after the device
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while Instant::now() < deadline {
+                let event = fs::read_dir(Path::new("/sys/class/input").join(&sysname))
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .find(|name| name.starts_with("event"));
+                if let Some(node) = event.map(|name| Path::new(INPUT_DIR).join(name)).filter(|node| node.exists()) {
+                    device.node = node;
+                    return Some(device);
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            None
+        }
+
+        fn emit(&mut self, events: &[(u16, u16, i32)]) {
+            for &(type_, code, value) in events.iter().chain(&[(EV_SYN, SYN_REPORT, 0)]) {
+                let event = KernelEvent {
+                    time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+                    type_,
+                    code,
+                    value,
+                };
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(&event as *const KernelEvent as *const u8, std::mem::size_of::<KernelEvent>())
+                };
+                self.file.write_all(bytes).unwrap();
+            }
+        }
+    }
+
+    impl Drop for VirtualDevice {
+        fn drop(&mut self) {
+            unsafe { libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY) };
+        }
+    }
+
+    // Whether this process has `node` open, that is the source watches it
+    fn is_watched(node: &Path) -> bool {
+        fs::read_dir("/proc/self/fd")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .any(|entry| fs::read_link(entry.path()).is_ok_and(|target| target == node))
+    }
+
+    fn wait_for(timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !done() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn tells_keys_from_buttons() {
+        assert!(is_key(KEY_A as u16));
+        assert!(is_key(KEY_BRIGHTNESSUP));
+        assert!(!is_key(BTN_MISC));
+        assert!(!is_key(BTN_LEFT));
+        assert!(!is_key(BTN_TOUCH as u16));
+        assert!(!is_key(BTN_TRIGGER_HAPPY));
+    }
+
+    // Needs write access to /dev/uinput and read access to the new event
+    // nodes, so in practice root; skipped otherwise
+    #[test]
+    fn reads_uinput_devices() {
+        let _isolated = crate::testing::isolate();
+        let Some(mut uinput) = VirtualDevice::create() else {
+            eprintln!("skipped: cannot create a device through /dev/uinput");
+            return;
+        };
+        let Some(device) = open_device(&uinput.node) else {
+            eprintln!("skipped: cannot open {}", uinput.node.display());
+            return;
+        };
+        assert!(device.kind == DeviceKind::Both);
+        drop(device);
+
+        register_source(Box::new(EvdevSource::new()));
+        let monitor = ActivityMonitor::new();
+        assert!(monitor.start());
+        assert!(is_watched(&uinput.node));
+
+        uinput.emit(&[(EV_KEY, KEY_A as u16, KEY_PRESSED)]);
+        uinput.emit(&[(EV_KEY, KEY_A as u16, KEY_RELEASED)]);
+        uinput.emit(&[(EV_KEY, KEY_BRIGHTNESSUP, KEY_PRESSED)]);
+        uinput.emit(&[(EV_KEY, KEY_BRIGHTNESSUP, KEY_RELEASED)]);
+        uinput.emit(&[(EV_KEY, BTN_LEFT, KEY_PRESSED), (EV_REL, REL_X, 3), (EV_REL, REL_Y, -4)]);
+        uinput.emit(&[(EV_KEY, BTN_LEFT, KEY_RELEASED)]);
+        uinput.emit(&[(EV_REL, REL_WHEEL, -2)]);
+        wait_for(Duration::from_secs(2), || monitor.snapshot().mouse_count >= 3);
+
+        let snapshot = monitor.snapshot();
+        assert_eq!(snapshot.keyboard_count, 2);
+        assert_eq!(snapshot.mouse_count, 3);
+        let mouse = monitor.mouse_counts();
+        assert_eq!(mouse.left_clicks, 1);
+        assert_eq!(mouse.wheel_vertical, 2);
+        assert_eq!(mouse.moves, 1);
+        assert_eq!(mouse.drags, 1);
+
+        // A device plugged in later is picked up by the next scan
+        let mut hotplugged = VirtualDevice::create().expect("a second uinput device");
+        assert!(wait_for(Duration::from_secs(5), || is_watched(&hotplugged.node)));
+        hotplugged.emit(&[(EV_KEY, KEY_SPACE as u16, KEY_PRESSED)]);
+        hotplugged.emit(&[(EV_KEY, KEY_SPACE as u16, KEY_RELEASED)]);
+        assert!(wait_for(Duration::from_secs(2), || monitor.snapshot().keyboard_count == 3));
+
+        monitor.stop();
+    }
+}
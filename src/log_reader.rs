@@ -0,0 +1,439 @@
+// activity_monitor/src/log_reader.rs
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use flate2::read::GzDecoder;
+use crate::activity_log::{Counts, LogFormat, LogRecord, CSV_HEADER};
+use crate::monitor::MouseCounts;
+use crate::rotation;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    // The first line is neither the CSV header nor a JSON object
+    UnrecognizedHeader(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Io(error) => write!(f, "{error}"),
+            ReadError::UnrecognizedHeader(header) => write!(f, "unrecognized log header: {header:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
+
+// Yields the records of one log written by save_activity_log_as(), in
+// either format and gzipped or not. Rows that cannot be parsed, including
+// a last row torn by a crash, are skipped and counted.
+//
+// CSV rows only carry the end of their interval, the counts and the idle
+// time. They come back with schema_version 0, an empty host, session and
+// states, and the previous row's time as interval_start (their own time
+// for the first row).
+pub struct LogReader {
+    lines: Box<dyn BufRead>,
+    format: Option<LogFormat>,
+    // First data line when it had to be read to detect the format
+    pending: Option<Vec<u8>>,
+    previous_end: Option<u64>,
+    skipped: u64,
+}
+
+impl LogReader {
+    pub fn open(path: &Path) -> Result<LogReader, ReadError> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        if file.fill_buf()?.starts_with(GZIP_MAGIC) {
+            LogReader::new(BufReader::new(GzDecoder::new(file)))
+        } else {
+            LogReader::new(file)
+        }
+    }
+
+    // Read a log from any source; checks the header straight away
+    pub fn new<R: BufRead + 'static>(reader: R) -> Result<LogReader, ReadError> {
+        let mut log_reader = LogReader {
+            lines: Box::new(reader),
+            format: None,
+            pending: None,
+            previous_end: None,
+            skipped: 0,
+        };
+
+        let first = match log_reader.next_line()? {
+            Some(first) => first,
+            None => return Ok(log_reader), // Empty log
+        };
+
+        if first == trim_line(CSV_HEADER) {
+            log_reader.format = Some(LogFormat::Csv);
+        } else if first.starts_with(b"{") {
+            log_reader.format = Some(LogFormat::JsonLines);
+            log_reader.pending = Some(first);
+        } else {
+            return Err(ReadError::UnrecognizedHeader(String::from_utf8_lossy(&first).into_owned()));
+        }
+
+        Ok(log_reader)
+    }
+
+    // None for an empty log
+    pub fn format(&self) -> Option<LogFormat> {
+        self.format
+    }
+
+    // Rows skipped so far because they could not be parsed
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    // The next complete, non-empty line without its line ending. A last
+    // line without a newline was cut short and counts as skipped.
+    fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let mut line = Vec::new();
+            if self.lines.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+
+            if !line.ends_with(b"\n") {
+                self.skipped += 1;
+                return Ok(None);
+            }
+
+            let line = trim_line(&line);
+            if !line.is_empty() {
+                return Ok(Some(line.to_vec()));
+            }
+        }
+    }
+
+    fn parse(&mut self, line: &[u8]) -> Option<LogRecord> {
+        match self.format? {
+            LogFormat::Csv => {
+                let record = parse_csv_row(std::str::from_utf8(line).ok()?, self.previous_end)?;
+                self.previous_end = Some(record.interval_end);
+                Some(record)
+            },
+            LogFormat::JsonLines => serde_json::from_slice(line).ok(),
+        }
+    }
+}
+
+impl Iterator for LogReader {
+    type Item = LogRecord;
+
+    // A read error, such as a gzip stream broken partway, ends the log and
+    // counts as one skipped row
+    fn next(&mut self) -> Option<LogRecord> {
+        loop {
+            let line = match self.pending.take() {
+                Some(line) => line,
+                None => match self.next_line() {
+                    Ok(line) => line?,
+                    Err(_) => {
+                        self.skipped += 1;
+                        return None;
+                    },
+                },
+            };
+
+            match self.parse(&line) {
+                Some(record) => return Some(record),
+                None => self.skipped += 1,
+            }
+        }
+    }
+}
+
+// timestamp,keyboard_count,mouse_count,idle_time_seconds, with both counts
+// empty when they were not available
+fn parse_csv_row(row: &str, previous_end: Option<u64>) -> Option<LogRecord> {
+    let fields: Vec<&str> = row.split(',').collect();
+    let [timestamp, keyboard, mouse, idle_time] = fields[..] else {
+        return None;
+    };
+
+    let interval_end: u64 = timestamp.parse().ok()?;
+    let counts = match (keyboard, mouse) {
+        ("", "") => None,
+        (keyboard, mouse) => Some(Counts {
+            keyboard: keyboard.parse().ok()?,
+            mouse: mouse.parse().ok()?,
+            ..Counts::default()
+        }),
+    };
+
+    Some(LogRecord {
+        schema_version: 0,
+        interval_start: previous_end.filter(|&end| end <= interval_end).unwrap_or(interval_end),
+        interval_end,
+        host: String::new(),
+        session_id: String::new(),
+        counts,
+        idle_time_seconds: idle_time.parse().ok()?,
+        idle_state: String::new(),
+        session_state: String::new(),
+    })
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+// Totals over the records of a log that ended within a time range. Times
+// are in seconds (since the epoch for the timestamps).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LogSummary {
+    pub records: u64,
+    // Rows that could not be parsed and segments that could not be read,
+    // in the whole log
+    pub skipped: u64,
+    // Records without counts are left out of these
+    pub keyboard_count: u64,
+    pub mouse_count: u64,
+    // Earliest interval_start and latest interval_end, or 0 without records
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+    // Largest idle time logged
+    pub longest_idle_seconds: u64,
+    // Records logged while idle
+    pub idle_records: u64,
+    // From JSON Lines records; CSV logs and records logged before the
+    // breakdown add nothing
+    pub mouse: MouseCounts,
+}
+
+impl LogSummary {
+    pub fn add(&mut self, record: &LogRecord) {
+        if self.records == 0 {
+            self.first_timestamp = record.interval_start;
+        }
+        self.records += 1;
+
+        if let Some(counts) = record.counts {
+            self.keyboard_count += counts.keyboard;
+            self.mouse_count += counts.mouse;
+            self.mouse.add(&counts.mouse_detail);
+        }
+
+        self.first_timestamp = self.first_timestamp.min(record.interval_start);
+        self.last_timestamp = self.last_timestamp.max(record.interval_end);
+        self.longest_idle_seconds = self.longest_idle_seconds.max(record.idle_time_seconds);
+        if record.idle_state == "idle" {
+            self.idle_records += 1;
+        }
+    }
+}
+
+// The records of the log at `path` and its rotated segments, oldest first,
+// whose interval ended in [from, to) (seconds since the epoch). Returns
+// them with the number of rows skipped. A segment that cannot be read,
+// such as one with an unknown header or a broken gzip stream, counts as
+// one skipped row; the read only fails when no segment could be opened.
+pub fn read_log(path: &Path, from: u64, to: u64) -> Result<(Vec<LogRecord>, u64), ReadError> {
+    let mut records = Vec::new();
+    let skipped = for_each_record(path, |record| {
+        if in_range(&record, from, to) {
+            records.push(record);
+        }
+    })?;
+
+    Ok((records, skipped))
+}
+
+// Like read_log(), totalled
+pub fn summarize_log(path: &Path, from: u64, to: u64) -> Result<LogSummary, ReadError> {
+    let mut summary = LogSummary::default();
+    summary.skipped = for_each_record(path, |record| {
+        if in_range(&record, from, to) {
+            summary.add(&record);
+        }
+    })?;
+
+    Ok(summary)
+}
+
+// Every record of every segment in order, returning the rows skipped. The
+// first CSV row of a segment starts where the previous segment ended.
+fn for_each_record(path: &Path, mut visit: impl FnMut(LogRecord)) -> Result<u64, ReadError> {
+    let mut skipped = 0;
+    let mut previous_end = None;
+    let mut opened = false;
+    let mut last_error = None;
+
+    for segment in rotation::log_segments(path) {
+        let mut reader = match LogReader::open(&segment) {
+            Ok(reader) => reader,
+            Err(error) => {
+                skipped += 1;
+                last_error = Some(error);
+                continue;
+            },
+        };
+        opened = true;
+
+        reader.previous_end = previous_end;
+        for record in reader.by_ref() {
+            previous_end = Some(record.interval_end);
+            visit(record);
+        }
+        skipped += reader.skipped();
+    }
+
+    match last_error {
+        Some(error) if !opened => Err(error),
+        _ => Ok(skipped),
+    }
+}
+
+fn in_range(record: &LogRecord, from: u64, to: u64) -> bool {
+    record.interval_end >= from && record.interval_end < to
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn reader(contents: &'static str) -> Result<LogReader, ReadError> {
+        LogReader::new(contents.as_bytes())
+    }
+
+    #[test]
+    fn reads_csv_rows() {
+        let mut log = reader("timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n160,,,70\n").unwrap();
+        assert_eq!(log.format(), Some(LogFormat::Csv));
+
+        let first = log.next().unwrap();
+        assert_eq!((first.interval_start, first.interval_end), (100, 100));
+        assert_eq!(first.counts, Some(Counts { keyboard: 3, mouse: 4, ..Counts::default() }));
+
+        let second = log.next().unwrap();
+        assert_eq!((second.interval_start, second.interval_end), (100, 160));
+        assert_eq!(second.counts, None);
+        assert_eq!(second.idle_time_seconds, 70);
+
+        assert!(log.next().is_none());
+        assert_eq!(log.skipped(), 0);
+    }
+
+    #[test]
+    fn summary_adds_the_json_mouse_breakdown() {
+        let log = reader(concat!(
+            r#"{"schema_version":1,"interval_start":40,"interval_end":100,"host":"h","session_id":"s","counts":{"keyboard":3,"mouse":40,"mouse_detail":{"left_clicks":2,"right_clicks":1,"middle_clicks":0,"extra_clicks":0,"wheel_vertical":6,"wheel_horizontal":0,"moves":30,"drags":1}},"idle_time_seconds":5,"idle_state":"active","session_state":"active"}"#,
+            "\n",
+            r#"{"schema_version":1,"interval_start":100,"interval_end":160,"host":"h","session_id":"s","counts":{"keyboard":1,"mouse":2},"idle_time_seconds":0,"idle_state":"active","session_state":"active"}"#,
+            "\n",
+        ))
+        .unwrap();
+
+        let mut summary = LogSummary::default();
+        for record in log {
+            summary.add(&record);
+        }
+
+        assert_eq!(summary.mouse_count, 42);
+        assert_eq!((summary.mouse.left_clicks, summary.mouse.right_clicks), (2, 1));
+        assert_eq!((summary.mouse.wheel_vertical, summary.mouse.moves, summary.mouse.drags), (6, 30, 1));
+    }
+
+    #[test]
+    fn skips_malformed_and_torn_rows() {
+        let mut log = reader("timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\nbad,row\n200,1,1,0\n300,7").unwrap();
+
+        let ends: Vec<u64> = log.by_ref().map(|record| record.interval_end).collect();
+        assert_eq!(ends, vec![100, 200]);
+        assert_eq!(log.skipped(), 2);
+    }
+
+    #[test]
+    fn reads_json_lines() {
+        let log = reader(concat!(
+            r#"{"schema_version":1,"interval_start":40,"interval_end":100,"host":"h","session_id":"s","counts":{"keyboard":3,"mouse":4},"idle_time_seconds":5,"idle_state":"idle","session_state":"locked","added_later":true}"#,
+            "\n",
+            r#"{"schema_version":1,"interval_start":100,"interval_end":160,"host":"h","session_id":"s","counts":null,"idle_time_seconds":65,"idle_state":"active","session_state":"active"}"#,
+            "\n",
+        ))
+        .unwrap();
+        assert_eq!(log.format(), Some(LogFormat::JsonLines));
+
+        let records: Vec<LogRecord> = log.collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].to_snapshot().activity_state, crate::ACTIVITY_STATE_IDLE);
+        assert_eq!(records[0].to_snapshot().session_state, crate::SESSION_STATE_LOCKED);
+        assert!(!records[1].to_snapshot().counters_available);
+    }
+
+    #[test]
+    fn rejects_unknown_header() {
+        assert!(matches!(reader("time,keys\n1,2\n"), Err(ReadError::UnrecognizedHeader(_))));
+        assert_eq!(reader("").unwrap().format(), None);
+    }
+
+    #[test]
+    fn summary_totals_records() {
+        let log = reader("timestamp,keyboard_count,mouse_count,idle_time_seconds\n100,3,4,5\n160,,,70\n220,1,2,0\n").unwrap();
+
+        let mut summary = LogSummary::default();
+        for record in log.filter(|record| in_range(record, 150, 300)) {
+            summary.add(&record);
+        }
+
+        assert_eq!(summary.records, 2);
+        assert_eq!((summary.keyboard_count, summary.mouse_count), (1, 2));
+        assert_eq!((summary.first_timestamp, summary.last_timestamp), (100, 220));
+        assert_eq!(summary.longest_idle_seconds, 70);
+    }
+
+    #[test]
+    fn skips_unreadable_segments() {
+        let dir = TempDir::new();
+        let header = "timestamp,keyboard_count,mouse_count,idle_time_seconds\n";
+        let segments = [
+            ("activity.2024-06-13.csv", format!("{header}100,1,1,0\n160,2,2,0\n").into_bytes()),
+            ("activity.2024-06-14.csv", b"time,keys\n200,1\n".to_vec()),
+            ("activity.2024-06-15.csv.gz", vec![0x1f, 0x8b, 0x00, 0x42]),
+            ("activity.csv", format!("{header}220,3,3,0\n").into_bytes()),
+        ];
+        let oldest = SystemTime::now() - Duration::from_secs(3600);
+        for (modified, (name, contents)) in (0..).map(|n| oldest + Duration::from_secs(n)).zip(&segments) {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        }
+        let path = dir.join("activity.csv");
+
+        let (records, skipped) = read_log(&path, 0, u64::MAX).unwrap();
+        let intervals: Vec<(u64, u64)> = records.iter().map(|record| (record.interval_start, record.interval_end)).collect();
+        assert_eq!(intervals, vec![(100, 100), (100, 160), (160, 220)]);
+        assert_eq!(skipped, 2);
+
+        let summary = summarize_log(&path, 200, u64::MAX).unwrap();
+        assert_eq!((summary.records, summary.skipped), (1, 2));
+        assert_eq!((summary.first_timestamp, summary.keyboard_count), (160, 3));
+
+        // Nothing readable at all is still an error
+        fs::write(&path, "time,keys\n").unwrap();
+        for (name, _) in &segments[..3] {
+            fs::remove_file(dir.join(name)).unwrap();
+        }
+        assert!(matches!(read_log(&path, 0, u64::MAX), Err(ReadError::UnrecognizedHeader(_))));
+    }
+}
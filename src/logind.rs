@@ -0,0 +1,339 @@
+// activity_monitor/src/logind.rs
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use zbus::blocking::proxy::Builder as ProxyBuilder;
+use zbus::blocking::{Connection, MessageIterator, Proxy};
+use zbus::message::Type as MessageType;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::MatchRule;
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent};
+
+const LOGIND_SERVICE: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+// Connect to this bus address instead of the system bus (used to run
+// against a private dbus-daemon with a mock logind)
+const BUS_ADDRESS_ENV: &str = "ACTIVITY_MONITOR_LOGIND_BUS";
+
+// Tracks the IdleHint edge so only a transition back to active counts as
+// genuine activity
+struct SessionWatcher<'a> {
+    session: Proxy<'a>,
+    idle_hint: bool,
+}
+
+impl SessionWatcher<'_> {
+    // Re-read the session hints. logind may send changed properties either
+    // with their values or as invalidated names, so always fetch them.
+    fn refresh(&mut self) {
+        if let Ok(locked) = self.session.get_property::<bool>("LockedHint") {
+            dispatch(InputEvent::SessionLocked(locked));
+        }
+
+        if let Ok(idle_hint) = self.session.get_property::<bool>("IdleHint") {
+            // The desktop sets IdleHint long after the last input, so it
+            // only tells us when the user came back, not when they left.
+            // IdleSinceHint is the CLOCK_REALTIME of that edge in usec.
+            if self.idle_hint && !idle_hint {
+                match self.session.get_property::<u64>("IdleSinceHint") {
+                    Ok(since) => dispatch(InputEvent::ActivityAt(since / 1_000_000)),
+                    Err(_) => dispatch(InputEvent::Activity),
+                }
+            }
+            self.idle_hint = idle_hint;
+        }
+    }
+}
+
+fn connect(address: Option<&str>) -> zbus::Result<Connection> {
+    match address {
+        Some(address) => zbus::blocking::connection::Builder::address(address)?.build(),
+        None => Connection::system(),
+    }
+}
+
+fn session_proxy(conn: &Connection) -> zbus::Result<Proxy<'static>> {
+    let manager = ProxyBuilder::<Proxy>::new(conn)
+        .destination(LOGIND_SERVICE)?
+        .path(MANAGER_PATH)?
+        .interface(MANAGER_INTERFACE)?
+        .cache_properties(CacheProperties::No)
+        .build()?;
+
+    // "auto" resolves to the session of the calling process, or the
+    // user's display session when the caller is not part of one
+    let session_path: OwnedObjectPath = manager.call("GetSession", &("auto",))?;
+
+    ProxyBuilder::<Proxy>::new(conn)
+        .destination(LOGIND_SERVICE)?
+        .path(session_path)?
+        .interface(SESSION_INTERFACE)?
+        .cache_properties(CacheProperties::No)
+        .build()
+}
+
+fn handle_signal(watcher: &mut SessionWatcher, message: &zbus::Message) {
+    let header = message.header();
+    let interface = header.interface().map(|name| name.as_str());
+    let member = header.member().map(|name| name.as_str());
+    let path = header.path().map(|path| path.as_str());
+    let on_session = path == Some(watcher.session.path().as_str());
+
+    match (interface, member) {
+        (Some(SESSION_INTERFACE), Some("Lock")) if on_session => {
+            dispatch(InputEvent::SessionLocked(true));
+        }
+        (Some(SESSION_INTERFACE), Some("Unlock")) if on_session => {
+            dispatch(InputEvent::SessionLocked(false));
+        }
+        (Some(PROPERTIES_INTERFACE), Some("PropertiesChanged")) if on_session => {
+            watcher.refresh();
+        }
+        (Some(MANAGER_INTERFACE), Some("PrepareForSleep")) => {
+            // true right before suspend, false after resume
+            if let Ok(going_to_sleep) = message.body().deserialize::<bool>() {
+                dispatch(InputEvent::Sleeping(going_to_sleep));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn run_signal_loop(running: Arc<AtomicBool>, session: Proxy<'static>, messages: MessageIterator) {
+    let mut watcher = SessionWatcher { session, idle_hint: false };
+    watcher.refresh();
+
+    // Ends when stop_monitoring closes the connection
+    for message in messages {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if let Ok(message) = message {
+            handle_signal(&mut watcher, &message);
+        }
+    }
+}
+
+// Session lock and suspend state from systemd-logind. This is an extra
+// source next to the input backends; it reports no key or pointer events.
+pub struct LogindSource {
+    // Bus to connect to; the system bus when None
+    address: Option<String>,
+    running: Arc<AtomicBool>,
+    connection: Option<Connection>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl LogindSource {
+    pub fn new() -> LogindSource {
+        LogindSource::with_address(std::env::var(BUS_ADDRESS_ENV).ok())
+    }
+
+    pub(crate) fn with_address(address: Option<String>) -> LogindSource {
+        LogindSource {
+            address,
+            running: Arc::new(AtomicBool::new(false)),
+            connection: None,
+            thread: None,
+        }
+    }
+}
+
+impl Default for LogindSource {
+    fn default() -> LogindSource {
+        LogindSource::new()
+    }
+}
+
+impl ActivitySource for LogindSource {
+    fn name(&self) -> &str {
+        "logind"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::SESSION
+    }
+
+    // Fails if the bus is unreachable or logind has no session for us
+    fn start(&mut self) -> bool {
+        if self.running.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        let conn = match connect(self.address.as_deref()) {
+            Ok(conn) => conn,
+            Err(_) => return false,
+        };
+
+        // Subscribe before reading the initial state so no change is missed
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .sender(LOGIND_SERVICE)
+            .map(|builder| builder.build());
+        let messages = match rule.and_then(|rule| MessageIterator::for_match_rule(rule, &conn, None)) {
+            Ok(messages) => messages,
+            Err(_) => return false,
+        };
+        let session = match session_proxy(&conn) {
+            Ok(session) => session,
+            Err(_) => return false,
+        };
+
+        self.running.store(true, Ordering::SeqCst);
+
+        self.connection = Some(conn);
+        let running = self.running.clone();
+        self.thread = Some(thread::spawn(move || run_signal_loop(running, session, messages)));
+
+        true
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // Closing the connection ends the signal iterator
+        if let Some(conn) = self.connection.take() {
+            let _ = conn.close();
+        }
+
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::time::{Duration, Instant};
+    use crate::testing::{self, TempDir};
+
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/test";
+
+    // A dbus-daemon of our own, stopped when dropped
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+        // Holds the config and socket; removed after the daemon stops
+        _dir: TempDir,
+    }
+
+    impl PrivateBus {
+        fn start() -> Option<PrivateBus> {
+            let dir = TempDir::new();
+            let config = dir.join("bus.conf");
+            std::fs::write(
+                &config,
+                format!(
+                    "<busconfig>
+                        <type>session</type>
+                        <listen>unix:path={}</listen>
+                        <policy context=\"default\">
+                            <allow send_destination=\"*\"/>
+                            <allow receive_sender=\"*\"/>
+                            <allow own=\"*\"/>
+                        </policy>
+                    </busconfig>",
+                    dir.join("socket").display()
+                ),
+            )
+            .ok()?;
+
+            let mut daemon = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?).read_line(&mut address).ok()?;
+            Some(PrivateBus { daemon, address: address.trim().to_string(), _dir: dir })
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    struct Manager;
+
+    #[zbus::interface(name = "org.freedesktop.login1.Manager")]
+    impl Manager {
+        fn get_session(&self, _id: &str) -> OwnedObjectPath {
+            OwnedObjectPath::try_from(SESSION_PATH).unwrap()
+        }
+    }
+
+    struct Session;
+
+    #[zbus::interface(name = "org.freedesktop.login1.Session")]
+    impl Session {
+        #[zbus(property)]
+        fn locked_hint(&self) -> bool {
+            true
+        }
+
+        #[zbus(property)]
+        fn idle_hint(&self) -> bool {
+            false
+        }
+    }
+
+    fn wait_for_state(state: u32) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while crate::get_session_state() != state {
+            assert!(Instant::now() < deadline, "session state never became {state}");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Needs dbus-daemon; skipped without it
+    #[test]
+    fn follows_a_mock_logind_on_a_private_bus() {
+        let _isolated = testing::isolate();
+        let Some(bus) = PrivateBus::start() else {
+            eprintln!("skipped: cannot run dbus-daemon");
+            return;
+        };
+
+        // The bus is there, logind is not
+        let mut source = LogindSource::with_address(Some(bus.address.clone()));
+        assert!(!source.start());
+
+        let logind = zbus::blocking::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name(LOGIND_SERVICE)
+            .unwrap()
+            .serve_at(MANAGER_PATH, Manager)
+            .unwrap()
+            .serve_at(SESSION_PATH, Session)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(source.start());
+        // Read when the source starts
+        wait_for_state(crate::SESSION_STATE_LOCKED);
+
+        logind.emit_signal(None::<&str>, SESSION_PATH, SESSION_INTERFACE, "Unlock", &()).unwrap();
+        wait_for_state(crate::SESSION_STATE_ACTIVE);
+
+        for sleeping in [true, false] {
+            logind.emit_signal(None::<&str>, MANAGER_PATH, MANAGER_INTERFACE, "PrepareForSleep", &(sleeping,)).unwrap();
+            wait_for_state(if sleeping { crate::SESSION_STATE_SLEEPING } else { crate::SESSION_STATE_ACTIVE });
+        }
+
+        source.stop();
+    }
+}
@@ -1,19 +1,14 @@
 // activity_monitor/src/macos.rs
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use std::os::raw::{c_void, c_int};
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent, MouseButtons, PointerEvent};
 
 static RUNNING: AtomicBool = AtomicBool::new(false);
-static mut EVENT_TAP_REF: Option<*mut c_void> = None;
-
-// Stateful tracking of keys and activity
-lazy_static::lazy_static! {
-    static ref PRESSED_KEYS: Mutex<HashMap<u16, Instant>> = Mutex::new(HashMap::new());
-    static ref KEY_TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(2)); // Timeout for key presses
-}
+// The thread that owns the event tap; it alone releases the tap
+static MONITORING_THREAD: Mutex<Option<thread::JoinHandle<()>>> = Mutex::new(None);
 
 // Define CGPoint structure
 #[repr(C)]
@@ -22,60 +17,20 @@ struct CGPoint {
     y: f64,
 }
 
-// Smart activity detection for keyboard
-fn process_keyboard_event(key_code: u16, is_down: bool) -> (bool, bool) {
-    let mut keys = PRESSED_KEYS.lock().unwrap();
-    let key_timeout = *KEY_TIMEOUT.lock().unwrap();
-    
-    // Get current time
-    let now = Instant::now();
-    
-    // For key down events
-    if is_down {
-        // If this key is not already pressed (or has timed out), count it as new activity
-        if !keys.contains_key(&key_code) || 
-           now.duration_since(*keys.get(&key_code).unwrap()) > key_timeout {
-            // Update key press time
-            keys.insert(key_code, now);
-            
-            // Signal genuine activity
-            return (true, true);
-        }
-    } else {
-        // Remove key from pressed keys if it exists
-        if keys.contains_key(&key_code) {
-            // Only count key up if it hasn't been too long since key down (prevent stuck keys)
-            let time_since_key_down = if let Some(time) = keys.get(&key_code) {
-                now.duration_since(*time)
-            } else {
-                Duration::from_secs(0)
-            };
-            
-            keys.remove(&key_code);
-            
-            // For key up, we don't count it as genuine activity
-            return (false, false);
-        }
-    }
-    
-    // No new activity detected
-    (false, false)
-}
-
-// Check for timeout on all pressed keys
-fn cleanup_stale_keys() {
-    let mut keys = PRESSED_KEYS.lock().unwrap();
-    let now = Instant::now();
-    let key_timeout = *KEY_TIMEOUT.lock().unwrap();
-    
-    // Remove keys that have been pressed too long (stuck keys)
-    keys.retain(|_, time| now.duration_since(*time) < key_timeout);
-}
-
 // Define event types
 const EVENT_TYPE_KEY_DOWN: u32 = 10;
 const EVENT_TYPE_KEY_UP: u32 = 11;
-const EVENT_TYPE_MOUSE_EVENTS: [u32; 6] = [1, 2, 3, 4, 5, 22]; // Various mouse events
+const EVENT_TYPE_LEFT_MOUSE_DOWN: u32 = 1;
+const EVENT_TYPE_LEFT_MOUSE_UP: u32 = 2;
+const EVENT_TYPE_RIGHT_MOUSE_DOWN: u32 = 3;
+const EVENT_TYPE_RIGHT_MOUSE_UP: u32 = 4;
+const EVENT_TYPE_MOUSE_MOVED: u32 = 5;
+const EVENT_TYPE_LEFT_MOUSE_DRAGGED: u32 = 6;
+const EVENT_TYPE_RIGHT_MOUSE_DRAGGED: u32 = 7;
+const EVENT_TYPE_SCROLL_WHEEL: u32 = 22;
+const EVENT_TYPE_OTHER_MOUSE_DOWN: u32 = 25;
+const EVENT_TYPE_OTHER_MOUSE_UP: u32 = 26;
+const EVENT_TYPE_OTHER_MOUSE_DRAGGED: u32 = 27;
 
 // Callback type for event tap
 type EventTapCallbackType = unsafe extern "C" fn(
@@ -108,8 +63,17 @@ extern "C" {
     // Get key code from event
     fn GetKeyCodeFromEvent(event: *mut c_void) -> u16;
     
+    // Get the button number of a mouse event
+    fn GetMouseButtonFromEvent(event: *mut c_void) -> i64;
+
+    // Get the lines scrolled by a scroll wheel event
+    fn GetScrollDeltaFromEvent(event: *mut c_void, vertical: *mut i32, horizontal: *mut i32);
+
     // Get current mouse position
     fn GetCurrentMousePos(point: *mut CGPoint) -> c_int;
+
+    // Get the pixels per inch of the main display, or 0
+    fn GetMainDisplayDpi() -> f64;
 }
 
 // Callback function for the event tap
@@ -126,50 +90,72 @@ unsafe extern "C" fn event_callback(
         // Extract key code from event
         let key_code = GetKeyCodeFromEvent(event);
         
-        // Process the event - get both increment_counter and is_genuine flags
-        let (increment_counter, is_genuine) = process_keyboard_event(
-            key_code, 
-            event_type_u32 == EVENT_TYPE_KEY_DOWN
-        );
-        
-        if increment_counter {
-            // Only increment counter if we detected new activity
-            super::increment_keyboard();
-        }
-        
-        // Update activity time only for genuine activity
-        if is_genuine {
-            super::update_genuine_activity_time(true);
-        }
+        dispatch(InputEvent::Key {
+            code: key_code as u32,
+            pressed: event_type_u32 == EVENT_TYPE_KEY_DOWN,
+        });
     } 
     // Handle mouse events
-    else if EVENT_TYPE_MOUSE_EVENTS.contains(&event_type_u32) {
-        // Mouse activity is always considered genuine
-        super::increment_mouse();
-        super::update_genuine_activity_time(true);
+    else if let Some(pointer) = pointer_event(event_type_u32, event) {
+        dispatch(InputEvent::Pointer(pointer));
     }
     
     // Return the event unchanged
     event
 }
 
-pub fn start_monitoring() {
+unsafe fn pointer_event(event_type: u32, event: *mut c_void) -> Option<PointerEvent> {
+    let mut pointer = PointerEvent::default();
+    match event_type {
+        EVENT_TYPE_LEFT_MOUSE_DOWN => pointer.pressed = MouseButtons::LEFT,
+        EVENT_TYPE_LEFT_MOUSE_UP => pointer.released = MouseButtons::LEFT,
+        EVENT_TYPE_RIGHT_MOUSE_DOWN => pointer.pressed = MouseButtons::RIGHT,
+        EVENT_TYPE_RIGHT_MOUSE_UP => pointer.released = MouseButtons::RIGHT,
+        EVENT_TYPE_OTHER_MOUSE_DOWN => pointer.pressed = other_button(event),
+        EVENT_TYPE_OTHER_MOUSE_UP => pointer.released = other_button(event),
+        EVENT_TYPE_MOUSE_MOVED
+        | EVENT_TYPE_LEFT_MOUSE_DRAGGED
+        | EVENT_TYPE_RIGHT_MOUSE_DRAGGED
+        | EVENT_TYPE_OTHER_MOUSE_DRAGGED => pointer.moved = true,
+        EVENT_TYPE_SCROLL_WHEEL => {
+            let (mut vertical, mut horizontal) = (0, 0);
+            GetScrollDeltaFromEvent(event, &mut vertical, &mut horizontal);
+            pointer.wheel_vertical = vertical.unsigned_abs();
+            pointer.wheel_horizontal = horizontal.unsigned_abs();
+        }
+        _ => return None,
+    }
+
+    // Global display coordinates, from the top left of the main display
+    let mut point = CGPoint { x: 0.0, y: 0.0 };
+    if GetCurrentMousePos(&mut point) == 0 {
+        pointer.position = Some((point.x, point.y));
+    }
+    Some(pointer)
+}
+
+// Button 2 is the middle button; anything higher is extra
+unsafe fn other_button(event: *mut c_void) -> MouseButtons {
+    match GetMouseButtonFromEvent(event) {
+        2 => MouseButtons::MIDDLE,
+        _ => MouseButtons::EXTRA,
+    }
+}
+
+fn start_monitoring() {
     if RUNNING.load(Ordering::SeqCst) {
         return;
     }
     
     RUNNING.store(true, Ordering::SeqCst);
     
-    // Reset state
-    reset_monitoring_state();
-    
-    thread::spawn(|| {
+    let handle = thread::spawn(|| {
         unsafe {
             // Create an event tap for keyboard and mouse events
             let event_tap = InitializeEventTap(event_callback, std::ptr::null_mut());
             
             if !event_tap.is_null() {
-                EVENT_TAP_REF = Some(event_tap);
+                crate::pointer::report_screen_dpi(Some(GetMainDisplayDpi()));
                 
                 // Add the event tap to the current run loop
                 AddEventTapToCurrentRunLoop(event_tap);
@@ -178,19 +164,9 @@ pub fn start_monitoring() {
                 EnableEventTap(event_tap, true);
                 
                 // Run the event loop while monitoring is active
-                let mut last_cleanup = Instant::now();
-                let cleanup_interval = Duration::from_secs(10);
-                
                 while RUNNING.load(Ordering::SeqCst) {
-                    // Run the run loop for a short interval (50ms)
-                    RunCurrentRunLoopWithTimeout(0.05);
-                    
-                    // Periodically clean up stale keys
-                    let now = Instant::now();
-                    if now.duration_since(last_cleanup) > cleanup_interval {
-                        cleanup_stale_keys();
-                        last_cleanup = now;
-                    }
+                    // Run the run loop for one poll interval
+                    RunCurrentRunLoopWithTimeout(crate::source::poll_interval().as_secs_f64());
                     
                     // Small sleep to prevent excessive CPU usage
                     thread::sleep(Duration::from_millis(5));
@@ -199,33 +175,40 @@ pub fn start_monitoring() {
                 // Clean up the event tap
                 EnableEventTap(event_tap, false);
                 CleanupEventTap(event_tap);
-                EVENT_TAP_REF = None;
             }
         }
     });
+    *MONITORING_THREAD.lock().unwrap() = Some(handle);
 }
 
-pub fn stop_monitoring() {
+fn stop_monitoring() {
     RUNNING.store(false, Ordering::SeqCst);
     
-    // Give the monitoring thread a moment to clean up
-    thread::sleep(Duration::from_millis(100));
-    
-    // Explicitly disable and clean up the event tap if it's still active
-    unsafe {
-        if let Some(tap) = EVENT_TAP_REF {
-            EnableEventTap(tap, false);
-            CleanupEventTap(tap);
-            EVENT_TAP_REF = None;
-        }
+    // The thread notices within one poll interval and releases the tap
+    if let Some(handle) = MONITORING_THREAD.lock().unwrap().take() {
+        let _ = handle.join();
     }
-    
-    // Clear state
-    reset_monitoring_state();
 }
 
-// Reset monitoring state (called from lib.rs)
-pub fn reset_monitoring_state() {
-    let mut keys = PRESSED_KEYS.lock().unwrap();
-    keys.clear();
+// Quartz event tap on the user's session; needs the Accessibility or
+// Input Monitoring permission
+pub struct EventTapSource;
+
+impl ActivitySource for EventTapSource {
+    fn name(&self) -> &str {
+        "event-tap"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::KEYBOARD | Capabilities::MOUSE
+    }
+
+    fn start(&mut self) -> bool {
+        start_monitoring();
+        true
+    }
+
+    fn stop(&mut self) {
+        stop_monitoring();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+// activity_monitor/src/metrics.rs
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::monitor::default_monitor;
+
+// How long a scraper may take to send its request. Each connection has a
+// thread of its own, so a slow one holds up nobody else.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+struct Server {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+// The running server, if any. One per process.
+static SERVER: Mutex<Option<Server>> = Mutex::new(None);
+
+// Serve GET /metrics on 127.0.0.1:`port` (0 picks a free port) from
+// background threads, replacing any server started before. Returns the port.
+// Scrapes only read atomics, so they never hold up the input hooks.
+pub fn serve(port: u16) -> io::Result<u16> {
+    let mut server = SERVER.lock().unwrap();
+    if let Some(running) = server.take() {
+        running.stop();
+    }
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let address = listener.local_addr()?;
+    let stopping = Arc::new(AtomicBool::new(false));
+
+    let thread = {
+        let stopping = stopping.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    thread::spawn(move || respond(stream));
+                }
+            }
+        })
+    };
+
+    *server = Some(Server { address, stopping, thread });
+    Ok(address.port())
+}
+
+// Returns false if no server was running
+pub fn shutdown() -> bool {
+    match SERVER.lock().unwrap().take() {
+        Some(server) => {
+            server.stop();
+            true
+        },
+        None => false,
+    }
+}
+
+impl Server {
+    fn stop(self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.address);
+        let _ = self.thread.join();
+    }
+}
+
+// Answer one HTTP request and close the connection
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut openmetrics = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let header = header.to_ascii_lowercase();
+        if header.starts_with("accept:") && header.contains("application/openmetrics-text") {
+            openmetrics = true;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) if openmetrics => ("200 OK", OPENMETRICS_CONTENT_TYPE, render(true)),
+        (Some("GET"), Some("/metrics")) => ("200 OK", PROMETHEUS_CONTENT_TYPE, render(false)),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method Not Allowed\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+// The exposition text. OpenMetrics names counter families without the
+// `_total` suffix their samples carry, and ends with `# EOF`.
+fn render(openmetrics: bool) -> String {
+    let mut out = String::new();
+    let (keyboard_events, mouse_events) = crate::total_events();
+    let monitor = default_monitor();
+
+    let counter = |out: &mut String, name: &str, help: &str, value: u64| {
+        let family = if openmetrics { name.to_string() } else { format!("{name}_total") };
+        let _ = writeln!(out, "# HELP {family} {help}");
+        let _ = writeln!(out, "# TYPE {family} counter");
+        let _ = writeln!(out, "{name}_total {value}");
+    };
+    counter(&mut out, "activity_monitor_keyboard_events", "Key presses counted since the process started.", keyboard_events);
+    counter(&mut out, "activity_monitor_mouse_events", "Pointer events counted since the process started.", mouse_events);
+
+    let gauge = |out: &mut String, name: &str, help: &str, value: u64| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {value}");
+    };
+    gauge(&mut out, "activity_monitor_idle_seconds", "Seconds since the last genuine activity seen by the default monitor.", monitor.idle_time());
+    gauge(&mut out, "activity_monitor_monitoring", "Whether any monitor instance is monitoring.", crate::sources_running() as u64);
+    gauge(&mut out, "activity_monitor_default_monitoring", "Whether the default monitor is monitoring.", monitor.is_monitoring() as u64);
+    gauge(&mut out, "activity_monitor_counters_available", "Whether the running sources can count input events.", crate::counters_available() as u64);
+    gauge(&mut out, "activity_monitor_session_state", "0 active, 1 locked, 2 sleeping.", crate::get_session_state() as u64);
+    gauge(&mut out, "activity_monitor_capabilities", "Capability bits of the running sources.", crate::get_capabilities() as u64);
+
+    // Left out while sources are being started or stopped
+    if let Some(sources) = crate::source::try_source_status() {
+        let _ = writeln!(out, "# HELP activity_monitor_source_up Whether each registered input source is running.");
+        let _ = writeln!(out, "# TYPE activity_monitor_source_up gauge");
+        for source in sources {
+            let _ = writeln!(out, "activity_monitor_source_up{{source=\"{}\"}} {}", escape_label(&source.name), source.running as u64);
+        }
+    }
+
+    if openmetrics {
+        out.push_str("# EOF\n");
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+    use crate::source::InputEvent;
+    use crate::testing;
+
+    fn get(port: u16, accept: &str) -> String {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: {accept}\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn sample(text: &str, name: &str) -> u64 {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|value| value.parse().ok())
+            .unwrap()
+    }
+
+    #[test]
+    fn serves_both_formats() {
+        let _isolated = testing::isolate();
+        let (_clock, source, _monitor) = testing::simulated();
+        let port = serve(0).unwrap();
+        // A client that never sends its request holds up neither the
+        // scrapes nor the shutdown
+        let _silent = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let started = Instant::now();
+
+        let prometheus = get(port, "text/plain");
+        assert!(prometheus.starts_with("HTTP/1.1 200 OK"));
+        assert!(prometheus.contains("# TYPE activity_monitor_keyboard_events_total counter"));
+        assert!(prometheus.contains("activity_monitor_idle_seconds "));
+        assert!(!prometheus.contains("# EOF"));
+
+        let before = sample(&prometheus, "activity_monitor_keyboard_events_total");
+        source.emit(InputEvent::Key { code: 30, pressed: true });
+        source.emit(InputEvent::Key { code: 30, pressed: false });
+        assert!(sample(&get(port, "text/plain"), "activity_monitor_keyboard_events_total") > before);
+
+        let openmetrics = get(port, "application/openmetrics-text; version=1.0.0");
+        assert!(openmetrics.contains("# TYPE activity_monitor_keyboard_events counter"));
+        assert!(openmetrics.contains("activity_monitor_keyboard_events_total "));
+        assert!(openmetrics.ends_with("# EOF\n"));
+
+        assert!(shutdown());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err());
+    }
+}
@@ -0,0 +1,865 @@
+// activity_monitor/src/monitor.rs
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use crate::activity_log::{self, LogFormat, LogSync};
+use crate::autosave::{Autosave, AutosaveConfig, AutosaveError, Writer};
+use crate::config::{self, Config, ConfigError};
+use crate::events::{self, ActivityEvent, CallbackList};
+use crate::pointer::{Heatmap, HeatmapFormat, HeatmapLayer, PointerTravel};
+use crate::rotation::LogRotation;
+#[cfg(feature = "sqlite")]
+use crate::store::{ActivityStore, IdlePeriod};
+use crate::{clock, source};
+
+// One consumer's view of user activity. Every instance has its own counters
+// and idle baseline; all of them are fed by the same shared sources, which
+// run while at least one instance is monitoring.
+pub struct ActivityMonitor {
+    monitoring: AtomicBool,
+    keyboard_count: AtomicU64,
+    mouse_count: AtomicU64,
+    // What the mouse events were, where the source could tell
+    mouse_counts: Mutex<MouseCounts>,
+    pointer_travel: Mutex<PointerTravel>,
+    // Only kept once enable_heatmap() is called
+    heatmap: Mutex<Option<Heatmap>>,
+    last_genuine_activity: AtomicU64,
+    // When the counters were last reset
+    interval_start: AtomicU64,
+    // Identifies one start()..stop() run in log records
+    session_id: Mutex<String>,
+    // Counters are bumped under the read side and snapshotted under the
+    // write side, so a snapshot never sees half of an update
+    gate: RwLock<()>,
+    idle_threshold: AtomicU64,
+    // Set once idle time reaches the threshold, until the next activity.
+    // Transitions happen under the lock so each is reported exactly once.
+    idle: Mutex<bool>,
+    callbacks: CallbackList,
+    // Format of save_activity_log()
+    log_format: Mutex<LogFormat>,
+    // Applied to every log this instance writes
+    log_rotation: Mutex<Option<LogRotation>>,
+    log_sync: Mutex<LogSync>,
+    autosave: Mutex<Autosave>,
+    // Most recent failed autosave write since start_autosave()
+    autosave_error: Mutex<Option<AutosaveError>>,
+    // Reloads the config file given to load_config() when it changes
+    config_watcher: Mutex<Option<config::Watcher>>,
+    // Why the config file last failed to load, until it next loads
+    config_error: Mutex<Option<String>>,
+    // Attached store and the id of the callback feeding it idle periods
+    #[cfg(feature = "sqlite")]
+    store: Mutex<Option<(Arc<ActivityStore>, u64)>>,
+}
+
+// Everything a consumer usually polls, captured at one instant. Times are
+// in seconds (since the epoch for `timestamp`).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ActivitySnapshot {
+    pub timestamp: u64,
+    pub keyboard_count: u64,
+    pub mouse_count: u64,
+    pub idle_time: u64,
+    // ACTIVITY_STATE_* and SESSION_STATE_* values
+    pub activity_state: u32,
+    pub session_state: u32,
+    // When false the counts are not meaningful, see counters_available()
+    pub counters_available: bool,
+    // When the counters were last reset; the counts cover
+    // interval_start..timestamp
+    pub interval_start: u64,
+    pub mouse: MouseCounts,
+    pub pointer_travel: PointerTravel,
+}
+
+// Mouse events by kind. Sources that cannot tell kinds apart add to
+// mouse_count only, so these need not add up to it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MouseCounts {
+    // Button presses
+    pub left_clicks: u64,
+    pub right_clicks: u64,
+    pub middle_clicks: u64,
+    // Back, forward and any other button
+    pub extra_clicks: u64,
+    // Wheel notches in either direction
+    pub wheel_vertical: u64,
+    pub wheel_horizontal: u64,
+    // Pointer motion events, with or without a button held
+    pub moves: u64,
+    // Motion that started while a button was held, once per press
+    pub drags: u64,
+}
+
+impl MouseCounts {
+    pub fn add(&mut self, other: &MouseCounts) {
+        self.left_clicks += other.left_clicks;
+        self.right_clicks += other.right_clicks;
+        self.middle_clicks += other.middle_clicks;
+        self.extra_clicks += other.extra_clicks;
+        self.wheel_vertical += other.wheel_vertical;
+        self.wheel_horizontal += other.wheel_horizontal;
+        self.moves += other.moves;
+        self.drags += other.drags;
+    }
+}
+
+// Whether a monitor instance considers the user present
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivityState {
+    Active,
+    // Idle since the last activity at `since` (seconds since the epoch)
+    Idle { since: u64 },
+}
+
+pub const DEFAULT_IDLE_THRESHOLD_SECS: u64 = 300;
+
+// Registry entry for a live instance. Instances remove themselves in Drop
+// under the write lock, so a pointer read under the read lock is valid.
+struct MonitorPtr(*const ActivityMonitor);
+
+unsafe impl Send for MonitorPtr {}
+unsafe impl Sync for MonitorPtr {}
+
+lazy_static::lazy_static! {
+    // Every live instance, so events can fan out without keeping them alive
+    static ref MONITORS: RwLock<Vec<MonitorPtr>> = RwLock::new(Vec::new());
+    // The instance behind the handle-less FFI functions. Loads the file
+    // named by ACTIVITY_MONITOR_CONFIG; a failure shows in config_error().
+    static ref DEFAULT_MONITOR: Arc<ActivityMonitor> = {
+        let monitor = ActivityMonitor::new();
+        if let Some(path) = config::env_path() {
+            let _ = monitor.load_config(&path);
+        }
+        monitor
+    };
+}
+
+// Number of instances currently monitoring; sources run while it is non-zero
+static ACTIVE_MONITORS: Mutex<usize> = Mutex::new(0);
+
+impl ActivityMonitor {
+    pub fn new() -> Arc<ActivityMonitor> {
+        let monitor = Arc::new(ActivityMonitor {
+            monitoring: AtomicBool::new(false),
+            keyboard_count: AtomicU64::new(0),
+            mouse_count: AtomicU64::new(0),
+            mouse_counts: Mutex::new(MouseCounts::default()),
+            pointer_travel: Mutex::new(PointerTravel::default()),
+            heatmap: Mutex::new(None),
+            last_genuine_activity: AtomicU64::new(0),
+            interval_start: AtomicU64::new(0),
+            session_id: Mutex::new(String::new()),
+            gate: RwLock::new(()),
+            idle_threshold: AtomicU64::new(DEFAULT_IDLE_THRESHOLD_SECS),
+            idle: Mutex::new(false),
+            callbacks: CallbackList::default(),
+            log_format: Mutex::new(LogFormat::Csv),
+            log_rotation: Mutex::new(None),
+            log_sync: Mutex::new(LogSync::default()),
+            autosave: Mutex::new(Autosave::default()),
+            autosave_error: Mutex::new(None),
+            config_watcher: Mutex::new(None),
+            config_error: Mutex::new(None),
+            #[cfg(feature = "sqlite")]
+            store: Mutex::new(None),
+        });
+
+        MONITORS.write().unwrap().push(MonitorPtr(Arc::as_ptr(&monitor)));
+
+        monitor
+    }
+
+    // Returns false if this instance is already monitoring
+    pub fn start(&self) -> bool {
+        let mut active = ACTIVE_MONITORS.lock().unwrap();
+        if self.monitoring.swap(true, Ordering::SeqCst) {
+            return false; // Already monitoring
+        }
+
+        // Reset counters; idle time runs from the start
+        self.reset_counters();
+        self.last_genuine_activity.store(clock::now_secs(), Ordering::SeqCst);
+        *self.idle.lock().unwrap() = false;
+        *self.session_id.lock().unwrap() = new_session_id();
+        #[cfg(feature = "sqlite")]
+        self.begin_store_session();
+
+        // The first instance brings up the shared sources with fresh state
+        if *active == 0 {
+            source::reset_sources();
+            crate::sources_started(source::start_sources());
+        }
+        *active += 1;
+
+        events::start_idle_checks();
+        self.resume_autosave();
+
+        true
+    }
+
+    // Returns false if this instance is not monitoring
+    pub fn stop(&self) -> bool {
+        let mut active = ACTIVE_MONITORS.lock().unwrap();
+        if !self.monitoring.swap(false, Ordering::SeqCst) {
+            return false; // Not monitoring
+        }
+
+        self.finish_autosave();
+
+        #[cfg(feature = "sqlite")]
+        self.end_store_session();
+
+        // The last instance takes the shared sources down
+        *active -= 1;
+        if *active == 0 {
+            source::stop_sources();
+            crate::sources_stopped();
+        }
+
+        true
+    }
+
+    pub fn is_monitoring(&self) -> bool {
+        self.monitoring.load(Ordering::SeqCst)
+    }
+
+    pub fn keyboard_count(&self) -> u64 {
+        self.keyboard_count.load(Ordering::SeqCst)
+    }
+
+    pub fn mouse_count(&self) -> u64 {
+        self.mouse_count.load(Ordering::SeqCst)
+    }
+
+    pub fn mouse_counts(&self) -> MouseCounts {
+        *self.mouse_counts.lock().unwrap()
+    }
+
+    // Counted and reset along with the counters
+    pub fn pointer_travel(&self) -> PointerTravel {
+        *self.pointer_travel.lock().unwrap()
+    }
+
+    // Start a heatmap of where the pointer moves and clicks while this
+    // instance monitors, on a grid of `cell_size` pixel cells. Unlike the
+    // counters it is never reset; enabling it again starts an empty one.
+    pub fn enable_heatmap(&self, cell_size: u32) {
+        *self.heatmap.lock().unwrap() = Some(Heatmap::new(cell_size));
+    }
+
+    // Returns false if no heatmap was kept
+    pub fn disable_heatmap(&self) -> bool {
+        self.heatmap.lock().unwrap().take().is_some()
+    }
+
+    pub fn heatmap_cell_size(&self) -> Option<u32> {
+        self.heatmap.lock().unwrap().as_ref().map(Heatmap::cell_size)
+    }
+
+    pub fn heatmap(&self) -> Option<Heatmap> {
+        self.heatmap.lock().unwrap().clone()
+    }
+
+    // Export one layer of the heatmap to `path`, replacing the file. Returns
+    // false if no heatmap is kept or writing fails.
+    pub fn save_heatmap(&self, path: &Path, layer: HeatmapLayer, format: HeatmapFormat) -> bool {
+        self.heatmap().is_some_and(|heatmap| heatmap.save(path, layer, format).is_ok())
+    }
+
+    // Seconds since the last genuine activity seen by this instance
+    pub fn idle_time(&self) -> u64 {
+        let now = crate::awake_now_secs();
+
+        let last = self.last_genuine_activity.load(Ordering::SeqCst);
+        if last == 0 || now < last {
+            return 0;
+        }
+
+        now - last
+    }
+
+    // Only affects this instance; other instances keep their counts. Idle
+    // time is not a counter and keeps running from the last activity.
+    pub fn reset_counters(&self) {
+        let _gate = self.gate.write().unwrap();
+        self.keyboard_count.store(0, Ordering::SeqCst);
+        self.mouse_count.store(0, Ordering::SeqCst);
+        *self.mouse_counts.lock().unwrap() = MouseCounts::default();
+        *self.pointer_travel.lock().unwrap() = PointerTravel::default();
+        self.interval_start.store(clock::now_secs(), Ordering::SeqCst);
+    }
+
+    pub fn snapshot(&self) -> ActivitySnapshot {
+        self.capture(false)
+    }
+
+    // Take a snapshot and reset like reset_counters() in one step. Events
+    // that arrive meanwhile land in either this snapshot or the next.
+    pub fn take_snapshot_and_reset(&self) -> ActivitySnapshot {
+        self.capture(true)
+    }
+
+    fn capture(&self, reset: bool) -> ActivitySnapshot {
+        let activity_state = match self.activity_state() {
+            ActivityState::Active => crate::ACTIVITY_STATE_ACTIVE,
+            ActivityState::Idle { .. } => crate::ACTIVITY_STATE_IDLE,
+        };
+
+        let _gate = self.gate.write().unwrap();
+        let now = clock::now_secs();
+        let idle_time = self.idle_time();
+
+        let interval_start = if reset {
+            self.interval_start.swap(now, Ordering::SeqCst)
+        } else {
+            self.interval_start.load(Ordering::SeqCst)
+        };
+
+        let (keyboard_count, mouse_count, mouse, pointer_travel) = if reset {
+            (
+                self.keyboard_count.swap(0, Ordering::SeqCst),
+                self.mouse_count.swap(0, Ordering::SeqCst),
+                std::mem::take(&mut *self.mouse_counts.lock().unwrap()),
+                std::mem::take(&mut *self.pointer_travel.lock().unwrap()),
+            )
+        } else {
+            (self.keyboard_count(), self.mouse_count(), self.mouse_counts(), self.pointer_travel())
+        };
+
+        ActivitySnapshot {
+            timestamp: now,
+            keyboard_count,
+            mouse_count,
+            idle_time,
+            activity_state,
+            session_state: crate::get_session_state(),
+            counters_available: crate::counters_available(),
+            interval_start,
+            mouse,
+            pointer_travel,
+        }
+    }
+
+    // Put the counts of a taken snapshot back, e.g. when logging it failed,
+    // so the next snapshot covers both intervals
+    fn restore(&self, snapshot: &ActivitySnapshot) {
+        let _gate = self.gate.read().unwrap();
+        self.keyboard_count.fetch_add(snapshot.keyboard_count, Ordering::SeqCst);
+        self.mouse_count.fetch_add(snapshot.mouse_count, Ordering::SeqCst);
+        self.mouse_counts.lock().unwrap().add(&snapshot.mouse);
+        self.pointer_travel.lock().unwrap().add(&snapshot.pointer_travel);
+        self.interval_start.fetch_min(snapshot.interval_start, Ordering::SeqCst);
+    }
+
+    // Identifies the current start()..stop() run; empty before the first
+    pub fn session_id(&self) -> String {
+        self.session_id.lock().unwrap().clone()
+    }
+
+    // Idle time in seconds after which the user counts as idle. Raising it
+    // while idle does not end the idle period; only activity does.
+    pub fn set_idle_threshold(&self, seconds: u64) {
+        self.idle_threshold.store(seconds.max(1), Ordering::SeqCst);
+        self.check_idle();
+    }
+
+    pub fn idle_threshold(&self) -> u64 {
+        self.idle_threshold.load(Ordering::SeqCst)
+    }
+
+    // Also checked about once a second while monitoring, so transitions are
+    // reported to callbacks without anyone polling this
+    pub fn activity_state(&self) -> ActivityState {
+        self.check_idle();
+
+        if *self.idle.lock().unwrap() {
+            ActivityState::Idle {
+                since: self.last_genuine_activity.load(Ordering::SeqCst),
+            }
+        } else {
+            ActivityState::Active
+        }
+    }
+
+    // Become idle once idle time reaches the threshold
+    fn check_idle(&self) {
+        if !self.is_monitoring() {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        let idle_time = self.idle_time();
+
+        if !*idle && idle_time >= self.idle_threshold() {
+            *idle = true;
+
+            let idle_since = self.last_genuine_activity.load(Ordering::SeqCst);
+            self.notify(ActivityEvent {
+                kind: events::ACTIVITY_EVENT_IDLE_START,
+                timestamp: idle_since + idle_time,
+                idle_seconds: idle_time,
+                idle_since,
+            });
+        }
+    }
+
+    // Record genuine activity at `timestamp`, ending an idle period
+    fn record_activity(&self, timestamp: u64) {
+        let mut idle = self.idle.lock().unwrap();
+        let last = self.last_genuine_activity.fetch_max(timestamp, Ordering::SeqCst);
+
+        if *idle && timestamp > last {
+            *idle = false;
+
+            self.notify(ActivityEvent {
+                kind: events::ACTIVITY_EVENT_IDLE_END,
+                timestamp,
+                idle_seconds: timestamp - last,
+                idle_since: last,
+            });
+        }
+    }
+
+    // Call `callback` with every event seen by this instance while it is
+    // monitoring. Callbacks always run on the library's dispatcher thread,
+    // one at a time and in event order, never on an OS hook thread. Returns
+    // an id for unregister_callback().
+    pub fn register_callback<F>(&self, callback: F) -> u64
+    where
+        F: Fn(&ActivityEvent) + Send + Sync + 'static,
+    {
+        events::add(&self.callbacks, Arc::new(callback))
+    }
+
+    pub(crate) fn register_ffi_callback(&self, callback: events::ActivityCallback, user_data: *mut std::os::raw::c_void) -> u64 {
+        events::add(&self.callbacks, events::from_ffi(callback, user_data))
+    }
+
+    // Once this returns the callback is not running and will not be called
+    // again. Returns false for an unknown id.
+    pub fn unregister_callback(&self, id: u64) -> bool {
+        events::remove(&self.callbacks, id)
+    }
+
+    fn notify(&self, event: ActivityEvent) {
+        events::post(&self.callbacks, event);
+    }
+
+    fn notify_input(&self, kind: u32, timestamp: u64) {
+        self.notify(ActivityEvent {
+            kind,
+            timestamp,
+            idle_seconds: 0,
+            idle_since: 0,
+        });
+    }
+
+    // Append one row with the current counts in log_format() and reset
+    // them. On failure the counts are kept for the next attempt.
+    pub fn save_activity_log(&self, path: &Path) -> bool {
+        self.save_activity_log_as(path, self.log_format())
+    }
+
+    pub fn save_activity_log_as(&self, path: &Path, format: LogFormat) -> bool {
+        self.save_log(path, format).is_ok()
+    }
+
+    fn save_log(&self, path: &Path, format: LogFormat) -> io::Result<()> {
+        let snapshot = self.take_snapshot_and_reset();
+        let record = activity_log::LogRecord::new(&snapshot, &self.session_id());
+
+        activity_log::append(path, format, &record, self.log_rotation().as_ref(), self.log_sync())
+            .inspect_err(|_| self.restore(&snapshot))
+    }
+
+    // Defaults to LogFormat::Csv. Saves to a log already written in the
+    // other format fail rather than mix the two.
+    pub fn set_log_format(&self, format: LogFormat) {
+        *self.log_format.lock().unwrap() = format;
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        *self.log_format.lock().unwrap()
+    }
+
+    // Rotate logs written by this instance before a save that finds them
+    // due. None (the default) lets logs grow without limit.
+    pub fn set_log_rotation(&self, log_rotation: Option<LogRotation>) {
+        *self.log_rotation.lock().unwrap() = log_rotation;
+    }
+
+    pub fn log_rotation(&self) -> Option<LogRotation> {
+        *self.log_rotation.lock().unwrap()
+    }
+
+    // How far logs written by this instance are flushed before a save
+    // succeeds. Defaults to LogSync::Data.
+    pub fn set_log_sync(&self, sync: LogSync) {
+        *self.log_sync.lock().unwrap() = sync;
+    }
+
+    pub fn log_sync(&self) -> LogSync {
+        *self.log_sync.lock().unwrap()
+    }
+
+    // Save the activity log to `path` every `interval_secs` on a background
+    // thread while this instance is monitoring, and once more when it stops.
+    // Replaces any autosave started before.
+    pub fn start_autosave(&self, path: &Path, interval_secs: u64, format: LogFormat) {
+        let config = AutosaveConfig {
+            path: path.to_path_buf(),
+            interval_secs: interval_secs.max(1),
+            format,
+        };
+
+        let mut autosave = self.autosave.lock().unwrap();
+        if let Some(writer) = autosave.writer.take() {
+            writer.stop();
+        }
+        *self.autosave_error.lock().unwrap() = None;
+
+        if self.is_monitoring() {
+            autosave.writer = Some(Writer::spawn(self, config.clone()));
+        }
+        autosave.config = Some(config);
+    }
+
+    // Stop autosaving, saving the pending interval first while monitoring.
+    // Returns false if autosave was not started.
+    pub fn stop_autosave(&self) -> bool {
+        let mut autosave = self.autosave.lock().unwrap();
+        let config = match autosave.config.take() {
+            Some(config) => config,
+            None => return false,
+        };
+
+        if let Some(writer) = autosave.writer.take() {
+            writer.stop();
+            self.autosave_now(&config);
+        }
+
+        true
+    }
+
+    pub fn autosave_config(&self) -> Option<AutosaveConfig> {
+        self.autosave.lock().unwrap().config.clone()
+    }
+
+    // The most recent failed autosave write, if any since start_autosave().
+    // Counts from failed writes are kept and go out with the next one.
+    pub fn autosave_error(&self) -> Option<AutosaveError> {
+        self.autosave_error.lock().unwrap().clone()
+    }
+
+    pub(crate) fn autosave_now(&self, config: &AutosaveConfig) {
+        if let Err(error) = self.save_log(&config.path, config.format) {
+            *self.autosave_error.lock().unwrap() = Some(AutosaveError {
+                timestamp: clock::now_secs(),
+                message: error.to_string(),
+            });
+        }
+    }
+
+    // Bring the writer up with monitoring if autosave is configured
+    fn resume_autosave(&self) {
+        let mut autosave = self.autosave.lock().unwrap();
+        if autosave.writer.is_none() {
+            if let Some(config) = autosave.config.clone() {
+                autosave.writer = Some(Writer::spawn(self, config));
+            }
+        }
+    }
+
+    // Take the writer down with monitoring and save the final interval
+    fn finish_autosave(&self) {
+        let mut autosave = self.autosave.lock().unwrap();
+        if let Some(writer) = autosave.writer.take() {
+            writer.stop();
+        }
+
+        if let Some(config) = &autosave.config {
+            self.autosave_now(config);
+        }
+    }
+
+    // Apply the TOML config at `path` (see Config) and apply it again
+    // whenever the file changes, whether or not this instance is
+    // monitoring, until stop_watching_config() or the next load_config().
+    // A change that fails to load leaves the settings as they were and
+    // shows in config_error(). Fails without watching if the file cannot
+    // be loaded now.
+    pub fn load_config(&self, path: &Path) -> Result<(), ConfigError> {
+        let mut watcher = self.config_watcher.lock().unwrap();
+        if let Some(running) = watcher.take() {
+            running.stop();
+        }
+
+        // Watch first, so an edit made while loading is not missed
+        let watching = config::Watcher::spawn(self, path.to_path_buf());
+        if let Err(error) = self.reload_config(path) {
+            watching.stop();
+            return Err(error);
+        }
+
+        *watcher = Some(watching);
+        Ok(())
+    }
+
+    // Keeps the settings loaded so far. Returns false if no file was watched.
+    pub fn stop_watching_config(&self) -> bool {
+        match self.config_watcher.lock().unwrap().take() {
+            Some(watcher) => {
+                watcher.stop();
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Why the config file last failed to load, if it has not loaded since
+    pub fn config_error(&self) -> Option<String> {
+        self.config_error.lock().unwrap().clone()
+    }
+
+    pub(crate) fn reload_config(&self, path: &Path) -> Result<(), ConfigError> {
+        let result = Config::load(path).map(|config| config.apply(self));
+        *self.config_error.lock().unwrap() = result.as_ref().err().map(ToString::to_string);
+        result
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl ActivityMonitor {
+    // Record this instance's sessions and idle periods in `store`, replacing
+    // any store attached before. Intervals are added by save_to_store().
+    pub fn attach_store(&self, store: Arc<ActivityStore>) {
+        self.detach_store();
+
+        let recorder = store.clone();
+        let callback_id = self.register_callback(move |event| {
+            if let Some(events::Transition::BecameActive { idle_since, active_at }) = event.transition() {
+                let _ = recorder.record_idle_period(IdlePeriod {
+                    start: idle_since,
+                    end: active_at,
+                });
+            }
+        });
+
+        *self.store.lock().unwrap() = Some((store, callback_id));
+
+        if self.is_monitoring() {
+            self.begin_store_session();
+        }
+    }
+
+    pub fn detach_store(&self) {
+        self.end_store_session();
+
+        let attached = self.store.lock().unwrap().take();
+        if let Some((_, callback_id)) = attached {
+            self.unregister_callback(callback_id);
+        }
+    }
+
+    pub fn store(&self) -> Option<Arc<ActivityStore>> {
+        self.store.lock().unwrap().as_ref().map(|(store, _)| store.clone())
+    }
+
+    // Add the current counts to the attached store as one interval and
+    // reset them. On failure the counts are kept for the next attempt.
+    pub fn save_to_store(&self) -> bool {
+        let store = match self.store() {
+            Some(store) => store,
+            None => return false,
+        };
+
+        let snapshot = self.take_snapshot_and_reset();
+        let record = activity_log::LogRecord::new(&snapshot, &self.session_id());
+
+        if store.record_interval(&record).is_ok() {
+            true
+        } else {
+            self.restore(&snapshot);
+            false
+        }
+    }
+
+    fn begin_store_session(&self) {
+        if let Some(store) = self.store() {
+            let _ = store.begin_session(&self.session_id(), activity_log::host(), clock::now_secs());
+        }
+    }
+
+    fn end_store_session(&self) {
+        if let Some(store) = self.store() {
+            // An idle period still open at the end is cut short here
+            if *self.idle.lock().unwrap() {
+                let _ = store.record_idle_period(IdlePeriod {
+                    start: self.last_genuine_activity.load(Ordering::SeqCst),
+                    end: crate::awake_now_secs(),
+                });
+            }
+
+            let _ = store.end_session(clock::now_secs());
+        }
+    }
+}
+
+// Unique within the host: process id, start time and a per-process sequence
+fn new_session_id() -> String {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+    format!(
+        "{}-{}-{}",
+        std::process::id(),
+        clock::now_secs(),
+        SEQUENCE.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+impl Drop for ActivityMonitor {
+    fn drop(&mut self) {
+        // Stop first: taking the sources down may wait for a source thread
+        // that is dispatching to this instance under the read lock
+        self.stop();
+        self.stop_watching_config();
+        self.callbacks.lock().unwrap().clear();
+
+        let this = self as *const ActivityMonitor;
+        MONITORS.write().unwrap().retain(|entry| entry.0 != this);
+    }
+}
+
+pub fn default_monitor() -> &'static ActivityMonitor {
+    &DEFAULT_MONITOR
+}
+
+// Apply `f` to every instance that is currently monitoring
+fn for_each_monitoring(f: impl Fn(&ActivityMonitor)) {
+    let monitors = MONITORS.read().unwrap();
+    for entry in monitors.iter() {
+        let monitor = unsafe { &*entry.0 };
+        if monitor.is_monitoring() {
+            f(monitor);
+        }
+    }
+}
+
+// Whether any instance that is monitoring keeps a heatmap
+pub(crate) fn any_heatmap() -> bool {
+    let monitors = MONITORS.read().unwrap();
+    monitors.iter().any(|entry| {
+        let monitor = unsafe { &*entry.0 };
+        monitor.is_monitoring() && monitor.heatmap.lock().unwrap().is_some()
+    })
+}
+
+// Internal functions for the event dispatcher to call
+pub(crate) fn increment_keyboard() {
+    let now = clock::now_secs();
+    for_each_monitoring(|monitor| {
+        let _gate = monitor.gate.read().unwrap();
+        monitor.keyboard_count.fetch_add(1, Ordering::SeqCst);
+        monitor.notify_input(events::ACTIVITY_EVENT_KEYBOARD, now);
+    });
+}
+
+pub(crate) fn increment_mouse(counts: MouseCounts, travel: PointerTravel, position: Option<(f64, f64)>) {
+    let now = clock::now_secs();
+    let clicks = counts.left_clicks + counts.right_clicks + counts.middle_clicks + counts.extra_clicks;
+    for_each_monitoring(|monitor| {
+        let _gate = monitor.gate.read().unwrap();
+        monitor.mouse_count.fetch_add(1, Ordering::SeqCst);
+        monitor.mouse_counts.lock().unwrap().add(&counts);
+        monitor.pointer_travel.lock().unwrap().add(&travel);
+        if let (Some(heatmap), Some(position)) = (&mut *monitor.heatmap.lock().unwrap(), position) {
+            heatmap.record(position, counts.moves, clicks);
+        }
+        monitor.notify_input(events::ACTIVITY_EVENT_MOUSE, now);
+    });
+}
+
+// Record genuine activity at `timestamp` (seconds since the epoch),
+// without moving any instance's timestamp backwards
+pub(crate) fn record_genuine_activity_at(timestamp: u64) {
+    for_each_monitoring(|monitor| monitor.record_activity(timestamp));
+}
+
+// Mark instances idle once they pass their threshold. Called periodically
+// by the event dispatcher; the state getters also check on demand.
+pub(crate) fn check_idle() {
+    for_each_monitoring(|monitor| monitor.check_idle());
+}
+
+// Move every instance's last activity forward, e.g. by time spent asleep
+pub(crate) fn shift_genuine_activity(seconds: u64) {
+    for_each_monitoring(|monitor| {
+        monitor.last_genuine_activity.fetch_add(seconds, Ordering::SeqCst);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use crate::events::Transition;
+    use crate::source::InputEvent;
+    use crate::testing::{self, simulated, START};
+
+    #[test]
+    fn snapshots_keep_the_idle_baseline() {
+        let _isolated = testing::isolate();
+        let (clock, source, monitor) = simulated();
+        source.emit(InputEvent::Mouse);
+
+        for _ in 0..10 {
+            clock.advance(Duration::from_secs(60));
+            let snapshot = monitor.take_snapshot_and_reset();
+            assert_eq!(snapshot.interval_start, clock::now_secs() - 60);
+        }
+
+        assert_eq!(monitor.idle_time(), 600);
+        assert_eq!(monitor.activity_state(), ActivityState::Idle { since: START });
+
+        monitor.reset_counters();
+        assert_eq!(monitor.idle_time(), 600);
+        assert_eq!(monitor.snapshot().idle_time, 600);
+    }
+
+    #[test]
+    fn idle_transitions_at_the_threshold() {
+        let _isolated = testing::isolate();
+        let (clock, source, monitor) = simulated();
+        let (sender, events) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        monitor.register_callback(move |event| {
+            if let Some(transition) = event.transition() {
+                let _ = sender.lock().unwrap().send(transition);
+            }
+        });
+
+        clock.advance(Duration::from_secs(299));
+        assert_eq!(monitor.activity_state(), ActivityState::Active);
+
+        // Nobody polls: the periodic check makes the transition
+        clock.advance(Duration::from_secs(1));
+        let became_idle = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(became_idle, Transition::BecameIdle { idle_since: START });
+        assert_eq!(monitor.activity_state(), ActivityState::Idle { since: START });
+
+        clock.advance(Duration::from_secs(100));
+        source.emit(InputEvent::Mouse);
+        let became_active = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(became_active, Transition::BecameActive { idle_since: START, active_at: START + 400 });
+        assert_eq!(monitor.activity_state(), ActivityState::Active);
+        assert_eq!(monitor.idle_time(), 0);
+    }
+}
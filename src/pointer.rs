@@ -0,0 +1,377 @@
+// activity_monitor/src/pointer.rs
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::source::PointerEvent;
+
+// Where the pointer is, shared by every source like the held keys in
+// debounce. Positions are in pixels of the whole desktop, y growing down.
+struct Tracking {
+    // Last position a source reported
+    position: Option<(f64, f64)>,
+    configured_dpi: Option<f64>,
+    detected_dpi: Option<f64>,
+}
+
+static TRACKING: Mutex<Tracking> = Mutex::new(Tracking {
+    position: None,
+    configured_dpi: None,
+    detected_dpi: None,
+});
+
+// Whether pointer travel is measured, for every monitor
+static TRAVEL_ENABLED: AtomicBool = AtomicBool::new(true);
+
+const MM_PER_INCH: f64 = 25.4;
+
+// How far the pointer went. Millimetres only cover travel while the
+// screen's DPI was known, see screen_dpi().
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PointerTravel {
+    pub pixels: f64,
+    pub millimetres: f64,
+}
+
+impl PointerTravel {
+    pub fn add(&mut self, other: &PointerTravel) {
+        self.pixels += other.pixels;
+        self.millimetres += other.millimetres;
+    }
+}
+
+// Measure how far the pointer travels (the default). Turned off, travel
+// stays where it is and sources that must ask the display where the
+// pointer is skip that while no monitor keeps a heatmap either.
+pub fn set_travel_enabled(enabled: bool) {
+    TRAVEL_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn travel_enabled() -> bool {
+    TRAVEL_ENABLED.load(Ordering::SeqCst)
+}
+
+// Whether anything uses pointer positions, for sources where getting one
+// costs more than reading the event
+pub(crate) fn positions_wanted() -> bool {
+    travel_enabled() || crate::monitor::any_heatmap()
+}
+
+// Pixels per inch of the screen, for travel in millimetres. None (the
+// default) goes back to what the running source detected, if anything.
+// Takes effect from the next pointer event.
+pub fn set_screen_dpi(dpi: Option<f64>) {
+    TRACKING.lock().unwrap().configured_dpi = dpi.filter(|dpi| dpi.is_finite() && *dpi > 0.0);
+}
+
+pub fn screen_dpi() -> Option<f64> {
+    let tracking = TRACKING.lock().unwrap();
+    tracking.configured_dpi.or(tracking.detected_dpi)
+}
+
+// For sources that can tell from the display; None when they cannot
+pub(crate) fn report_screen_dpi(dpi: Option<f64>) {
+    TRACKING.lock().unwrap().detected_dpi = dpi.filter(|dpi| dpi.is_finite() && *dpi > 0.0);
+}
+
+// How far an event moved the pointer, and where the pointer is: the
+// event's position, or the last one reported for events without one
+pub(crate) fn track(event: &PointerEvent) -> (PointerTravel, Option<(f64, f64)>) {
+    let mut tracking = TRACKING.lock().unwrap();
+    let pixels = match (event.position, tracking.position) {
+        _ if !travel_enabled() => 0.0,
+        (Some((x, y)), Some((last_x, last_y))) => (x - last_x).hypot(y - last_y),
+        (Some(_), None) => 0.0,
+        (None, _) => event.delta.0.hypot(event.delta.1),
+    };
+
+    if event.position.is_some() {
+        tracking.position = event.position;
+    }
+
+    let dpi = tracking.configured_dpi.or(tracking.detected_dpi);
+    let travel = PointerTravel {
+        pixels,
+        millimetres: dpi.map_or(0.0, |dpi| pixels / dpi * MM_PER_INCH),
+    };
+    (travel, tracking.position)
+}
+
+// Forget the last position; the DPI settings stay
+pub(crate) fn reset() {
+    TRACKING.lock().unwrap().position = None;
+}
+
+// Values for the FFI heatmap functions
+pub const HEATMAP_LAYER_POSITIONS: u32 = 0;
+pub const HEATMAP_LAYER_CLICKS: u32 = 1;
+pub const HEATMAP_FORMAT_MATRIX: u32 = 0;
+pub const HEATMAP_FORMAT_PGM: u32 = 1;
+
+// Which count of a heatmap to export
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeatmapLayer {
+    // Pointer motion events
+    Positions,
+    // Button presses
+    Clicks,
+}
+
+impl HeatmapLayer {
+    pub fn from_ffi(layer: u32) -> Option<HeatmapLayer> {
+        match layer {
+            HEATMAP_LAYER_POSITIONS => Some(HeatmapLayer::Positions),
+            HEATMAP_LAYER_CLICKS => Some(HeatmapLayer::Clicks),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeatmapFormat {
+    // A `#` comment line with the origin and cell size, then one line of
+    // space-separated counts per row of cells, from the top
+    Matrix,
+    // Binary 8-bit greyscale PGM, one pixel per cell, white at the
+    // busiest cell
+    Pgm,
+}
+
+impl HeatmapFormat {
+    pub fn from_ffi(format: u32) -> Option<HeatmapFormat> {
+        match format {
+            HEATMAP_FORMAT_MATRIX => Some(HeatmapFormat::Matrix),
+            HEATMAP_FORMAT_PGM => Some(HeatmapFormat::Pgm),
+            _ => None,
+        }
+    }
+}
+
+pub const DEFAULT_HEATMAP_CELL_SIZE: u32 = 64;
+// Most cells an exported grid has, see Heatmap::matrix()
+pub const MAX_HEATMAP_MATRIX_CELLS: usize = 1 << 20;
+
+// Pointer positions and clicks counted on a coarse grid of square cells.
+// The grid grows to wherever the pointer goes, including screens left of
+// or above the main one, so the screen size need not be known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Heatmap {
+    cell_size: u32,
+    // Positions and clicks per cell, by column and row
+    cells: HashMap<(i64, i64), [u64; 2]>,
+}
+
+// The counts of one layer over the smallest rectangle of cells that covers
+// everything recorded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeatmapMatrix {
+    // Desktop pixel at the top left of the first cell
+    pub origin_x: i64,
+    pub origin_y: i64,
+    // The heatmap's, or a multiple of it for a coarsened grid
+    pub cell_size: u64,
+    pub columns: usize,
+    pub rows: usize,
+    // Row by row from the top
+    pub values: Vec<u64>,
+}
+
+impl Heatmap {
+    // Cells of `cell_size` pixels square, at least 1
+    pub fn new(cell_size: u32) -> Heatmap {
+        Heatmap {
+            cell_size: cell_size.max(1),
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn cell_size(&self) -> u32 {
+        self.cell_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn record(&mut self, (x, y): (f64, f64), positions: u64, clicks: u64) {
+        if positions == 0 && clicks == 0 {
+            return;
+        }
+
+        let size = self.cell_size as f64;
+        let cell = ((x / size).floor() as i64, (y / size).floor() as i64);
+        let counts = self.cells.entry(cell).or_default();
+        counts[0] += positions;
+        counts[1] += clicks;
+    }
+
+    // Grids past MAX_HEATMAP_MATRIX_CELLS (a stray position far off screen)
+    // are coarsened instead: cells merge in blocks, the cell size doubling
+    // until the grid fits
+    pub fn matrix(&self, layer: HeatmapLayer) -> HeatmapMatrix {
+        let index = match layer {
+            HeatmapLayer::Positions => 0,
+            HeatmapLayer::Clicks => 1,
+        };
+
+        let mut scale: i64 = 1;
+        loop {
+            let blocks = || self.cells.keys().map(|(column, row)| (column.div_euclid(scale), row.div_euclid(scale)));
+            let (Some(left), Some(top)) = (blocks().map(|(column, _)| column).min(), blocks().map(|(_, row)| row).min()) else {
+                return HeatmapMatrix {
+                    cell_size: self.cell_size as u64,
+                    ..HeatmapMatrix::default()
+                };
+            };
+            let right = blocks().map(|(column, _)| column).max().unwrap_or(left);
+            let bottom = blocks().map(|(_, row)| row).max().unwrap_or(top);
+
+            let columns = right as i128 - left as i128 + 1;
+            let rows = bottom as i128 - top as i128 + 1;
+            if columns.checked_mul(rows).is_none_or(|cells| cells > MAX_HEATMAP_MATRIX_CELLS as i128) {
+                scale *= 2;
+                continue;
+            }
+
+            let (columns, rows) = (columns as usize, rows as usize);
+            let mut values = vec![0; columns * rows];
+            for ((column, row), counts) in &self.cells {
+                let (column, row) = (column.div_euclid(scale), row.div_euclid(scale));
+                values[(row - top) as usize * columns + (column - left) as usize] += counts[index];
+            }
+
+            let cell_size = (self.cell_size as i64).saturating_mul(scale);
+            return HeatmapMatrix {
+                origin_x: left.saturating_mul(cell_size),
+                origin_y: top.saturating_mul(cell_size),
+                cell_size: cell_size as u64,
+                columns,
+                rows,
+                values,
+            };
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, layer: HeatmapLayer, format: HeatmapFormat) -> io::Result<()> {
+        let matrix = self.matrix(layer);
+
+        match format {
+            HeatmapFormat::Matrix => {
+                writeln!(
+                    writer,
+                    "# origin {} {}, cell size {}, {} columns, {} rows",
+                    matrix.origin_x, matrix.origin_y, matrix.cell_size, matrix.columns, matrix.rows
+                )?;
+                for row in matrix.values.chunks(matrix.columns.max(1)) {
+                    let line: Vec<String> = row.iter().map(ToString::to_string).collect();
+                    writeln!(writer, "{}", line.join(" "))?;
+                }
+            },
+            HeatmapFormat::Pgm => {
+                write!(writer, "P5\n{} {}\n255\n", matrix.columns, matrix.rows)?;
+                // Rounded up, so every cell the pointer visited shows
+                let busiest = matrix.values.iter().copied().max().unwrap_or(0).max(1);
+                let pixels: Vec<u8> = matrix
+                    .values
+                    .iter()
+                    .map(|value| (*value as u128 * 255).div_ceil(busiest as u128) as u8)
+                    .collect();
+                writer.write_all(&pixels)?;
+            },
+        }
+
+        writer.flush()
+    }
+
+    // Replaces the file at `path`
+    pub fn save(&self, path: &Path, layer: HeatmapLayer, format: HeatmapFormat) -> io::Result<()> {
+        self.write(&mut BufWriter::new(File::create(path)?), layer, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::ActivityMonitor;
+    use crate::source::{register_source, Capabilities, InputEvent, MockSource};
+
+    #[test]
+    fn grid_covers_recorded_cells() {
+        let mut heatmap = Heatmap::new(100);
+        heatmap.record((-50.0, 10.0), 1, 0);
+        heatmap.record((250.0, 120.0), 2, 1);
+        heatmap.record((299.0, 199.0), 1, 0);
+
+        let positions = heatmap.matrix(HeatmapLayer::Positions);
+        assert_eq!((positions.origin_x, positions.origin_y), (-100, 0));
+        assert_eq!((positions.columns, positions.rows), (4, 2));
+        assert_eq!(positions.values, vec![1, 0, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(heatmap.matrix(HeatmapLayer::Clicks).values, vec![0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let mut matrix = Vec::new();
+        heatmap.write(&mut matrix, HeatmapLayer::Positions, HeatmapFormat::Matrix).unwrap();
+        assert_eq!(
+            String::from_utf8(matrix).unwrap(),
+            "# origin -100 0, cell size 100, 4 columns, 2 rows\n1 0 0 0\n0 0 0 3\n"
+        );
+
+        let mut pgm = Vec::new();
+        heatmap.write(&mut pgm, HeatmapLayer::Positions, HeatmapFormat::Pgm).unwrap();
+        assert_eq!(pgm, b"P5\n4 2\n255\n\x55\0\0\0\0\0\0\xff");
+    }
+
+    #[test]
+    fn positions_are_wanted_for_travel_or_a_heatmap() {
+        let _isolated = crate::testing::isolate();
+        let source = MockSource::new(Capabilities::MOUSE);
+        register_source(Box::new(source.clone()));
+        let monitor = ActivityMonitor::new();
+        monitor.start();
+
+        let moved = |x: f64| PointerEvent { moved: true, position: Some((x, 0.0)), ..PointerEvent::default() };
+        track(&moved(0.0));
+        assert_eq!(track(&moved(30.0)).0.pixels, 30.0);
+        assert!(positions_wanted());
+
+        set_travel_enabled(false);
+        assert_eq!(track(&moved(70.0)), (PointerTravel::default(), Some((70.0, 0.0))));
+        assert!(!positions_wanted());
+
+        monitor.enable_heatmap(10);
+        assert!(positions_wanted());
+        source.emit(InputEvent::Pointer(moved(75.0)));
+        assert_eq!(monitor.heatmap().unwrap().matrix(HeatmapLayer::Positions).values, vec![1]);
+        assert_eq!(monitor.pointer_travel(), PointerTravel::default());
+
+        monitor.stop();
+        assert!(!positions_wanted());
+    }
+
+    #[test]
+    fn far_off_positions_coarsen_the_grid() {
+        let mut heatmap = Heatmap::new(1);
+        heatmap.record((0.0, 0.0), 1, 0);
+        heatmap.record((1.0, 1.0), 1, 0);
+        heatmap.record((2000.0, 2000.0), 1, 1);
+
+        // 2001 by 2001 cells would be too many; blocks of 2 by 2 fit
+        let positions = heatmap.matrix(HeatmapLayer::Positions);
+        assert_eq!((positions.columns, positions.rows, positions.cell_size), (1001, 1001, 2));
+        assert_eq!(positions.values[0], 2);
+        assert_eq!(positions.values[1001 * 1001 - 1], 1);
+        assert_eq!(positions.values.iter().sum::<u64>(), 3);
+
+        // Positions nowhere near each other still fit
+        heatmap.record((-1e300, 1e300), 1, 0);
+        heatmap.record((f64::MAX, f64::MIN), 1, 0);
+        let positions = heatmap.matrix(HeatmapLayer::Positions);
+        assert!(positions.columns * positions.rows <= MAX_HEATMAP_MATRIX_CELLS);
+        assert_eq!(positions.values.iter().sum::<u64>(), 5);
+    }
+}
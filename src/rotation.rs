@@ -0,0 +1,463 @@
+// activity_monitor/src/rotation.rs
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::clock;
+
+// Added to a log's path for the file its writers lock, see
+// activity_log::append()
+pub(crate) const LOCK_EXTENSION: &str = ".lock";
+
+// Values accepted by set_log_rotation()
+pub const LOG_ROTATE_NONE: u32 = 0;
+pub const LOG_ROTATE_SIZE: u32 = 1;
+pub const LOG_ROTATE_DAILY: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotateOn {
+    // Once the log has grown to this many bytes
+    Size(u64),
+    // On the first write of a new local calendar day
+    Daily,
+}
+
+// How a log file is split into segments. The active file keeps its path;
+// rotated segments are renamed next to it as `<stem>.<stamp>.<ext>`, where
+// the stamp is the local time of the segment's last write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogRotation {
+    pub rotate_on: RotateOn,
+    // How many rotated segments to keep, newest first
+    pub keep_files: Option<usize>,
+    // Delete rotated segments last written longer ago than this (seconds)
+    pub max_age_secs: Option<u64>,
+    // Gzip rotated segments, adding `.gz` to their names
+    pub compress: bool,
+}
+
+impl LogRotation {
+    // None for LOG_ROTATE_NONE and invalid settings. Zero limits mean none.
+    pub fn from_ffi(mode: u32, max_bytes: u64, keep_files: u32, max_age_secs: u64, compress: bool) -> Option<LogRotation> {
+        let rotate_on = match mode {
+            LOG_ROTATE_SIZE if max_bytes > 0 => RotateOn::Size(max_bytes),
+            LOG_ROTATE_DAILY => RotateOn::Daily,
+            _ => return None,
+        };
+
+        Some(LogRotation {
+            rotate_on,
+            keep_files: (keep_files > 0).then_some(keep_files as usize),
+            max_age_secs: (max_age_secs > 0).then_some(max_age_secs),
+            compress,
+        })
+    }
+}
+
+// Move the log at `path` aside if `rotation` says it is due, so the next
+// write starts a new file with its own header. Only a failed rename is an
+// error; compression and retention are best effort.
+pub(crate) fn rotate_if_due(path: &Path, rotation: &LogRotation) -> io::Result<()> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    if metadata.len() == 0 {
+        return Ok(());
+    }
+
+    let last_write = local_time(metadata.modified().unwrap_or(UNIX_EPOCH));
+    let due = match rotation.rotate_on {
+        RotateOn::Size(max_bytes) => metadata.len() >= max_bytes,
+        RotateOn::Daily => last_write.date_naive() != local_time(now()).date_naive(),
+    };
+    if !due {
+        return Ok(());
+    }
+
+    let stamp = match rotation.rotate_on {
+        RotateOn::Size(_) => last_write.format("%Y-%m-%dT%H%M%S").to_string(),
+        RotateOn::Daily => last_write.format("%Y-%m-%d").to_string(),
+    };
+    let rotated = unused_segment_path(path, &stamp);
+    fs::rename(path, &rotated)?;
+
+    if rotation.compress {
+        let _ = compress(&rotated);
+    }
+    remove_expired(path, rotation);
+
+    Ok(())
+}
+
+// `<stem>.<stamp>.<ext>`, with a counter after the stamp if that name is
+// taken with or without `.gz`
+fn unused_segment_path(path: &Path, stamp: &str) -> PathBuf {
+    let (stem, extension) = split_name(path);
+
+    let mut sequence = 0;
+    loop {
+        let mut name = format!("{stem}.{stamp}");
+        if sequence > 0 {
+            name.push_str(&format!("-{sequence}"));
+        }
+        name.push_str(&extension);
+
+        let candidate = path.with_file_name(&name);
+        let compressed = path.with_file_name(format!("{name}.gz"));
+        if !candidate.exists() && !compressed.exists() {
+            return candidate;
+        }
+        sequence += 1;
+    }
+}
+
+// The file name before and from its last dot ("activity", ".csv")
+fn split_name(path: &Path) -> (String, String) {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (stem, extension)
+}
+
+// Replace `path` with `path.gz`, keeping its modification time for retention
+fn compress(path: &Path) -> io::Result<()> {
+    let mut compressed_name = path.as_os_str().to_owned();
+    compressed_name.push(".gz");
+    let compressed = PathBuf::from(compressed_name);
+
+    let result = (|| {
+        let input = File::open(path)?;
+        let modified = input.metadata()?.modified()?;
+
+        let mut encoder = GzEncoder::new(BufWriter::new(File::create(&compressed)?), Compression::default());
+        io::copy(&mut BufReader::new(input), &mut encoder)?;
+
+        let output = encoder.finish()?.into_inner().map_err(|error| error.into_error())?;
+        output.sync_all()?;
+        output.set_modified(modified)
+    })();
+
+    match result {
+        Ok(()) => fs::remove_file(path),
+        Err(error) => {
+            let _ = fs::remove_file(&compressed);
+            Err(error)
+        },
+    }
+}
+
+// Apply the retention limits to the rotated segments of `path`
+fn remove_expired(path: &Path, rotation: &LogRotation) {
+    let mut segments = rotated_segments(path);
+    // Newest first
+    segments.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+
+    let max_age = rotation.max_age_secs.map(Duration::from_secs);
+    let now = now();
+
+    for (index, (segment, modified)) in segments.iter().enumerate() {
+        let too_many = rotation.keep_files.is_some_and(|keep| index >= keep);
+        let too_old = max_age.is_some_and(|max_age| {
+            now.duration_since(*modified).is_ok_and(|age| age > max_age)
+        });
+
+        if too_many || too_old {
+            let _ = fs::remove_file(segment);
+        }
+    }
+}
+
+// The rotated segments of `path` oldest first, then `path` itself if it
+// exists, so reading them in order yields the log in write order
+pub fn log_segments(path: &Path) -> Vec<PathBuf> {
+    let mut segments = rotated_segments(path);
+    segments.sort_by_key(|(_, modified)| *modified);
+
+    let mut paths: Vec<PathBuf> = segments.into_iter().map(|(segment, _)| segment).collect();
+    if path.exists() {
+        paths.push(path.to_path_buf());
+    }
+    paths
+}
+
+// Rotated segments of `path` and when each was last written
+fn rotated_segments(path: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let (stem, extension) = split_name(path);
+    let prefix = format!("{stem}.");
+    let compressed_extension = format!("{extension}.gz");
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Writers hold `<path>.lock` for good; it is never a segment
+            if name.ends_with(LOCK_EXTENSION) {
+                return false;
+            }
+            let rest = match name.strip_prefix(&prefix) {
+                Some(rest) => rest,
+                None => return false,
+            };
+            // A stamp must sit between the prefix and the extension
+            [&compressed_extension, &extension].iter().any(|extension| {
+                rest.strip_suffix(extension.as_str()).is_some_and(is_stamp)
+            })
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((entry.path(), modified))
+        })
+        .collect()
+}
+
+// "2026-10-16" or "2026-10-16T143000", optionally followed by "-<n>"
+fn is_stamp(stamp: &str) -> bool {
+    let stamp = match stamp.rsplit_once('-') {
+        Some((head, sequence)) if head.len() >= 10 && sequence.parse::<u32>().is_ok() => head,
+        _ => stamp,
+    };
+
+    NaiveDate::parse_from_str(stamp, "%Y-%m-%d").is_ok()
+        || NaiveDateTime::parse_from_str(stamp, "%Y-%m-%dT%H%M%S").is_ok()
+}
+
+fn now() -> SystemTime {
+    UNIX_EPOCH + clock::now()
+}
+
+fn local_time(time: SystemTime) -> DateTime<Local> {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    Local.timestamp_opt(seconds, 0).single().unwrap_or_else(Local::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::testing::TempDir;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn write(path: &Path, contents: &str, modified: SystemTime) {
+        fs::write(path, contents).unwrap();
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32) -> SystemTime {
+        let time = Local.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap();
+        UNIX_EPOCH + Duration::from_secs(time.timestamp() as u64)
+    }
+
+    fn rotation(rotate_on: RotateOn) -> LogRotation {
+        LogRotation {
+            rotate_on,
+            keep_files: None,
+            max_age_secs: None,
+            compress: false,
+        }
+    }
+
+    #[test]
+    fn size_rotation_starts_at_the_limit() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        let modified = local(2024, 6, 14, 9);
+
+        write(&path, "0123456789", modified);
+        rotate_if_due(&path, &rotation(RotateOn::Size(11))).unwrap();
+        assert_eq!(names(&dir), ["activity.csv"]);
+
+        rotate_if_due(&path, &rotation(RotateOn::Size(10))).unwrap();
+        assert_eq!(names(&dir), ["activity.2024-06-14T090000.csv"]);
+        assert_eq!(fs::read_to_string(dir.join("activity.2024-06-14T090000.csv")).unwrap(), "0123456789");
+
+        // Same stamp again: a counter keeps both
+        write(&path, "abcdefghij", modified);
+        rotate_if_due(&path, &rotation(RotateOn::Size(10))).unwrap();
+        assert_eq!(names(&dir), ["activity.2024-06-14T090000-1.csv", "activity.2024-06-14T090000.csv"]);
+
+        // Nothing to rotate
+        rotate_if_due(&path, &rotation(RotateOn::Size(10))).unwrap();
+        write(&path, "", modified);
+        rotate_if_due(&path, &rotation(RotateOn::Size(0))).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn daily_rotation_waits_for_a_new_day() {
+        // Compares against the core clock, which other tests replace
+        let _isolated = crate::testing::isolate();
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+
+        write(&path, "today\n", SystemTime::now());
+        rotate_if_due(&path, &rotation(RotateOn::Daily)).unwrap();
+        assert_eq!(names(&dir), ["activity.csv"]);
+
+        write(&path, "yesterday\n", local(2024, 6, 14, 23));
+        rotate_if_due(&path, &rotation(RotateOn::Daily)).unwrap();
+        assert_eq!(names(&dir), ["activity.2024-06-14.csv"]);
+    }
+
+    #[test]
+    fn compressed_segments_keep_contents_and_time() {
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        let modified = local(2024, 6, 14, 23);
+        write(&path, "timestamp\n1\n", modified);
+
+        let rotation = LogRotation {
+            compress: true,
+            ..rotation(RotateOn::Daily)
+        };
+        rotate_if_due(&path, &rotation).unwrap();
+        assert_eq!(names(&dir), ["activity.2024-06-14.csv.gz"]);
+
+        let segment = dir.join("activity.2024-06-14.csv.gz");
+        let mut contents = String::new();
+        GzDecoder::new(File::open(&segment).unwrap()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "timestamp\n1\n");
+        assert_eq!(fs::metadata(&segment).unwrap().modified().unwrap(), modified);
+
+        // The name is taken compressed too
+        write(&path, "timestamp\n2\n", modified);
+        rotate_if_due(&path, &rotation).unwrap();
+        assert_eq!(names(&dir), ["activity.2024-06-14-1.csv.gz", "activity.2024-06-14.csv.gz"]);
+    }
+
+    // Rotated segments last written 2, 3, 4 and 5 days ago, the log itself
+    // a day ago and due for rotation, and files that are not segments
+    fn aged_segments() -> (TempDir, PathBuf) {
+        let dir = TempDir::new();
+        let now = SystemTime::now();
+        for days in 2..=5 {
+            write(&dir.join(format!("activity.2020-01-0{days}.csv")), "", now - DAY * days);
+        }
+        write(&dir.join("activity.notes.csv"), "", now - DAY * 10);
+        write(&dir.join("other.2020-01-01.csv"), "", now - DAY * 10);
+
+        let path = dir.join("activity.csv");
+        write(&path, "0123456789", now - DAY);
+        (dir, path)
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_segments() {
+        let (dir, path) = aged_segments();
+        let rotation = LogRotation {
+            keep_files: Some(3),
+            ..rotation(RotateOn::Size(1))
+        };
+        rotate_if_due(&path, &rotation).unwrap();
+
+        let rotated = log_segments(&path);
+        assert_eq!(rotated.len(), 3);
+        assert_eq!(rotated[0], dir.join("activity.2020-01-03.csv"));
+        assert_eq!(rotated[1], dir.join("activity.2020-01-02.csv"));
+        assert!(dir.join("activity.notes.csv").exists());
+        assert!(dir.join("other.2020-01-01.csv").exists());
+    }
+
+    #[test]
+    fn retention_drops_old_segments() {
+        let _isolated = crate::testing::isolate();
+        let (dir, path) = aged_segments();
+        let rotation = LogRotation {
+            max_age_secs: Some((DAY * 7 / 2).as_secs()),
+            ..rotation(RotateOn::Size(1))
+        };
+        rotate_if_due(&path, &rotation).unwrap();
+
+        assert!(!dir.join("activity.2020-01-04.csv").exists());
+        assert!(!dir.join("activity.2020-01-05.csv").exists());
+        assert_eq!(log_segments(&path).len(), 3);
+        assert!(dir.join("activity.notes.csv").exists());
+    }
+
+    #[test]
+    fn segments_are_listed_oldest_first_then_the_log() {
+        let (dir, path) = aged_segments();
+        let compressed = dir.join("activity.2020-01-09.csv.gz");
+        write(&compressed, "", SystemTime::now() - DAY * 6);
+
+        assert_eq!(
+            log_segments(&path),
+            vec![
+                compressed,
+                dir.join("activity.2020-01-05.csv"),
+                dir.join("activity.2020-01-04.csv"),
+                dir.join("activity.2020-01-03.csv"),
+                dir.join("activity.2020-01-02.csv"),
+                path.clone(),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(log_segments(&path).len(), 5);
+        assert!(log_segments(&dir.join("missing").join("activity.csv")).is_empty());
+    }
+
+    #[test]
+    fn lock_files_are_not_segments() {
+        let _isolated = crate::testing::isolate();
+        let (dir, path) = aged_segments();
+        let old = SystemTime::now() - DAY * 30;
+        write(&dir.join("activity.csv.lock"), "", old);
+        // Lock files of logs without an extension
+        let bare = dir.join("activity");
+        write(&dir.join("activity.lock"), "", old);
+        write(&dir.join("activity.2020-01-01.lock"), "", old);
+
+        assert!(!log_segments(&path).iter().any(|segment| segment.to_string_lossy().ends_with(".lock")));
+        assert!(log_segments(&bare).is_empty());
+
+        let rotation = LogRotation {
+            keep_files: Some(1),
+            max_age_secs: Some(1),
+            ..rotation(RotateOn::Size(1))
+        };
+        rotate_if_due(&path, &rotation).unwrap();
+        write(&bare, "0123456789", SystemTime::now());
+        rotate_if_due(&bare, &rotation).unwrap();
+
+        for lock in ["activity.csv.lock", "activity.lock", "activity.2020-01-01.lock"] {
+            assert!(dir.join(lock).exists(), "{lock}");
+        }
+    }
+
+    #[test]
+    fn stamps() {
+        for stamp in ["2026-10-16", "2026-10-16T143000", "2026-10-16-2", "2026-10-16T143000-12"] {
+            assert!(is_stamp(stamp), "{stamp}");
+        }
+        for stamp in ["", "notes", "2026-13-01", "2026-10-16T1430", "2026-10-16-", "2026-10-16-x", "16-10-2026"] {
+            assert!(!is_stamp(stamp), "{stamp}");
+        }
+    }
+}
@@ -0,0 +1,241 @@
+// activity_monitor/src/simulated.rs
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::clock::{Clock, SimulatedClock};
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent};
+
+struct Replay {
+    // Script times are offsets from the clock when the source first started
+    origin: Option<Duration>,
+    pending: VecDeque<(Duration, InputEvent)>,
+}
+
+// Replays a scripted list of (offset, event) pairs against a
+// SimulatedClock. Nothing happens on its own: the caller advances time and
+// every event that falls due is delivered on the calling thread, with the
+// clock set to the event's exact time. Install the same clock with
+// set_clock() so the core sees that time too. Clones share state.
+#[derive(Clone)]
+pub struct SimulatedSource {
+    clock: SimulatedClock,
+    running: Arc<AtomicBool>,
+    replay: Arc<Mutex<Replay>>,
+}
+
+impl SimulatedSource {
+    // Events must be sorted by offset; ties keep their script order
+    pub fn new(clock: SimulatedClock, script: Vec<(Duration, InputEvent)>) -> SimulatedSource {
+        SimulatedSource {
+            clock,
+            running: Arc::new(AtomicBool::new(false)),
+            replay: Arc::new(Mutex::new(Replay {
+                origin: None,
+                pending: script.into(),
+            })),
+        }
+    }
+
+    pub fn clock(&self) -> &SimulatedClock {
+        &self.clock
+    }
+
+    // Time since the source first started, on the simulated clock
+    pub fn elapsed(&self) -> Duration {
+        match self.replay.lock().unwrap().origin {
+            Some(origin) => self.clock.now().saturating_sub(origin),
+            None => Duration::from_secs(0),
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.replay.lock().unwrap().pending.len()
+    }
+
+    // Move the clock forward by `by`, delivering every event due on the
+    // way. Returns how many events were delivered. Before the first start
+    // only the clock moves; while stopped, due events are discarded like
+    // input that happens while no hook is installed.
+    pub fn advance(&self, by: Duration) -> usize {
+        let target = self.clock.now() + by;
+        let mut delivered = 0;
+
+        loop {
+            let next = {
+                let mut replay = self.replay.lock().unwrap();
+                let origin = match replay.origin {
+                    Some(origin) => origin,
+                    None => break,
+                };
+                let due = match replay.pending.front() {
+                    Some((offset, _)) => origin + *offset <= target,
+                    None => false,
+                };
+                if !due {
+                    break;
+                }
+                let (offset, event) = replay.pending.pop_front().unwrap();
+                (origin + offset, event)
+            };
+
+            let (at, event) = next;
+            if at > self.clock.now() {
+                self.clock.set(at);
+            }
+
+            // Dispatch without holding the lock so the core may call back
+            // into this source (e.g. to reset it)
+            if self.running.load(Ordering::SeqCst) {
+                dispatch(event);
+                delivered += 1;
+            }
+        }
+
+        self.clock.set(target);
+        delivered
+    }
+
+    // Deliver the rest of the script, leaving the clock at the last event
+    pub fn replay_all(&self) -> usize {
+        let last = {
+            let replay = self.replay.lock().unwrap();
+            match (replay.origin, replay.pending.back()) {
+                (Some(origin), Some((offset, _))) => Some(origin + *offset),
+                _ => None,
+            }
+        };
+
+        match last {
+            Some(last) => self.advance(last.saturating_sub(self.clock.now())),
+            None => 0,
+        }
+    }
+}
+
+impl ActivitySource for SimulatedSource {
+    fn name(&self) -> &str {
+        "simulated"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::KEYBOARD | Capabilities::MOUSE | Capabilities::SESSION
+    }
+
+    fn start(&mut self) -> bool {
+        self.replay.lock().unwrap().origin.get_or_insert(self.clock.now());
+        self.running.store(true, Ordering::SeqCst);
+        true
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::activity_log::LogFormat;
+    use crate::clock;
+    use crate::monitor::ActivityMonitor;
+    use crate::source::{register_source, MouseButtons, PointerEvent};
+    use crate::testing::{self, TempDir};
+
+    const START: u64 = 1_700_000_000;
+
+    fn at(millis: u64, event: InputEvent) -> (Duration, InputEvent) {
+        (Duration::from_millis(millis), event)
+    }
+
+    fn key(code: u32, pressed: bool) -> InputEvent {
+        InputEvent::Key { code, pressed }
+    }
+
+    #[test]
+    fn script_gives_exact_counts_idle_time_and_log_row() {
+        let _isolated = testing::isolate();
+        let clock = SimulatedClock::new(Duration::from_secs(START));
+        clock::set_clock(Arc::new(clock.clone()));
+
+        let click = PointerEvent { pressed: MouseButtons::LEFT, ..PointerEvent::default() };
+        let release = PointerEvent { released: MouseButtons::LEFT, wheel_vertical: 2, ..PointerEvent::default() };
+        let source = SimulatedSource::new(
+            clock.clone(),
+            vec![
+                // Held past the 2 s repeat timeout: counted again once
+                at(0, key(30, true)),
+                at(500, key(30, true)),
+                at(1_000, key(30, true)),
+                at(1_500, key(30, true)),
+                at(2_000, key(30, true)),
+                at(2_500, key(30, true)),
+                at(2_600, key(30, false)),
+                at(3_000, key(31, true)),
+                at(3_100, key(31, false)),
+                at(4_000, InputEvent::Mouse),
+                at(5_000, InputEvent::Pointer(click)),
+                at(5_500, InputEvent::Pointer(release)),
+            ],
+        );
+        register_source(Box::new(source.clone()));
+
+        let monitor = ActivityMonitor::new();
+        monitor.set_idle_threshold(300);
+        monitor.start();
+
+        assert_eq!(source.advance(Duration::from_millis(2_200)), 5);
+        assert_eq!(monitor.keyboard_count(), 1);
+
+        assert_eq!(source.replay_all(), 7);
+        assert_eq!(source.elapsed(), Duration::from_millis(5_500));
+        assert_eq!((monitor.keyboard_count(), monitor.mouse_count()), (3, 3));
+        assert_eq!((monitor.mouse_counts().left_clicks, monitor.mouse_counts().wheel_vertical), (1, 2));
+        assert_eq!(monitor.idle_time(), 0);
+
+        source.advance(Duration::from_secs(60));
+        assert_eq!(source.remaining(), 0);
+        assert_eq!(monitor.idle_time(), 60);
+
+        let dir = TempDir::new();
+        let path = dir.join("activity.csv");
+        assert!(monitor.save_activity_log_as(&path, LogFormat::Csv));
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "timestamp,keyboard_count,mouse_count,idle_time_seconds\n1700000065,3,3,60\n"
+        );
+        assert_eq!((monitor.keyboard_count(), monitor.mouse_count()), (0, 0));
+        assert_eq!(monitor.idle_time(), 60);
+    }
+
+    #[test]
+    fn events_due_while_stopped_are_dropped() {
+        let _isolated = testing::isolate();
+        let clock = SimulatedClock::new(Duration::from_secs(START));
+        clock::set_clock(Arc::new(clock.clone()));
+        let source = SimulatedSource::new(
+            clock.clone(),
+            vec![at(1_000, InputEvent::Mouse), at(2_000, InputEvent::Mouse), at(3_000, InputEvent::Mouse)],
+        );
+        register_source(Box::new(source.clone()));
+
+        // Before the first start only the clock moves
+        assert_eq!(source.advance(Duration::from_secs(10)), 0);
+        assert_eq!(source.remaining(), 3);
+
+        let monitor = ActivityMonitor::new();
+        monitor.start();
+        assert_eq!(source.advance(Duration::from_millis(1_500)), 1);
+
+        monitor.stop();
+        assert_eq!(source.advance(Duration::from_secs(1)), 0);
+        assert_eq!(source.remaining(), 1);
+
+        monitor.start();
+        assert_eq!(source.replay_all(), 1);
+        assert_eq!(monitor.mouse_count(), 1);
+        assert_eq!(clock.now(), Duration::from_secs(START + 13));
+    }
+}
@@ -0,0 +1,479 @@
+// activity_monitor/src/source.rs
+use std::ops::BitOr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::monitor::MouseCounts;
+use crate::pointer::PointerTravel;
+
+// What kind of activity a source can observe
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    // Individual key presses
+    pub const KEYBOARD: Capabilities = Capabilities(1 << 0);
+    // Individual pointer events
+    pub const MOUSE: Capabilities = Capabilities(1 << 1);
+    // Whether the user is active, without countable events
+    pub const IDLE: Capabilities = Capabilities(1 << 2);
+    // Session lock and system sleep
+    pub const SESSION: Capabilities = Capabilities(1 << 3);
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Capabilities) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+// Mouse buttons as the core counts them. Sources report any button past
+// the middle one (back, forward, side...) as EXTRA.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MouseButtons(pub u32);
+
+impl MouseButtons {
+    pub const NONE: MouseButtons = MouseButtons(0);
+    pub const LEFT: MouseButtons = MouseButtons(1 << 0);
+    pub const RIGHT: MouseButtons = MouseButtons(1 << 1);
+    pub const MIDDLE: MouseButtons = MouseButtons(1 << 2);
+    pub const EXTRA: MouseButtons = MouseButtons(1 << 3);
+
+    pub fn contains(self, other: MouseButtons) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn without(self, other: MouseButtons) -> MouseButtons {
+        MouseButtons(self.0 & !other.0)
+    }
+}
+
+impl BitOr for MouseButtons {
+    type Output = MouseButtons;
+
+    fn bitor(self, other: MouseButtons) -> MouseButtons {
+        MouseButtons(self.0 | other.0)
+    }
+}
+
+// One native pointer event, or one frame of them where the platform splits
+// a report up. The core works out drags from the buttons held, and travel
+// from the positions or, failing those, the deltas.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PointerEvent {
+    // Buttons that went down or came up
+    pub pressed: MouseButtons,
+    pub released: MouseButtons,
+    pub moved: bool,
+    // Wheel notches turned, in either direction
+    pub wheel_vertical: u32,
+    pub wheel_horizontal: u32,
+    // Where the pointer is, in pixels of the whole desktop, for sources
+    // that know
+    pub position: Option<(f64, f64)>,
+    // How far it moved, in pixels, for sources that only see relative motion
+    pub delta: (f64, f64),
+}
+
+// Events a source feeds into the core. Key codes are source specific; the
+// core only uses them to pair presses with releases.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Key { code: u32, pressed: bool },
+    Pointer(PointerEvent),
+    // Pointer activity the source cannot break down; counts only towards
+    // the total mouse count
+    Mouse,
+    // Genuine activity that has no countable event (e.g. Wayland resume)
+    Activity,
+    // Genuine activity at an earlier time, in seconds since the epoch
+    ActivityAt(u64),
+    SessionLocked(bool),
+    Sleeping(bool),
+}
+
+// A pluggable producer of input events. Sources run their own threads and
+// report through `dispatch`; the core owns counting and key debouncing.
+pub trait ActivitySource: Send {
+    fn name(&self) -> &str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    // Returns false if the source is not usable here (missing device,
+    // display server, permissions...), in which case it must not run
+    fn start(&mut self) -> bool;
+
+    fn stop(&mut self);
+
+    // Drop any per-source state; called whenever counters are reset
+    fn reset(&mut self) {}
+}
+
+struct Registered {
+    source: Box<dyn ActivitySource>,
+    running: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref SOURCES: Mutex<Vec<Registered>> = Mutex::new(Vec::new());
+}
+
+// How long the built-in sources wait for input at a time before checking
+// whether they should stop. Shorter makes stopping snappier at some CPU
+// cost; on Wayland it is also how often presence is reported while active.
+static POLL_INTERVAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_POLL_INTERVAL.as_millis() as u64);
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Takes effect from the next wait of each running source. Clamped to
+// 1 ms..1 s.
+pub fn set_poll_interval(interval: Duration) {
+    let milliseconds = (interval.as_millis() as u64).clamp(1, 1000);
+    POLL_INTERVAL_MS.store(milliseconds, Ordering::SeqCst);
+}
+
+pub fn poll_interval() -> Duration {
+    Duration::from_millis(POLL_INTERVAL_MS.load(Ordering::SeqCst))
+}
+
+// Add a source to be run by start_monitoring(). If no source is registered
+// when monitoring starts, the platform defaults are used.
+pub fn register_source(source: Box<dyn ActivitySource>) {
+    SOURCES.lock().unwrap().push(Registered { source, running: false });
+}
+
+// Remove all registered sources, stopping any that are running
+pub fn clear_sources() {
+    let mut sources = SOURCES.lock().unwrap();
+    for registered in sources.iter_mut().filter(|registered| registered.running) {
+        registered.source.stop();
+    }
+    sources.clear();
+}
+
+// Feed one event into the core
+pub fn dispatch(event: InputEvent) {
+    match event {
+        InputEvent::Key { code, pressed } => {
+            let (increment_counter, is_genuine) = crate::debounce::process_keyboard_event(code, pressed);
+
+            // Update activity time only for genuine activity. This goes
+            // first so an idle period ends before the event that ended it.
+            if is_genuine {
+                crate::update_genuine_activity_time(true);
+            }
+
+            if increment_counter {
+                crate::increment_keyboard();
+            }
+        }
+        InputEvent::Pointer(pointer) => {
+            // Mouse activity is always considered genuine
+            crate::update_genuine_activity_time(true);
+            let counts = crate::debounce::process_pointer_event(&pointer);
+            let (travel, position) = crate::pointer::track(&pointer);
+            crate::increment_mouse(counts, travel, position);
+        }
+        InputEvent::Mouse => {
+            crate::update_genuine_activity_time(true);
+            crate::increment_mouse(MouseCounts::default(), PointerTravel::default(), None);
+        }
+        InputEvent::Activity => crate::update_genuine_activity_time(true),
+        InputEvent::ActivityAt(timestamp) => crate::record_genuine_activity_at(timestamp),
+        InputEvent::SessionLocked(locked) => crate::set_session_locked(locked),
+        InputEvent::Sleeping(sleeping) => crate::set_system_sleeping(sleeping),
+    }
+}
+
+// Start every registered source and return the combined capabilities of
+// the ones that came up
+pub(crate) fn start_sources() -> Capabilities {
+    let mut sources = SOURCES.lock().unwrap();
+    if sources.is_empty() {
+        sources.extend(crate::default_sources().into_iter().map(|source| Registered {
+            source,
+            running: false,
+        }));
+    }
+
+    let mut capabilities = Capabilities::NONE;
+    for registered in sources.iter_mut() {
+        if !registered.running {
+            registered.running = registered.source.start();
+        }
+        if registered.running {
+            capabilities = capabilities | registered.source.capabilities();
+        }
+    }
+    capabilities
+}
+
+pub(crate) fn stop_sources() {
+    let mut sources = SOURCES.lock().unwrap();
+    for registered in sources.iter_mut().filter(|registered| registered.running) {
+        registered.source.stop();
+        registered.running = false;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceStatus {
+    pub name: String,
+    pub running: bool,
+}
+
+// Every registered source and whether it is running. None instead of
+// waiting while sources are being started or stopped.
+pub fn try_source_status() -> Option<Vec<SourceStatus>> {
+    let sources = SOURCES.try_lock().ok()?;
+    Some(
+        sources
+            .iter()
+            .map(|registered| SourceStatus {
+                name: registered.source.name().to_string(),
+                running: registered.running,
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn reset_sources() {
+    crate::debounce::reset();
+    crate::pointer::reset();
+
+    let mut sources = SOURCES.lock().unwrap();
+    for registered in sources.iter_mut() {
+        registered.source.reset();
+    }
+}
+
+// Runs the first of several alternative sources that starts, e.g. evdev
+// with a display-server fallback
+pub struct FirstAvailable {
+    name: String,
+    candidates: Vec<Box<dyn ActivitySource>>,
+    active: Option<usize>,
+}
+
+impl FirstAvailable {
+    pub fn new(name: &str, candidates: Vec<Box<dyn ActivitySource>>) -> FirstAvailable {
+        FirstAvailable {
+            name: name.to_string(),
+            candidates,
+            active: None,
+        }
+    }
+
+    // The candidate that is currently running, if any
+    pub fn active(&self) -> Option<&dyn ActivitySource> {
+        self.active.map(|index| self.candidates[index].as_ref())
+    }
+}
+
+impl ActivitySource for FirstAvailable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Before start this is everything any candidate could provide
+    fn capabilities(&self) -> Capabilities {
+        match self.active() {
+            Some(source) => source.capabilities(),
+            None => self
+                .candidates
+                .iter()
+                .fold(Capabilities::NONE, |caps, source| caps | source.capabilities()),
+        }
+    }
+
+    fn start(&mut self) -> bool {
+        if self.active.is_some() {
+            return true;
+        }
+        self.active = self.candidates.iter_mut().position(|source| source.start());
+        self.active.is_some()
+    }
+
+    fn stop(&mut self) {
+        if let Some(index) = self.active.take() {
+            self.candidates[index].stop();
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some(index) = self.active {
+            self.candidates[index].reset();
+        }
+    }
+}
+
+struct MockState {
+    capabilities: Capabilities,
+    available: AtomicBool,
+    running: AtomicBool,
+}
+
+// A source driven by hand, for exercising the pipeline without any OS
+// hooks. Clones share state: register one and emit through another.
+#[derive(Clone)]
+pub struct MockSource {
+    state: Arc<MockState>,
+}
+
+impl MockSource {
+    pub fn new(capabilities: Capabilities) -> MockSource {
+        MockSource {
+            state: Arc::new(MockState {
+                capabilities,
+                available: AtomicBool::new(true),
+                running: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    // Make start() fail, as a source would without its device or server
+    pub fn set_available(&self, available: bool) {
+        self.state.available.store(available, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.running.load(Ordering::SeqCst)
+    }
+
+    // Deliver an event as if it came from the OS. Events are dropped while
+    // the source is stopped, like real hooks. Returns whether it was delivered.
+    pub fn emit(&self, event: InputEvent) -> bool {
+        if !self.is_running() {
+            return false;
+        }
+        dispatch(event);
+        true
+    }
+}
+
+impl ActivitySource for MockSource {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.state.capabilities
+    }
+
+    fn start(&mut self) -> bool {
+        if !self.state.available.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.state.running.store(true, Ordering::SeqCst);
+        true
+    }
+
+    fn stop(&mut self) {
+        self.state.running.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::ActivityMonitor;
+    use crate::testing;
+
+    fn key(code: u32, pressed: bool) -> InputEvent {
+        InputEvent::Key { code, pressed }
+    }
+
+    #[test]
+    fn events_pass_the_key_filter_into_counts() {
+        let _isolated = testing::isolate();
+        let source = MockSource::new(Capabilities::KEYBOARD | Capabilities::MOUSE);
+        register_source(Box::new(source.clone()));
+
+        let monitor = ActivityMonitor::new();
+        assert!(monitor.start());
+        assert!(source.is_running());
+
+        // The second press is an auto-repeat of the first
+        for event in [key(30, true), key(30, true), key(30, false), key(30, true)] {
+            assert!(source.emit(event));
+        }
+        source.emit(InputEvent::Pointer(PointerEvent {
+            pressed: MouseButtons::RIGHT,
+            ..PointerEvent::default()
+        }));
+        source.emit(InputEvent::Mouse);
+
+        assert_eq!(monitor.keyboard_count(), 2);
+        assert_eq!(monitor.mouse_count(), 2);
+        assert_eq!(monitor.mouse_counts().right_clicks, 1);
+        assert!(crate::counters_available());
+
+        assert!(monitor.stop());
+        assert!(!source.is_running());
+        assert!(!source.emit(InputEvent::Mouse));
+        assert_eq!(monitor.mouse_count(), 2);
+    }
+
+    #[test]
+    fn first_available_runs_the_first_candidate_that_starts() {
+        let _isolated = testing::isolate();
+        let missing = MockSource::new(Capabilities::KEYBOARD | Capabilities::MOUSE);
+        missing.set_available(false);
+        let fallback = MockSource::new(Capabilities::IDLE);
+        register_source(Box::new(FirstAvailable::new(
+            "input",
+            vec![Box::new(missing.clone()), Box::new(fallback.clone())],
+        )));
+
+        let monitor = ActivityMonitor::new();
+        monitor.start();
+        assert!(!missing.is_running());
+        assert!(fallback.is_running());
+        assert_eq!(Capabilities(crate::get_capabilities()), Capabilities::IDLE);
+        assert!(!crate::counters_available());
+        assert_eq!(
+            try_source_status().unwrap(),
+            vec![SourceStatus { name: "input".to_string(), running: true }]
+        );
+
+        monitor.stop();
+        assert!(!fallback.is_running());
+    }
+
+    #[test]
+    fn sources_run_while_any_monitor_does() {
+        let _isolated = testing::isolate();
+        let source = MockSource::new(Capabilities::KEYBOARD);
+        register_source(Box::new(source.clone()));
+
+        let first = ActivityMonitor::new();
+        let second = ActivityMonitor::new();
+        first.start();
+        second.start();
+
+        first.stop();
+        assert!(source.is_running());
+        source.emit(key(1, true));
+        assert_eq!((first.keyboard_count(), second.keyboard_count()), (0, 1));
+
+        second.stop();
+        assert!(!source.is_running());
+    }
+}
@@ -0,0 +1,199 @@
+// activity_monitor/src/statsd.rs
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::monitor::default_monitor;
+
+// Where and how the push thread sends metrics
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatsdConfig {
+    pub address: SocketAddr,
+    pub interval_secs: u64,
+    // Metric names are `<prefix>.<metric>`
+    pub prefix: String,
+    // DogStatsD tags added to every metric; none sends plain StatsD. The
+    // characters that delimit tags or metrics are sent as `_`.
+    pub tags: Vec<(String, String)>,
+}
+
+impl StatsdConfig {
+    // Plain StatsD to `address` (e.g. "127.0.0.1:8125") every `interval_secs`
+    pub fn new(address: impl ToSocketAddrs, interval_secs: u64) -> io::Result<StatsdConfig> {
+        let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "address did not resolve")
+        })?;
+
+        Ok(StatsdConfig {
+            address,
+            interval_secs: interval_secs.max(1),
+            prefix: "activity_monitor".to_string(),
+            tags: Vec::new(),
+        })
+    }
+
+    // Parse DogStatsD tags from "key:value,key:value"; a tag without a
+    // value is sent bare. `|`, `#` and line breaks are sent as `_`.
+    pub fn with_tags(mut self, tags: &str) -> StatsdConfig {
+        self.tags = tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| match tag.split_once(':') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (tag.to_string(), String::new()),
+            })
+            .collect();
+        self
+    }
+}
+
+struct Pusher {
+    // Dropped to make the thread push once more and exit
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+// The running push thread, if any. One per process.
+static PUSHER: Mutex<Option<Pusher>> = Mutex::new(None);
+
+// Send keyboard and mouse event deltas as counters, and idle time and
+// monitoring state as gauges, every `config.interval_secs` from a
+// background thread, replacing any push started before. Deltas come from
+// the process-wide event totals, so they are unaffected by counter resets.
+pub fn start(config: StatsdConfig) -> io::Result<()> {
+    let socket = match config.address {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+    };
+    socket.connect(config.address)?;
+
+    let mut pusher = PUSHER.lock().unwrap();
+    if let Some(running) = pusher.take() {
+        running.finish();
+    }
+
+    let (stop, stopped) = mpsc::channel::<()>();
+    let interval = Duration::from_secs(config.interval_secs);
+
+    let thread = thread::spawn(move || {
+        let mut last = crate::total_events();
+
+        loop {
+            let stopping = !matches!(stopped.recv_timeout(interval), Err(mpsc::RecvTimeoutError::Timeout));
+
+            let totals = crate::total_events();
+            // A lost datagram is lost; the next push carries on from here
+            let _ = socket.send(render(&config, last, totals).as_bytes());
+            last = totals;
+
+            if stopping {
+                break;
+            }
+        }
+    });
+
+    *pusher = Some(Pusher { stop, thread });
+    Ok(())
+}
+
+// Push the final deltas and stop. Returns false if nothing was pushing.
+pub fn stop() -> bool {
+    match PUSHER.lock().unwrap().take() {
+        Some(pusher) => {
+            pusher.finish();
+            true
+        },
+        None => false,
+    }
+}
+
+impl Pusher {
+    fn finish(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
+
+// One datagram with a metric per line
+fn render(config: &StatsdConfig, last: (u64, u64), totals: (u64, u64)) -> String {
+    let monitor = default_monitor();
+    let tags = render_tags(&config.tags);
+    let prefix = &config.prefix;
+
+    let metrics = [
+        ("keyboard_events", totals.0.wrapping_sub(last.0), "c"),
+        ("mouse_events", totals.1.wrapping_sub(last.1), "c"),
+        ("idle_seconds", monitor.idle_time(), "g"),
+        ("monitoring", crate::sources_running() as u64, "g"),
+        ("counters_available", crate::counters_available() as u64, "g"),
+        ("session_state", crate::get_session_state() as u64, "g"),
+    ];
+
+    metrics
+        .iter()
+        .map(|(name, value, kind)| format!("{prefix}.{name}:{value}|{kind}{tags}\n"))
+        .collect()
+}
+
+// "|#key:value,bare" or nothing
+fn render_tags(tags: &[(String, String)]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|(key, value)| match (clean_tag(key), clean_tag(value)) {
+            (key, value) if value.is_empty() => key,
+            (key, value) => format!("{key}:{value}"),
+        })
+        .collect();
+    format!("|#{}", tags.join(","))
+}
+
+// A tag part cannot end the tags, start another section or another metric
+fn clean_tag(part: &str) -> String {
+    part.replace(['|', '#', ',', '\n', '\r'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_dogstatsd_tags() {
+        let config = StatsdConfig::new("127.0.0.1:8125", 10).unwrap().with_tags("env:test, team:ops,canary");
+        let packet = render(&config, (10, 20), (13, 20));
+
+        let lines: Vec<&str> = packet.lines().collect();
+        assert_eq!(lines[0], "activity_monitor.keyboard_events:3|c|#env:test,team:ops,canary");
+        assert_eq!(lines[1], "activity_monitor.mouse_events:0|c|#env:test,team:ops,canary");
+        assert!(lines[2].starts_with("activity_monitor.idle_seconds:"));
+        assert!(lines[2].ends_with("|g|#env:test,team:ops,canary"));
+
+        let config = config.with_tags("env:te|st,ro#le:a\nb");
+        assert!(render(&config, (0, 0), (0, 0)).starts_with("activity_monitor.keyboard_events:0|c|#env:te_st,ro_le:a_b\n"));
+        let config = StatsdConfig { tags: vec![("team".to_string(), "ops,dev".to_string())], ..config };
+        assert!(render(&config, (0, 0), (0, 0)).starts_with("activity_monitor.keyboard_events:0|c|#team:ops_dev\n"));
+    }
+
+    #[test]
+    fn pushes_to_udp_listener_on_stop() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let config = StatsdConfig::new(listener.local_addr().unwrap(), 3600).unwrap();
+        start(config).unwrap();
+        assert!(stop());
+
+        let mut buffer = [0; 1024];
+        let length = listener.recv(&mut buffer).unwrap();
+        let packet = std::str::from_utf8(&buffer[..length]).unwrap();
+
+        assert!(packet.starts_with("activity_monitor.keyboard_events:"));
+        assert!(packet.lines().all(|line| line.ends_with("|c") || line.ends_with("|g")));
+        assert!(!stop());
+    }
+}
@@ -0,0 +1,619 @@
+// activity_monitor/src/store.rs
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use chrono::{Local, NaiveDate, TimeZone};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use crate::activity_log::LogRecord;
+use crate::monitor::MouseCounts;
+
+// Each entry upgrades the schema by one version; PRAGMA user_version
+// records how many have been applied. Only ever append to this list.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        host TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        ended_at INTEGER
+    );
+    CREATE TABLE intervals (
+        id INTEGER PRIMARY KEY,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        interval_start INTEGER NOT NULL,
+        interval_end INTEGER NOT NULL,
+        keyboard_count INTEGER,
+        mouse_count INTEGER,
+        idle_time_seconds INTEGER NOT NULL,
+        idle_state TEXT NOT NULL,
+        session_state TEXT NOT NULL
+    );
+    CREATE INDEX intervals_by_start ON intervals(interval_start);
+    CREATE TABLE idle_periods (
+        id INTEGER PRIMARY KEY,
+        session_id TEXT NOT NULL REFERENCES sessions(id),
+        idle_start INTEGER NOT NULL,
+        idle_end INTEGER NOT NULL
+    );
+    CREATE INDEX idle_periods_by_start ON idle_periods(idle_start);",
+    // The mouse breakdown; NULL in rows from before it
+    "ALTER TABLE intervals ADD COLUMN left_clicks INTEGER;
+    ALTER TABLE intervals ADD COLUMN right_clicks INTEGER;
+    ALTER TABLE intervals ADD COLUMN middle_clicks INTEGER;
+    ALTER TABLE intervals ADD COLUMN extra_clicks INTEGER;
+    ALTER TABLE intervals ADD COLUMN wheel_vertical INTEGER;
+    ALTER TABLE intervals ADD COLUMN wheel_horizontal INTEGER;
+    ALTER TABLE intervals ADD COLUMN mouse_moves INTEGER;
+    ALTER TABLE intervals ADD COLUMN mouse_drags INTEGER;",
+];
+
+// Totals over a time range. Times are in seconds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ActivityTotals {
+    pub keyboard_count: u64,
+    pub mouse_count: u64,
+    // Time covered by logged intervals, and the part of it spent idle
+    pub tracked_seconds: u64,
+    pub idle_seconds: u64,
+    // Intervals from before the breakdown was stored add nothing
+    pub mouse: MouseCounts,
+}
+
+// Totals for one local calendar day starting at `day_start` (seconds since
+// the epoch)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DailyTotals {
+    pub day_start: u64,
+    pub totals: ActivityTotals,
+}
+
+// A stretch without activity, from the last activity to the next one
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IdlePeriod {
+    pub start: u64,
+    pub end: u64,
+}
+
+// Intervals, idle periods and monitoring sessions in a SQLite database.
+// Range queries take [from, to) in seconds since the epoch; intervals and
+// idle periods are clipped to the range. The counts of a clipped interval
+// are prorated by the share of its time inside the range.
+pub struct ActivityStore {
+    connection: Mutex<Connection>,
+    // The session new rows belong to
+    session_id: Mutex<Option<String>>,
+}
+
+impl ActivityStore {
+    // Open or create the database at `path`, upgrading its schema
+    pub fn open(path: &Path) -> rusqlite::Result<ActivityStore> {
+        ActivityStore::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(mut connection: Connection) -> rusqlite::Result<ActivityStore> {
+        migrate(&mut connection)?;
+
+        Ok(ActivityStore {
+            connection: Mutex::new(connection),
+            session_id: Mutex::new(None),
+        })
+    }
+
+    pub fn begin_session(&self, session_id: &str, host: &str, started_at: u64) -> rusqlite::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR IGNORE INTO sessions (id, host, started_at) VALUES (?1, ?2, ?3)",
+            params![session_id, host, sql_int(started_at)],
+        )?;
+
+        *self.session_id.lock().unwrap() = Some(session_id.to_string());
+        Ok(())
+    }
+
+    pub fn end_session(&self, ended_at: u64) -> rusqlite::Result<()> {
+        let session_id = match self.session_id.lock().unwrap().take() {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+
+        self.connection.lock().unwrap().execute(
+            "UPDATE sessions SET ended_at = ?2 WHERE id = ?1",
+            params![session_id, sql_int(ended_at)],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_interval(&self, record: &LogRecord) -> rusqlite::Result<()> {
+        let connection = self.connection.lock().unwrap();
+
+        // Intervals logged outside begin_session() still need a session row
+        connection.execute(
+            "INSERT OR IGNORE INTO sessions (id, host, started_at) VALUES (?1, ?2, ?3)",
+            params![record.session_id, record.host, sql_int(record.interval_start)],
+        )?;
+
+        let mouse = record.counts.map(|counts| counts.mouse_detail);
+        connection.execute(
+            "INSERT INTO intervals (session_id, interval_start, interval_end, keyboard_count,
+                mouse_count, idle_time_seconds, idle_state, session_state, left_clicks, right_clicks,
+                middle_clicks, extra_clicks, wheel_vertical, wheel_horizontal, mouse_moves, mouse_drags)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            params![
+                record.session_id,
+                sql_int(record.interval_start),
+                sql_int(record.interval_end),
+                record.counts.map(|counts| sql_int(counts.keyboard)),
+                record.counts.map(|counts| sql_int(counts.mouse)),
+                sql_int(record.idle_time_seconds),
+                record.idle_state,
+                record.session_state,
+                mouse.map(|mouse| sql_int(mouse.left_clicks)),
+                mouse.map(|mouse| sql_int(mouse.right_clicks)),
+                mouse.map(|mouse| sql_int(mouse.middle_clicks)),
+                mouse.map(|mouse| sql_int(mouse.extra_clicks)),
+                mouse.map(|mouse| sql_int(mouse.wheel_vertical)),
+                mouse.map(|mouse| sql_int(mouse.wheel_horizontal)),
+                mouse.map(|mouse| sql_int(mouse.moves)),
+                mouse.map(|mouse| sql_int(mouse.drags)),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Ignored outside a session
+    pub fn record_idle_period(&self, period: IdlePeriod) -> rusqlite::Result<()> {
+        let session_id = match self.session_id.lock().unwrap().clone() {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO idle_periods (session_id, idle_start, idle_end) VALUES (?1, ?2, ?3)",
+            params![session_id, sql_int(period.start), sql_int(period.end)],
+        )?;
+        Ok(())
+    }
+
+    pub fn totals(&self, from: u64, to: u64) -> rusqlite::Result<ActivityTotals> {
+        let connection = self.connection.lock().unwrap();
+        let mut totals = ActivityTotals::default();
+
+        for_each_interval(&connection, from, to, |interval| totals.add_interval(&interval, from, to))?;
+
+        let idle_seconds = connection.query_row(
+            "SELECT COALESCE(SUM(MIN(idle_end, ?2) - MAX(idle_start, ?1)), 0)
+             FROM idle_periods WHERE idle_end > ?1 AND idle_start < ?2",
+            params![sql_int(from), sql_int(to)],
+            |row| row.get::<_, i64>(0),
+        )?;
+        totals.idle_seconds = (idle_seconds as u64).min(totals.tracked_seconds);
+
+        Ok(totals)
+    }
+
+    // One entry per local calendar day with any data, oldest first. Rows
+    // that span midnight are split between the days like totals() splits
+    // them at the range bounds, and each day's idle time is capped at its
+    // tracked time.
+    pub fn daily_totals(&self, from: u64, to: u64) -> rusqlite::Result<Vec<DailyTotals>> {
+        let connection = self.connection.lock().unwrap();
+        let mut days: BTreeMap<u64, ActivityTotals> = BTreeMap::new();
+
+        for_each_interval(&connection, from, to, |interval| {
+            if interval.end <= interval.start {
+                days.entry(local_day(interval.end).0).or_default().add_interval(&interval, from, to);
+                return;
+            }
+            for_each_day(interval.start.max(from), interval.end.min(to), |day, start, end| {
+                days.entry(day).or_default().add_interval(&interval, start, end);
+            });
+        })?;
+
+        let mut statement = connection.prepare(
+            "SELECT MAX(idle_start, ?1), MIN(idle_end, ?2)
+             FROM idle_periods WHERE idle_end > ?1 AND idle_start < ?2",
+        )?;
+        let rows = statement.query_map(params![sql_int(from), sql_int(to)], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64))
+        })?;
+        for row in rows {
+            let (start, end) = row?;
+            for_each_day(start, end, |day, start, end| {
+                days.entry(day).or_default().idle_seconds += end - start;
+            });
+        }
+
+        Ok(days
+            .into_iter()
+            .map(|(day_start, mut totals)| {
+                totals.idle_seconds = totals.idle_seconds.min(totals.tracked_seconds);
+                DailyTotals { day_start, totals }
+            })
+            .collect())
+    }
+
+    // The `limit` longest idle periods in the range, longest first
+    pub fn longest_idle_gaps(&self, from: u64, to: u64, limit: usize) -> rusqlite::Result<Vec<IdlePeriod>> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = connection.prepare(
+            "SELECT MAX(idle_start, ?1) AS start, MIN(idle_end, ?2) AS end
+             FROM idle_periods WHERE idle_end > ?1 AND idle_start < ?2
+             ORDER BY end - start DESC, start LIMIT ?3",
+        )?;
+        let rows = statement.query_map(params![sql_int(from), sql_int(to), sql_int(limit)], |row| {
+            Ok(IdlePeriod {
+                start: row.get::<_, i64>(0)? as u64,
+                end: row.get::<_, i64>(1)? as u64,
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    // The schema version of the open database
+    pub fn schema_version(&self) -> rusqlite::Result<u32> {
+        self.connection
+            .lock()
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+    }
+}
+
+// An interval as the range queries read it back
+struct StoredInterval {
+    start: u64,
+    end: u64,
+    // Keyboard and mouse, when the counters were available
+    counts: Option<(u64, u64)>,
+    // None in rows from before the breakdown
+    mouse: Option<MouseCounts>,
+}
+
+impl StoredInterval {
+    // An interval without length only lies in a range holding its end
+    fn overlaps(&self, from: u64, to: u64) -> bool {
+        if self.end <= self.start {
+            (from..to).contains(&self.end)
+        } else {
+            self.end > from && self.start < to
+        }
+    }
+
+    // The part of `count`, spread evenly over the interval, that falls in
+    // [from, to). Taken from the running total at each bound so that the
+    // parts of one interval always add up to the whole count.
+    fn share(&self, count: u64, from: u64, to: u64) -> u64 {
+        if self.end <= self.start {
+            return if self.overlaps(from, to) { count } else { 0 };
+        }
+
+        let up_to = |time: u64| {
+            let elapsed = time.clamp(self.start, self.end) - self.start;
+            (count as u128 * elapsed as u128 / (self.end - self.start) as u128) as u64
+        };
+        up_to(to).saturating_sub(up_to(from))
+    }
+}
+
+impl ActivityTotals {
+    // Add the part of `interval` that falls in [from, to)
+    fn add_interval(&mut self, interval: &StoredInterval, from: u64, to: u64) {
+        let share = |count: u64| interval.share(count, from, to);
+
+        if let Some((keyboard, mouse)) = interval.counts {
+            self.keyboard_count += share(keyboard);
+            self.mouse_count += share(mouse);
+        }
+        if let Some(mouse) = interval.mouse {
+            self.mouse.add(&MouseCounts {
+                left_clicks: share(mouse.left_clicks),
+                right_clicks: share(mouse.right_clicks),
+                middle_clicks: share(mouse.middle_clicks),
+                extra_clicks: share(mouse.extra_clicks),
+                wheel_vertical: share(mouse.wheel_vertical),
+                wheel_horizontal: share(mouse.wheel_horizontal),
+                moves: share(mouse.moves),
+                drags: share(mouse.drags),
+            });
+        }
+        self.tracked_seconds += interval.end.min(to).saturating_sub(interval.start.max(from));
+    }
+}
+
+// Calls `add` with every interval that has a part in [from, to)
+fn for_each_interval(connection: &Connection, from: u64, to: u64, mut add: impl FnMut(StoredInterval)) -> rusqlite::Result<()> {
+    let mut statement = connection.prepare(
+        "SELECT interval_start, interval_end, keyboard_count, mouse_count, left_clicks, right_clicks,
+            middle_clicks, extra_clicks, wheel_vertical, wheel_horizontal, mouse_moves, mouse_drags
+         FROM intervals WHERE interval_end >= ?1 AND interval_start < ?2",
+    )?;
+    let rows = statement.query_map(params![sql_int(from), sql_int(to)], |row| {
+        let keyboard = row.get::<_, Option<i64>>(2)?;
+        let mouse = row.get::<_, Option<i64>>(3)?;
+        Ok(StoredInterval {
+            start: row.get::<_, i64>(0)? as u64,
+            end: row.get::<_, i64>(1)? as u64,
+            counts: keyboard.zip(mouse).map(|(keyboard, mouse)| (keyboard as u64, mouse as u64)),
+            mouse: mouse_counts(row, 4)?,
+        })
+    })?;
+
+    for interval in rows {
+        let interval = interval?;
+        if interval.overlaps(from, to) {
+            add(interval);
+        }
+    }
+    Ok(())
+}
+
+// The mouse breakdown columns read from `row`, starting at column `first`,
+// in MouseCounts order; None in rows from before the breakdown
+fn mouse_counts(row: &Row, first: usize) -> rusqlite::Result<Option<MouseCounts>> {
+    if row.get::<_, Option<i64>>(first)?.is_none() {
+        return Ok(None);
+    }
+
+    let count = |offset: usize| row.get::<_, i64>(first + offset).map(|count| count as u64);
+    Ok(Some(MouseCounts {
+        left_clicks: count(0)?,
+        right_clicks: count(1)?,
+        middle_clicks: count(2)?,
+        extra_clicks: count(3)?,
+        wheel_vertical: count(4)?,
+        wheel_horizontal: count(5)?,
+        moves: count(6)?,
+        drags: count(7)?,
+    }))
+}
+
+// Split [from, to) at local midnights, calling `add` with the start of
+// each day and the part of the range within it
+fn for_each_day(from: u64, to: u64, mut add: impl FnMut(u64, u64, u64)) {
+    let mut time = from;
+    while time < to {
+        let (day_start, day_end) = local_day(time);
+        let end = day_end.min(to);
+        add(day_start, time, end);
+        time = end;
+    }
+}
+
+// The local calendar day holding `time`, as [start, end) in seconds since
+// the epoch. A time chrono cannot place starts a day that never ends.
+fn local_day(time: u64) -> (u64, u64) {
+    let date = match Local.timestamp_opt(sql_int(time), 0).single() {
+        Some(local) => local.date_naive(),
+        None => return (time, u64::MAX),
+    };
+
+    let start = day_start(date).filter(|&start| start <= time).unwrap_or(time);
+    let end = date.succ_opt().and_then(day_start).filter(|&end| end > time).unwrap_or(u64::MAX);
+    (start, end)
+}
+
+// Midnight of `date`, or the hour after it when a clock change skips it
+fn day_start(date: NaiveDate) -> Option<u64> {
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    let start = Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| Local.from_local_datetime(&(midnight + chrono::Duration::hours(1))).earliest())?;
+    u64::try_from(start.timestamp()).ok()
+}
+
+// SQLite integers are signed. Values past i64::MAX only come from open
+// ended ranges like `to = u64::MAX`, which stay open ended this way.
+fn sql_int<T: TryInto<i64>>(value: T) -> i64 {
+    value.try_into().unwrap_or(i64::MAX)
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = connection
+        .query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+        .optional()?
+        .unwrap_or(0)
+        .max(0) as usize;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", (index + 1) as i64)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use crate::activity_log::{Counts, LOG_SCHEMA_VERSION};
+
+    fn store() -> ActivityStore {
+        ActivityStore::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn interval(start: u64, end: u64, keyboard: u64, mouse: u64) -> LogRecord {
+        LogRecord {
+            schema_version: LOG_SCHEMA_VERSION,
+            interval_start: start,
+            interval_end: end,
+            host: "host".to_string(),
+            session_id: "session".to_string(),
+            counts: Some(Counts {
+                keyboard,
+                mouse,
+                ..Counts::default()
+            }),
+            idle_time_seconds: 0,
+            idle_state: "active".to_string(),
+            session_state: "active".to_string(),
+        }
+    }
+
+    fn local_midnight(year: i32, month: u32, day: u32) -> u64 {
+        Local.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap().timestamp() as u64
+    }
+
+    #[test]
+    fn migrations_bring_old_databases_up_to_date() {
+        let connection = Connection::open_in_memory().unwrap();
+        // Before the first migration, with a table of the host's own
+        connection.execute_batch("CREATE TABLE notes (text TEXT)").unwrap();
+
+        let store = ActivityStore::with_connection(connection).unwrap();
+        assert_eq!(store.schema_version().unwrap(), MIGRATIONS.len() as u32);
+        store.begin_session("session", "host", 100).unwrap();
+        store.record_interval(&interval(100, 160, 1, 2)).unwrap();
+
+        // Up to date: running the migrations again changes nothing
+        let connection = store.connection.into_inner().unwrap();
+        let store = ActivityStore::with_connection(connection).unwrap();
+        assert_eq!(store.schema_version().unwrap(), MIGRATIONS.len() as u32);
+        assert_eq!(store.totals(0, u64::MAX).unwrap().keyboard_count, 1);
+    }
+
+    #[test]
+    fn rows_from_before_the_mouse_breakdown_add_nothing_to_it() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO sessions (id, host, started_at) VALUES ('old', 'host', 0);
+                INSERT INTO intervals (session_id, interval_start, interval_end, keyboard_count, mouse_count,
+                    idle_time_seconds, idle_state, session_state)
+                VALUES ('old', 0, 60, 1, 5, 0, 'active', 'active');",
+            )
+            .unwrap();
+
+        let store = ActivityStore::with_connection(connection).unwrap();
+        assert_eq!(store.schema_version().unwrap(), MIGRATIONS.len() as u32);
+        let mut record = interval(60, 120, 0, 3);
+        if let Some(counts) = record.counts.as_mut() {
+            counts.mouse_detail = MouseCounts {
+                left_clicks: 2,
+                wheel_vertical: 1,
+                ..MouseCounts::default()
+            };
+        }
+        store.begin_session("session", "host", 60).unwrap();
+        store.record_interval(&record).unwrap();
+
+        let totals = store.totals(0, u64::MAX).unwrap();
+        assert_eq!(totals.mouse_count, 8);
+        assert_eq!(
+            totals.mouse,
+            MouseCounts {
+                left_clicks: 2,
+                wheel_vertical: 1,
+                ..MouseCounts::default()
+            }
+        );
+        let days = store.daily_totals(0, u64::MAX).unwrap();
+        assert_eq!(days.iter().map(|day| day.totals.mouse.left_clicks).sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn totals_clip_to_the_range() {
+        let store = store();
+        store.begin_session("session", "host", 1000).unwrap();
+        store.record_interval(&interval(1000, 1060, 5, 7)).unwrap();
+        store.record_interval(&interval(1060, 1120, 1, 1)).unwrap();
+        store.record_idle_period(IdlePeriod { start: 1030, end: 1090 }).unwrap();
+
+        assert_eq!(
+            store.totals(0, u64::MAX).unwrap(),
+            ActivityTotals {
+                keyboard_count: 6,
+                mouse_count: 8,
+                tracked_seconds: 120,
+                idle_seconds: 60,
+                ..ActivityTotals::default()
+            }
+        );
+        // Times are clipped and counts spread over their interval's time
+        assert_eq!(
+            store.totals(1050, 1070).unwrap(),
+            ActivityTotals {
+                keyboard_count: 1,
+                mouse_count: 2,
+                tracked_seconds: 20,
+                idle_seconds: 20,
+                ..ActivityTotals::default()
+            }
+        );
+        // The parts on either side of a bound add up to the whole
+        let (before, after) = (store.totals(0, 1050).unwrap(), store.totals(1050, u64::MAX).unwrap());
+        assert_eq!((before.keyboard_count + after.keyboard_count, before.mouse_count + after.mouse_count), (6, 8));
+        assert_eq!(store.totals(2000, u64::MAX).unwrap(), ActivityTotals::default());
+    }
+
+    #[test]
+    fn days_split_at_local_midnight() {
+        let store = store();
+        let day_before = local_midnight(2024, 6, 14);
+        let midnight = local_midnight(2024, 6, 15);
+        store.begin_session("session", "host", midnight - 120).unwrap();
+        store.record_interval(&interval(midnight - 120, midnight - 60, 1, 0)).unwrap();
+        // Spans midnight, so half of it goes to each day
+        store.record_interval(&interval(midnight - 60, midnight + 60, 2, 0)).unwrap();
+        store.record_interval(&interval(midnight + 60, midnight + 120, 4, 3)).unwrap();
+        store.record_idle_period(IdlePeriod { start: midnight - 30, end: midnight + 40 }).unwrap();
+        store.record_idle_period(IdlePeriod { start: midnight + 70, end: midnight + 100 }).unwrap();
+        // More idle time than was tracked that day, which is capped
+        store.record_idle_period(IdlePeriod { start: day_before + 3600, end: midnight - 120 }).unwrap();
+
+        assert_eq!(
+            store.daily_totals(0, u64::MAX).unwrap(),
+            vec![
+                DailyTotals {
+                    day_start: day_before,
+                    totals: ActivityTotals {
+                        keyboard_count: 2,
+                        mouse_count: 0,
+                        tracked_seconds: 120,
+                        idle_seconds: 120,
+                        ..ActivityTotals::default()
+                    },
+                },
+                DailyTotals {
+                    day_start: midnight,
+                    totals: ActivityTotals {
+                        keyboard_count: 5,
+                        mouse_count: 3,
+                        tracked_seconds: 120,
+                        idle_seconds: 70,
+                        ..ActivityTotals::default()
+                    },
+                },
+            ]
+        );
+        assert_eq!(store.daily_totals(midnight + 60, u64::MAX).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn longest_idle_gaps_come_first() {
+        let store = store();
+        store.begin_session("session", "host", 0).unwrap();
+        for (start, end) in [(100, 200), (300, 600), (700, 1000), (1100, 1150), (1200, 1600)] {
+            store.record_idle_period(IdlePeriod { start, end }).unwrap();
+        }
+
+        // Equal lengths go earliest first; the last one is clipped to 200
+        assert_eq!(
+            store.longest_idle_gaps(0, 1400, 3).unwrap(),
+            vec![
+                IdlePeriod { start: 300, end: 600 },
+                IdlePeriod { start: 700, end: 1000 },
+                IdlePeriod { start: 1200, end: 1400 },
+            ]
+        );
+        assert_eq!(store.longest_idle_gaps(0, u64::MAX, usize::MAX).unwrap().len(), 5);
+        assert_eq!(store.longest_idle_gaps(0, u64::MAX, 1).unwrap(), vec![IdlePeriod { start: 1200, end: 1600 }]);
+    }
+}
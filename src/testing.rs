@@ -0,0 +1,99 @@
+// activity_monitor/src/testing.rs
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use crate::clock::SimulatedClock;
+use crate::debounce::{self, KeyRepeatConfig};
+use crate::monitor::ActivityMonitor;
+use crate::source::{register_source, Capabilities, MockSource};
+use crate::{clock, pointer, source};
+
+// Sources, the clock, the key filter and the pointer settings are
+// process-wide, while tests run in parallel. Tests that start a monitor or
+// change any of them hold this guard, so they run one at a time and only
+// see the sources they register themselves; nothing falls back to the real
+// devices.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+pub(crate) struct Isolated {
+    _serial: MutexGuard<'static, ()>,
+}
+
+// Declare it before any monitor, so the monitors are dropped first
+pub(crate) fn isolate() -> Isolated {
+    // A failed test must not take the others down with it
+    let serial = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    restore_defaults();
+    Isolated { _serial: serial }
+}
+
+impl Drop for Isolated {
+    fn drop(&mut self) {
+        restore_defaults();
+    }
+}
+
+fn restore_defaults() {
+    source::clear_sources();
+    clock::use_system_clock();
+    debounce::set_key_repeat_config(KeyRepeatConfig::default());
+    debounce::reset();
+    pointer::set_travel_enabled(true);
+    pointer::set_screen_dpi(None);
+    pointer::report_screen_dpi(None);
+}
+
+// Where simulated() starts the clock, in seconds since the epoch
+pub(crate) const START: u64 = 1_700_000_000;
+
+// A started monitor fed by a mock source, on a simulated clock. Call it
+// with the isolate() guard held.
+pub(crate) fn simulated() -> (SimulatedClock, MockSource, Arc<ActivityMonitor>) {
+    let clock = SimulatedClock::new(Duration::from_secs(START));
+    clock::set_clock(Arc::new(clock.clone()));
+    let source = MockSource::new(Capabilities::KEYBOARD | Capabilities::MOUSE);
+    register_source(Box::new(source.clone()));
+
+    let monitor = ActivityMonitor::new();
+    monitor.set_idle_threshold(300);
+    monitor.start();
+    (clock, source, monitor)
+}
+
+// A fresh directory per test, removed again when dropped, also when the
+// test fails
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub(crate) fn new() -> TempDir {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "activity-monitor-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
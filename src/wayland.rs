@@ -0,0 +1,472 @@
+// activity_monitor/src/wayland.rs
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::os::unix::io::AsRawFd;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+    ext_idle_notifier_v1::ExtIdleNotifierV1,
+};
+use wayland_protocols_plasma::idle::client::{
+    org_kde_kwin_idle::OrgKdeKwinIdle,
+    org_kde_kwin_idle_timeout::{self, OrgKdeKwinIdleTimeout},
+};
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent};
+
+// The compositor reports "idle" once no input arrived for this long, so
+// idle time is accurate to roughly this granularity
+const IDLE_TIMEOUT_MS: u32 = 1000;
+
+// Wayland clients never see global input, only whether the seat is idle
+struct IdleState {
+    idle: bool,
+    // When activity was last passed on
+    reported: Option<Instant>,
+}
+
+impl IdleState {
+    fn new() -> IdleState {
+        IdleState { idle: false, reported: None }
+    }
+
+    // Until the compositor says otherwise the user is active. Passing that
+    // on once per idle timeout is as precise as the compositor is.
+    fn report_activity(&mut self, now: Instant) {
+        let interval = Duration::from_millis(IDLE_TIMEOUT_MS as u64);
+        if self.idle || self.reported.is_some_and(|reported| now.duration_since(reported) < interval) {
+            return;
+        }
+        self.reported = Some(now);
+        dispatch(InputEvent::Activity);
+    }
+
+    fn set_idle(&mut self, idle: bool) {
+        self.idle = idle;
+        if !idle {
+            // Input again: that is news, whenever activity went out last
+            self.reported = None;
+            self.report_activity(Instant::now());
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for IdleState {
+    fn event(
+        _state: &mut Self,
+        _registry: &wl_registry::WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, ()> for IdleState {
+    fn event(
+        state: &mut Self,
+        _notification: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_idle_notification_v1::Event::Idled => state.set_idle(true),
+            ext_idle_notification_v1::Event::Resumed => state.set_idle(false),
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<OrgKdeKwinIdleTimeout, ()> for IdleState {
+    fn event(
+        state: &mut Self,
+        _timeout: &OrgKdeKwinIdleTimeout,
+        event: org_kde_kwin_idle_timeout::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            org_kde_kwin_idle_timeout::Event::Idle => state.set_idle(true),
+            org_kde_kwin_idle_timeout::Event::Resumed => state.set_idle(false),
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(IdleState: ignore WlSeat);
+delegate_noop!(IdleState: ExtIdleNotifierV1);
+delegate_noop!(IdleState: OrgKdeKwinIdle);
+
+// Request an idle notification for the first seat of the compositor at
+// `conn`, preferring ext-idle-notify-v1 over the KDE protocol
+fn connect(conn: Connection) -> Option<EventQueue<IdleState>> {
+    let (globals, event_queue) = registry_queue_init::<IdleState>(&conn).ok()?;
+    let qh = event_queue.handle();
+
+    let seat: WlSeat = globals.bind(&qh, 1..=1, ()).ok()?;
+
+    if let Ok(notifier) = globals.bind::<ExtIdleNotifierV1, _, _>(&qh, 1..=2, ()) {
+        // Version 2 ignores idle inhibitors (video players and the like),
+        // which is what we want when measuring actual input
+        if notifier.version() >= 2 {
+            notifier.get_input_idle_notification(IDLE_TIMEOUT_MS, &seat, &qh, ());
+        } else {
+            notifier.get_idle_notification(IDLE_TIMEOUT_MS, &seat, &qh, ());
+        }
+    } else if let Ok(idle) = globals.bind::<OrgKdeKwinIdle, _, _>(&qh, 1..=1, ()) {
+        idle.get_idle_timeout(&seat, IDLE_TIMEOUT_MS, &qh, ());
+    } else {
+        return None;
+    }
+
+    event_queue.flush().ok()?;
+    Some(event_queue)
+}
+
+// Wait up to `timeout` for events and dispatch them. Returns false if the
+// compositor connection was lost.
+fn dispatch_events(event_queue: &mut EventQueue<IdleState>, state: &mut IdleState, timeout: Duration) -> bool {
+    if event_queue.flush().is_err() {
+        return false;
+    }
+
+    if let Some(guard) = event_queue.prepare_read() {
+        let mut fd = libc::pollfd {
+            fd: guard.connection_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let ready = unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) };
+        if ready > 0 && guard.read().is_err() {
+            return false;
+        }
+    }
+
+    event_queue.dispatch_pending(state).is_ok()
+}
+
+// Tracks idleness through the compositor. Wayland clients never see global
+// input, so this source cannot count keys or pointer events.
+pub struct WaylandSource {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl WaylandSource {
+    pub fn new() -> WaylandSource {
+        WaylandSource {
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl Default for WaylandSource {
+    fn default() -> WaylandSource {
+        WaylandSource::new()
+    }
+}
+
+impl ActivitySource for WaylandSource {
+    fn name(&self) -> &str {
+        "wayland"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::IDLE
+    }
+
+    // Fails if not running under a compositor that supports either idle
+    // protocol
+    fn start(&mut self) -> bool {
+        if self.running.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        self.running.store(true, Ordering::SeqCst);
+
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let running = self.running.clone();
+
+        let handle = thread::spawn(move || {
+            let mut event_queue = match Connection::connect_to_env().ok().and_then(connect) {
+                Some(event_queue) => event_queue,
+                None => {
+                    let _ = ready_tx.send(false);
+                    return;
+                }
+            };
+            let _ = ready_tx.send(true);
+
+            let mut state = IdleState::new();
+
+            while running.load(Ordering::SeqCst) {
+                state.report_activity(Instant::now());
+
+                // Wait up to one poll interval so we notice a stop request promptly
+                if !dispatch_events(&mut event_queue, &mut state, crate::source::poll_interval()) {
+                    break;
+                }
+            }
+        });
+
+        let started = ready_rx.recv().unwrap_or(false);
+        if started {
+            self.thread = Some(handle);
+        } else {
+            let _ = handle.join();
+            self.running.store(false, Ordering::SeqCst);
+        }
+
+        started
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // Wait for the monitoring thread to disconnect
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+    use wayland_protocols::ext::idle_notify::v1::server::{
+        ext_idle_notification_v1::ExtIdleNotificationV1 as ServerNotification,
+        ext_idle_notifier_v1::{self as server_notifier, ExtIdleNotifierV1 as ServerNotifier},
+    };
+    use wayland_protocols_plasma::idle::server::{
+        org_kde_kwin_idle::{self as server_idle, OrgKdeKwinIdle as ServerIdle},
+        org_kde_kwin_idle_timeout::OrgKdeKwinIdleTimeout as ServerTimeout,
+    };
+    use wayland_server::protocol::wl_seat::WlSeat as ServerSeat;
+    use wayland_server::{Client, DataInit, Display, DisplayHandle, GlobalDispatch, New};
+    use crate::clock::{self, SimulatedClock};
+    use crate::monitor::ActivityMonitor;
+    use crate::source::{register_source, MockSource};
+    use crate::testing;
+
+    // A headless compositor offering a seat and the idle protocols asked
+    // for. It reports the idle requests it gets and sends idle (true) or
+    // resumed (false) to every notification it hands out.
+    struct Compositor {
+        requests: Sender<String>,
+        ext: Vec<ServerNotification>,
+        kde: Vec<ServerTimeout>,
+    }
+
+    struct ClientState;
+
+    impl wayland_server::backend::ClientData for ClientState {}
+
+    impl<I: wayland_server::Resource + 'static> GlobalDispatch<I, ()> for Compositor
+    where
+        Compositor: wayland_server::Dispatch<I, ()>,
+    {
+        fn bind(_: &mut Self, _: &DisplayHandle, _: &Client, resource: New<I>, _: &(), data_init: &mut DataInit<'_, Self>) {
+            data_init.init(resource, ());
+        }
+    }
+
+    impl wayland_server::Dispatch<ServerNotifier, ()> for Compositor {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            _notifier: &ServerNotifier,
+            request: server_notifier::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let (kind, id, timeout) = match request {
+                server_notifier::Request::GetIdleNotification { id, timeout, .. } => ("ext", id, timeout),
+                server_notifier::Request::GetInputIdleNotification { id, timeout, .. } => ("ext input", id, timeout),
+                _ => return,
+            };
+            state.ext.push(data_init.init(id, ()));
+            let _ = state.requests.send(format!("{kind} {timeout}"));
+        }
+    }
+
+    impl wayland_server::Dispatch<ServerIdle, ()> for Compositor {
+        fn request(
+            state: &mut Self,
+            _client: &Client,
+            _idle: &ServerIdle,
+            request: server_idle::Request,
+            _data: &(),
+            _dhandle: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            if let server_idle::Request::GetIdleTimeout { id, timeout, .. } = request {
+                state.kde.push(data_init.init(id, ()));
+                let _ = state.requests.send(format!("kde {timeout}"));
+            }
+        }
+    }
+
+    // Requests on these need no answer
+    macro_rules! ignore_requests {
+        ($($interface:ty),*) => {$(
+            impl wayland_server::Dispatch<$interface, ()> for Compositor {
+                fn request(
+                    _: &mut Self,
+                    _: &Client,
+                    _: &$interface,
+                    _: <$interface as wayland_server::Resource>::Request,
+                    _: &(),
+                    _: &DisplayHandle,
+                    _: &mut DataInit<'_, Self>,
+                ) {
+                }
+            }
+        )*};
+    }
+
+    ignore_requests!(ServerSeat, ServerNotification, ServerTimeout);
+
+    impl Compositor {
+        fn send(&self, idle: bool) {
+            for notification in &self.ext {
+                if idle {
+                    notification.idled();
+                } else {
+                    notification.resumed();
+                }
+            }
+            for timeout in &self.kde {
+                if idle {
+                    timeout.idle();
+                } else {
+                    timeout.resumed();
+                }
+            }
+        }
+    }
+
+    // Runs until the returned sender is dropped
+    fn compositor(ext_version: Option<u32>, kde: bool) -> (Connection, Sender<bool>, Receiver<String>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (commands, received) = mpsc::channel::<bool>();
+        let (requests, reported) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut display = Display::<Compositor>::new().unwrap();
+            let handle = display.handle();
+            handle.create_global::<Compositor, ServerSeat, ()>(1, ());
+            if let Some(version) = ext_version {
+                handle.create_global::<Compositor, ServerNotifier, ()>(version, ());
+            }
+            if kde {
+                handle.create_global::<Compositor, ServerIdle, ()>(1, ());
+            }
+            display.handle().insert_client(server, Arc::new(ClientState)).unwrap();
+
+            let mut compositor = Compositor { requests, ext: Vec::new(), kde: Vec::new() };
+            loop {
+                match received.try_recv() {
+                    Ok(idle) => compositor.send(idle),
+                    Err(TryRecvError::Disconnected) => return,
+                    Err(TryRecvError::Empty) => {}
+                }
+                let _ = display.dispatch_clients(&mut compositor);
+                let _ = display.flush_clients();
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        (Connection::from_socket(client).unwrap(), commands, reported)
+    }
+
+    // Dispatch until the state turns `idle`
+    fn wait_for(event_queue: &mut EventQueue<IdleState>, state: &mut IdleState, idle: bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while state.idle != idle {
+            assert!(Instant::now() < deadline, "the compositor never said idle = {idle}");
+            assert!(dispatch_events(event_queue, state, Duration::from_millis(20)));
+        }
+    }
+
+    fn next_request(requests: &Receiver<String>) -> String {
+        requests.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn ext_idle_notify_drives_activity() {
+        let _isolated = testing::isolate();
+        let clock = SimulatedClock::new(Duration::from_secs(1_700_000_000));
+        clock::set_clock(Arc::new(clock.clone()));
+        register_source(Box::new(MockSource::new(Capabilities::IDLE)));
+        let monitor = ActivityMonitor::new();
+        monitor.start();
+
+        // Version 2, with the KDE protocol also there, gets the input-only request
+        let (conn, commands, requests) = compositor(Some(2), true);
+        let mut event_queue = connect(conn).unwrap();
+        assert_eq!(next_request(&requests), "ext input 1000");
+        let mut state = IdleState::new();
+        let start = Instant::now();
+        state.report_activity(start);
+
+        commands.send(true).unwrap();
+        wait_for(&mut event_queue, &mut state, true);
+        clock.advance(Duration::from_secs(100));
+        state.report_activity(start + Duration::from_secs(100));
+        assert_eq!(monitor.idle_time(), 100);
+
+        // Resuming counts right away, however recently activity went out
+        commands.send(false).unwrap();
+        wait_for(&mut event_queue, &mut state, false);
+        assert_eq!(monitor.idle_time(), 0);
+
+        // While active, at most once per idle timeout
+        let resumed = state.reported.unwrap();
+        clock.advance(Duration::from_secs(5));
+        state.report_activity(resumed + Duration::from_millis(500));
+        assert_eq!(monitor.idle_time(), 5);
+        state.report_activity(resumed + Duration::from_millis(IDLE_TIMEOUT_MS as u64));
+        assert_eq!(monitor.idle_time(), 0);
+        monitor.stop();
+    }
+
+    #[test]
+    fn older_notifier_gets_the_plain_request() {
+        let (conn, _commands, requests) = compositor(Some(1), false);
+        assert!(connect(conn).is_some());
+        assert_eq!(next_request(&requests), "ext 1000");
+    }
+
+    #[test]
+    fn kde_idle_is_the_fallback() {
+        let _isolated = testing::isolate();
+        let (conn, commands, requests) = compositor(None, true);
+        let mut event_queue = connect(conn).unwrap();
+        assert_eq!(next_request(&requests), "kde 1000");
+
+        let mut state = IdleState::new();
+        commands.send(true).unwrap();
+        wait_for(&mut event_queue, &mut state, true);
+        commands.send(false).unwrap();
+        wait_for(&mut event_queue, &mut state, false);
+        assert!(state.reported.is_some());
+    }
+
+    #[test]
+    fn compositor_without_idle_protocols_is_refused() {
+        let (conn, _commands, _requests) = compositor(None, false);
+        assert!(connect(conn).is_none());
+    }
+}
@@ -1,95 +1,41 @@
 // activity_monitor/src/windows.rs
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use windows::Win32::UI::WindowsAndMessaging::{
     SetWindowsHookExW, UnhookWindowsHookEx, CallNextHookEx,
     WH_KEYBOARD_LL, WH_MOUSE_LL, HC_ACTION, HHOOK,
     KBDLLHOOKSTRUCT, MSLLHOOKSTRUCT, MSG, GetMessageW, TranslateMessage, DispatchMessageW,
-    WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP,
+    WM_KEYDOWN, WM_SYSKEYDOWN, WM_MOUSEMOVE, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_RBUTTONDOWN,
+    WM_RBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_XBUTTONDOWN, WM_XBUTTONUP, WM_MOUSEWHEEL,
+    WM_MOUSEHWHEEL,
 };
-use windows::Win32::Foundation::{LPARAM, WPARAM, LRESULT, HWND};
+use windows::Win32::Foundation::{LPARAM, WPARAM, LRESULT, HWND, POINT};
+use windows::Win32::Graphics::Gdi::{GetDC, GetDeviceCaps, ReleaseDC, HORZRES, HORZSIZE};
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent, MouseButtons, PointerEvent};
 
 static RUNNING: AtomicBool = AtomicBool::new(false);
 static mut KEYBOARD_HOOK: Option<HHOOK> = None;
 static mut MOUSE_HOOK: Option<HHOOK> = None;
 
-// Track pressed keys to avoid duplicate counting
-lazy_static::lazy_static! {
-    static ref PRESSED_KEYS: Mutex<HashMap<u32, Instant>> = Mutex::new(HashMap::new());
-    static ref KEY_TIMEOUT: Mutex<Duration> = Mutex::new(Duration::from_secs(2)); // Timeout for key presses
-}
-
-// Smart activity detection
-fn process_keyboard_event(virtual_key: u32, is_down: bool) -> (bool, bool) {
-    let mut keys = PRESSED_KEYS.lock().unwrap();
-    let key_timeout = *KEY_TIMEOUT.lock().unwrap();
-    
-    // Get current time
-    let now = Instant::now();
-    
-    // For key down events
-    if is_down {
-        // If this key is not already pressed (or has timed out), count it as new activity
-        if !keys.contains_key(&virtual_key) || 
-           now.duration_since(*keys.get(&virtual_key).unwrap()) > key_timeout {
-            // Update key press time
-            keys.insert(virtual_key, now);
-            
-            // Signal genuine activity
-            return (true, true);
-        }
-    } else {
-        // Remove key from pressed keys if it exists
-        if keys.contains_key(&virtual_key) {
-            // Only count key up if it hasn't been too long since key down (prevent stuck keys)
-            let time_since_key_down = if let Some(time) = keys.get(&virtual_key) {
-                now.duration_since(*time)
-            } else {
-                Duration::from_secs(0)
-            };
-            
-            keys.remove(&virtual_key);
-            
-            // For key up, we still count it as keyboard activity, but not genuine activity
-            // if it's been held down too long
-            let is_genuine = time_since_key_down < key_timeout;
-            
-            // Return keyboard activity but no genuine activity for key up events
-            return (false, false);
-        }
-    }
-    
-    // No new activity detected
-    (false, false)
-}
-
-// Check for timeout on all pressed keys
-fn cleanup_stale_keys() {
-    let mut keys = PRESSED_KEYS.lock().unwrap();
-    let now = Instant::now();
-    let key_timeout = *KEY_TIMEOUT.lock().unwrap();
-    
-    // Remove keys that have been pressed too long (stuck keys)
-    keys.retain(|_, time| now.duration_since(*time) < key_timeout);
-}
+// One wheel notch; high-resolution wheels report fractions of it
+const WHEEL_DELTA: i32 = 120;
+// Wheel movement short of a whole notch, carried to the next message
+static WHEEL_REMAINDER_VERTICAL: AtomicI32 = AtomicI32::new(0);
+static WHEEL_REMAINDER_HORIZONTAL: AtomicI32 = AtomicI32::new(0);
 
-pub fn start_monitoring() {
+fn start_monitoring() {
     if RUNNING.load(Ordering::SeqCst) {
         return;
     }
     
     RUNNING.store(true, Ordering::SeqCst);
     
-    // Reset state
-    reset_monitoring_state();
-    
     thread::spawn(|| {
         unsafe {
             let h_module = GetModuleHandleW(None).unwrap_or_default();
+            crate::pointer::report_screen_dpi(screen_dpi());
             
             // Set keyboard hook
             KEYBOARD_HOOK = Some(SetWindowsHookExW(
@@ -109,8 +55,6 @@ pub fn start_monitoring() {
 
             // Message loop to keep hooks active
             let mut msg = MSG::default();
-            let mut last_cleanup = Instant::now();
-            let cleanup_interval = Duration::from_secs(10);
             
             while RUNNING.load(Ordering::SeqCst) {
                 if GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
@@ -121,13 +65,6 @@ pub fn start_monitoring() {
                 }
                 
                 thread::sleep(Duration::from_millis(10));
-                
-                // Periodically clean up stale keys
-                let now = Instant::now();
-                if now.duration_since(last_cleanup) > cleanup_interval {
-                    cleanup_stale_keys();
-                    last_cleanup = now;
-                }
             }
             
             // Clean up hooks
@@ -144,7 +81,17 @@ pub fn start_monitoring() {
     });
 }
 
-pub fn stop_monitoring() {
+// From the physical size Windows reports for the primary monitor
+unsafe fn screen_dpi() -> Option<f64> {
+    let screen = GetDC(HWND(0));
+    let pixels = GetDeviceCaps(screen, HORZRES);
+    let millimetres = GetDeviceCaps(screen, HORZSIZE);
+    ReleaseDC(HWND(0), screen);
+
+    (millimetres > 0).then(|| pixels as f64 * 25.4 / millimetres as f64)
+}
+
+fn stop_monitoring() {
     RUNNING.store(false, Ordering::SeqCst);
     
     unsafe {
@@ -158,15 +105,28 @@ pub fn stop_monitoring() {
             MOUSE_HOOK = None;
         }
     }
-    
-    // Clear state
-    reset_monitoring_state();
 }
 
-// Reset monitoring state (called from lib.rs)
-pub fn reset_monitoring_state() {
-    let mut keys = PRESSED_KEYS.lock().unwrap();
-    keys.clear();
+// Low-level keyboard and mouse hooks for the whole desktop session
+pub struct WindowsHookSource;
+
+impl ActivitySource for WindowsHookSource {
+    fn name(&self) -> &str {
+        "windows-hooks"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::KEYBOARD | Capabilities::MOUSE
+    }
+
+    fn start(&mut self) -> bool {
+        start_monitoring();
+        true
+    }
+
+    fn stop(&mut self) {
+        stop_monitoring();
+    }
 }
 
 extern "system" fn keyboard_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
@@ -182,18 +142,10 @@ extern "system" fn keyboard_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> L
                 let is_key_down = wparam.0 == WM_KEYDOWN as usize || 
                                   wparam.0 == WM_SYSKEYDOWN as usize;
                 
-                // Process the event - get both increment_counter and is_genuine flags
-                let (increment_counter, is_genuine) = process_keyboard_event(virtual_key, is_key_down);
-                
-                if increment_counter {
-                    // Increment keyboard counter for activity
-                    super::increment_keyboard();
-                }
-                
-                // Update last activity time only for genuine activity
-                if is_genuine {
-                    super::update_genuine_activity_time(true);
-                }
+                dispatch(InputEvent::Key {
+                    code: virtual_key,
+                    pressed: is_key_down,
+                });
             }
         }
     }
@@ -205,12 +157,51 @@ extern "system" fn keyboard_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> L
 
 extern "system" fn mouse_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     if code == HC_ACTION as i32 {
-        // Mouse activity is always considered genuine
-        super::increment_mouse();
-        super::update_genuine_activity_time(true);
+        let mouse_struct: *const MSLLHOOKSTRUCT = lparam.0 as *const _;
+        let event = if mouse_struct.is_null() {
+            pointer_event(wparam.0 as u32, 0)
+        } else {
+            let info = unsafe { &*mouse_struct };
+            // Virtual screen coordinates, which go negative left of or above
+            // the primary monitor
+            let POINT { x, y } = info.pt;
+            PointerEvent {
+                position: Some((x as f64, y as f64)),
+                ..pointer_event(wparam.0 as u32, info.mouseData)
+            }
+        };
+
+        dispatch(InputEvent::Pointer(event));
     }
     
     unsafe {
         CallNextHookEx(HHOOK(0), code, wparam, lparam)
     }
-}
\ No newline at end of file
+}
+
+fn pointer_event(message: u32, mouse_data: u32) -> PointerEvent {
+    let mut event = PointerEvent::default();
+    match message {
+        WM_MOUSEMOVE => event.moved = true,
+        WM_LBUTTONDOWN => event.pressed = MouseButtons::LEFT,
+        WM_LBUTTONUP => event.released = MouseButtons::LEFT,
+        WM_RBUTTONDOWN => event.pressed = MouseButtons::RIGHT,
+        WM_RBUTTONUP => event.released = MouseButtons::RIGHT,
+        WM_MBUTTONDOWN => event.pressed = MouseButtons::MIDDLE,
+        WM_MBUTTONUP => event.released = MouseButtons::MIDDLE,
+        WM_XBUTTONDOWN => event.pressed = MouseButtons::EXTRA,
+        WM_XBUTTONUP => event.released = MouseButtons::EXTRA,
+        WM_MOUSEWHEEL => event.wheel_vertical = wheel_notches(&WHEEL_REMAINDER_VERTICAL, mouse_data),
+        WM_MOUSEHWHEEL => event.wheel_horizontal = wheel_notches(&WHEEL_REMAINDER_HORIZONTAL, mouse_data),
+        _ => {}
+    }
+    event
+}
+
+// The wheel delta is the signed high word of mouseData
+fn wheel_notches(remainder: &AtomicI32, mouse_data: u32) -> u32 {
+    let delta = (mouse_data >> 16) as i16 as i32;
+    let total = remainder.load(Ordering::Relaxed) + delta;
+    remainder.store(total % WHEEL_DELTA, Ordering::Relaxed);
+    (total / WHEEL_DELTA).unsigned_abs()
+}
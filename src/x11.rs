@@ -0,0 +1,377 @@
+// activity_monitor/src/x11.rs
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use x11_dl::xinput2::{self, XInput2};
+use x11_dl::xlib::{self, Display, Xlib};
+use crate::source::{dispatch, ActivitySource, Capabilities, InputEvent, MouseButtons, PointerEvent};
+
+// An open display with XInput2 raw events selected on the root window
+struct Session {
+    xlib: Xlib,
+    xinput2: XInput2,
+    display: *mut Display,
+    xi_opcode: c_int,
+}
+
+impl Session {
+    // Connect to $DISPLAY and subscribe to raw input. Fails when libX11/libXi
+    // are not installed, no X server is reachable or XInput 2.0 is missing.
+    fn open() -> Option<Session> {
+        let xlib = Xlib::open().ok()?;
+        let xinput2 = XInput2::open().ok()?;
+
+        unsafe {
+            let display = (xlib.XOpenDisplay)(std::ptr::null());
+            if display.is_null() {
+                return None;
+            }
+
+            let session = Session { xlib, xinput2, display, xi_opcode: 0 };
+            session.select_raw_events()
+        }
+    }
+
+    unsafe fn select_raw_events(mut self) -> Option<Session> {
+        let extension = CString::new("XInputExtension").unwrap();
+        let (mut opcode, mut event, mut error) = (0, 0, 0);
+        if (self.xlib.XQueryExtension)(
+            self.display,
+            extension.as_ptr(),
+            &mut opcode,
+            &mut event,
+            &mut error,
+        ) == 0 {
+            return None;
+        }
+        self.xi_opcode = opcode;
+
+        let (mut major, mut minor) = (2, 0);
+        if (self.xinput2.XIQueryVersion)(self.display, &mut major, &mut minor) != xlib::Success as c_int {
+            return None;
+        }
+
+        let mut mask = [0u8; 4];
+        xinput2::XISetMask(&mut mask, xinput2::XI_RawKeyPress);
+        xinput2::XISetMask(&mut mask, xinput2::XI_RawKeyRelease);
+        xinput2::XISetMask(&mut mask, xinput2::XI_RawButtonPress);
+        xinput2::XISetMask(&mut mask, xinput2::XI_RawButtonRelease);
+        xinput2::XISetMask(&mut mask, xinput2::XI_RawMotion);
+
+        let mut event_mask = xinput2::XIEventMask {
+            deviceid: xinput2::XIAllMasterDevices,
+            mask_len: mask.len() as c_int,
+            mask: mask.as_mut_ptr(),
+        };
+
+        let root = (self.xlib.XDefaultRootWindow)(self.display);
+        (self.xinput2.XISelectEvents)(self.display, root, &mut event_mask, 1);
+        (self.xlib.XFlush)(self.display);
+
+        Some(self)
+    }
+
+    // Wait up to `timeout` for the X connection to become readable
+    fn wait_for_events(&self, timeout: Duration) {
+        let mut fd = libc::pollfd {
+            fd: unsafe { (self.xlib.XConnectionNumber)(self.display) },
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        unsafe {
+            libc::poll(&mut fd, 1, timeout.as_millis() as c_int);
+        }
+    }
+
+    fn dispatch_pending(&self) {
+        // Asking where the pointer is costs a round trip, so it is asked at
+        // most once per batch for motion: only the batch's last motion event
+        // carries the position. Travel still adds up, as straight lines
+        // between the positions.
+        let wanted = crate::pointer::positions_wanted();
+        let mut motion = None;
+
+        unsafe {
+            while (self.xlib.XPending)(self.display) > 0 {
+                let mut event: xlib::XEvent = std::mem::zeroed();
+                (self.xlib.XNextEvent)(self.display, &mut event);
+
+                let cookie = &mut event.generic_event_cookie;
+                if cookie.type_ != xlib::GenericEvent || cookie.extension != self.xi_opcode {
+                    continue;
+                }
+
+                if (self.xlib.XGetEventData)(self.display, cookie) == 0 {
+                    continue;
+                }
+
+                let raw_event = &*(cookie.data as *const xinput2::XIRawEvent);
+                self.handle_raw_event(cookie.evtype, raw_event, wanted, &mut motion);
+
+                (self.xlib.XFreeEventData)(self.display, cookie);
+            }
+        }
+
+        if let Some(mut event) = motion {
+            if wanted {
+                event.position = self.pointer_position();
+            }
+            dispatch(InputEvent::Pointer(event));
+        }
+    }
+
+    // `motion` holds back the latest pointer motion; it goes out without a
+    // position once anything else happens
+    fn handle_raw_event(&self, evtype: c_int, raw_event: &xinput2::XIRawEvent, wanted: bool, motion: &mut Option<PointerEvent>) {
+        if evtype != xinput2::XI_RawMotion {
+            if let Some(event) = motion.take() {
+                dispatch(InputEvent::Pointer(event));
+            }
+        }
+
+        match evtype {
+            xinput2::XI_RawKeyPress | xinput2::XI_RawKeyRelease => {
+                // Raw key events carry the hardware keycode in `detail`
+                dispatch(InputEvent::Key {
+                    code: raw_event.detail as u32,
+                    pressed: evtype == xinput2::XI_RawKeyPress,
+                });
+            }
+            xinput2::XI_RawButtonPress | xinput2::XI_RawButtonRelease => {
+                let mut event = button_event(raw_event.detail, evtype == xinput2::XI_RawButtonPress);
+                if wanted && !event.pressed.is_empty() {
+                    event.position = self.pointer_position();
+                }
+                dispatch(InputEvent::Pointer(event));
+            }
+            xinput2::XI_RawMotion => {
+                // Smooth scrolling arrives as motion on the scroll valuators; only
+                // valuators 0 and 1 (x and y) are pointer movement
+                let valuators = &raw_event.valuators;
+                let moved = valuators.mask_len > 0 && !valuators.mask.is_null() && unsafe { *valuators.mask } & 0b11 != 0;
+                let event = PointerEvent {
+                    moved,
+                    ..PointerEvent::default()
+                };
+                if !moved {
+                    dispatch(InputEvent::Pointer(event));
+                } else if let Some(earlier) = motion.replace(event) {
+                    dispatch(InputEvent::Pointer(earlier));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Raw events carry no screen position, so ask the server (a round trip)
+    fn pointer_position(&self) -> Option<(f64, f64)> {
+        let (mut root, mut child) = (0, 0);
+        let (mut root_x, mut root_y, mut window_x, mut window_y) = (0, 0, 0, 0);
+        let mut buttons = 0;
+
+        let on_screen = unsafe {
+            (self.xlib.XQueryPointer)(
+                self.display,
+                (self.xlib.XDefaultRootWindow)(self.display),
+                &mut root,
+                &mut child,
+                &mut root_x,
+                &mut root_y,
+                &mut window_x,
+                &mut window_y,
+                &mut buttons,
+            )
+        };
+        (on_screen != 0).then_some((root_x as f64, root_y as f64))
+    }
+
+    // From the physical size the server reports for the default screen
+    fn screen_dpi(&self) -> Option<f64> {
+        unsafe {
+            let screen = (self.xlib.XDefaultScreen)(self.display);
+            let pixels = (self.xlib.XDisplayWidth)(self.display, screen);
+            let millimetres = (self.xlib.XDisplayWidthMM)(self.display, screen);
+            (millimetres > 0).then(|| pixels as f64 * 25.4 / millimetres as f64)
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xlib.XCloseDisplay)(self.display);
+        }
+    }
+}
+
+// Core protocol button numbers: 1-3 are left, middle and right, 4-7 are
+// wheel notches (sent for smooth scrolling too) and the rest are extra
+fn button_event(button: c_int, pressed: bool) -> PointerEvent {
+    let mut event = PointerEvent::default();
+    let buttons = match button {
+        1 => MouseButtons::LEFT,
+        2 => MouseButtons::MIDDLE,
+        3 => MouseButtons::RIGHT,
+        4 | 5 => {
+            event.wheel_vertical = pressed as u32;
+            return event;
+        }
+        6 | 7 => {
+            event.wheel_horizontal = pressed as u32;
+            return event;
+        }
+        _ => MouseButtons::EXTRA,
+    };
+
+    if pressed {
+        event.pressed = buttons;
+    } else {
+        event.released = buttons;
+    }
+    event
+}
+
+// Subscribes to XInput2 raw events on the root window of $DISPLAY. Works
+// for any user of the X session, without access to /dev/input.
+pub struct X11Source {
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl X11Source {
+    pub fn new() -> X11Source {
+        X11Source {
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl Default for X11Source {
+    fn default() -> X11Source {
+        X11Source::new()
+    }
+}
+
+impl ActivitySource for X11Source {
+    fn name(&self) -> &str {
+        "x11"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::KEYBOARD | Capabilities::MOUSE
+    }
+
+    // Fails if no X session with XInput2 is available
+    fn start(&mut self) -> bool {
+        if self.running.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        self.running.store(true, Ordering::SeqCst);
+
+        // The display connection is not thread safe, so it is opened and used
+        // only on the monitoring thread; the setup result is reported back
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let running = self.running.clone();
+
+        let handle = thread::spawn(move || {
+            let session = match Session::open() {
+                Some(session) => session,
+                None => {
+                    let _ = ready_tx.send(false);
+                    return;
+                }
+            };
+            crate::pointer::report_screen_dpi(session.screen_dpi());
+            let _ = ready_tx.send(true);
+
+            while running.load(Ordering::SeqCst) {
+                // Wait up to one poll interval so we notice a stop request promptly
+                session.wait_for_events(crate::source::poll_interval());
+                session.dispatch_pending();
+            }
+        });
+
+        let started = ready_rx.recv().unwrap_or(false);
+        if started {
+            self.thread = Some(handle);
+        } else {
+            let _ = handle.join();
+            self.running.store(false, Ordering::SeqCst);
+        }
+
+        started
+    }
+
+    fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        // Wait for the monitoring thread to close the display
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::raw::{c_uint, c_ulong};
+    use std::time::Instant;
+    // x11-dl names the XTest bindings after another extension
+    use x11_dl::xtest::Xf86vmode as XTest;
+    use crate::monitor::ActivityMonitor;
+    use crate::source::register_source;
+
+    // Drives the server on $DISPLAY through XTest, e.g. under xvfb-run;
+    // skipped when there is none
+    #[test]
+    fn counts_xtest_input() {
+        let _isolated = crate::testing::isolate();
+        register_source(Box::new(X11Source::new()));
+        let monitor = ActivityMonitor::new();
+        monitor.start();
+        let (Ok(xlib), Ok(xtest), true) = (Xlib::open(), XTest::open(), crate::counters_available()) else {
+            eprintln!("skipped: no X server with XInput2 and XTest");
+            monitor.stop();
+            return;
+        };
+
+        unsafe {
+            let display = (xlib.XOpenDisplay)(std::ptr::null());
+            assert!(!display.is_null());
+            // The source's own connection selects raw events; give the
+            // server a moment to take that in
+            thread::sleep(Duration::from_millis(100));
+
+            let keycode = (xlib.XKeysymToKeycode)(display, x11_dl::keysym::XK_a as c_ulong) as c_uint;
+            (xtest.XTestFakeKeyEvent)(display, keycode, xlib::True, 0);
+            (xtest.XTestFakeKeyEvent)(display, keycode, xlib::False, 0);
+            (xtest.XTestFakeMotionEvent)(display, -1, 10, 20, 0);
+            (xtest.XTestFakeMotionEvent)(display, -1, 30, 40, 0);
+            for button in [1, 4] {
+                (xtest.XTestFakeButtonEvent)(display, button, xlib::True, 0);
+                (xtest.XTestFakeButtonEvent)(display, button, xlib::False, 0);
+            }
+            (xlib.XSync)(display, xlib::False);
+            (xlib.XCloseDisplay)(display);
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while monitor.mouse_counts().wheel_vertical == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        assert_eq!(monitor.snapshot().keyboard_count, 1);
+        let mouse = monitor.mouse_counts();
+        assert_eq!(mouse.left_clicks, 1);
+        assert_eq!(mouse.wheel_vertical, 1);
+        assert!(mouse.moves >= 1);
+        monitor.stop();
+    }
+}